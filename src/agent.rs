@@ -0,0 +1,405 @@
+use std::collections::VecDeque;
+
+use macroquad::prelude::{Color, Rect, Vec2, vec2};
+use nalgebra::Vector3;
+
+use crate::config::Config;
+use crate::controller::{FollowMode, WaypointFollower};
+use crate::exploration::{ExplorationStrategy, Explorer};
+use crate::geometry::{Obstruction, RigidTransform};
+use crate::planner::{self, MapSource};
+use crate::simulation::{GpsFix, Landmark, LidarScan, Observation, Robot, RobotSighting};
+use crate::slam::{
+    Control, DeadReckoning, EkfSlam, FastSlam, LoopClosure, MotionModel, OdometryMotion,
+    PoseGraphSlam, Slam,
+};
+use crate::spatial_index::ObstructionGrid;
+use crate::world_gen::spawn_points;
+
+type Pose = (f32, f32, f32);
+type MapLandmark = (usize, f32, f32); // id and position
+
+// frames of gyro bias kept for the plot
+const GYRO_BIAS_HISTORY: usize = 1800;
+
+// body colors, in spawn order
+const COLORS: [Color; 5] = [
+    Color::new(0.0, 0.47, 0.95, 1.0),
+    Color::new(0.6, 0.3, 0.9, 1.0),
+    Color::new(0.95, 0.55, 0.1, 1.0),
+    Color::new(0.9, 0.25, 0.6, 1.0),
+    Color::new(0.85, 0.8, 0.2, 1.0),
+];
+
+// a loop closure one of the robot's estimators reported, and when
+pub struct LoggedLoopClosure {
+    pub time: f32, // see Agent::clock
+    pub estimator: &'static str,
+    pub color: Color,
+    pub event: LoopClosure,
+}
+
+/*
+ * one robot in the shared world and everything that's its own: its
+ * estimators, how it's driven and what it last sensed. the estimators know
+ * nothing of where the robot started, so each maps in its own frame with the
+ * start pose as the origin; the origin is only used to draw and plan in the
+ * world's coordinates
+ */
+pub struct Agent {
+    pub robot: Robot,
+    pub origin: Pose,     // true start pose, where the estimators' frame sits
+    pub noise_scale: f32, // scales the robot's noise, and its estimators' belief of it
+    pub color: Color,
+    pub follow_mode: FollowMode,
+    pub exploration: ExplorationStrategy, // autopilot, when not off
+
+    pub ekf_slam: EkfSlam,
+    pub gyro_ekf_slam: EkfSlam,
+    pub fast_slam: FastSlam,
+    pub dead_reckoning: DeadReckoning,
+    pub pose_graph: PoseGraphSlam,
+
+    pub follower: WaypointFollower,
+    pub explorer: Explorer,
+    pub planned_map: Vec<Obstruction>, // obstructions as an estimated map saw them
+    pub plan_failed: bool,
+
+    pub observations: Vec<Observation>,
+    pub scan: LidarScan,                         // the last lidar sweep
+    pub sightings: Vec<RobotSighting>,           // other robots seen on the last frame
+    pub gps_fix: Option<(GpsFix, f32)>,          // the last fix and the clock when it came
+    pub gyro_bias_history: VecDeque<(f32, f32)>, // true and estimated bias, oldest first
+    pub clock: f32,                              // seconds simulated since it spawned
+    pub loop_closures: Vec<LoggedLoopClosure>,   // every estimator's, oldest first
+}
+
+impl Agent {
+    /*
+     * the index-th robot, at its spawn point; with the autopilot on, all but
+     * the first start out exploring on their own
+     */
+    pub fn new(index: usize, cfg: &Config) -> Self {
+        let spawn = spawn_points(cfg)[index];
+        let exploration = if index == 0 || !cfg.robot_autopilot {
+            cfg.exploration
+        } else {
+            ExplorationStrategy::Frontier
+        };
+
+        Self {
+            robot: Robot::at(spawn.x, spawn.y, 0.0),
+            origin: (spawn.x, spawn.y, 0.0),
+            noise_scale: cfg.robot_noise_scales[index % cfg.robot_noise_scales.len()],
+            color: COLORS[index % COLORS.len()],
+            follow_mode: if exploration == ExplorationStrategy::Off {
+                cfg.follow_mode
+            } else {
+                FollowMode::Once
+            },
+            exploration,
+            ekf_slam: EkfSlam::new(),
+            gyro_ekf_slam: EkfSlam::with_gyro_bias(cfg),
+            fast_slam: FastSlam::new(100),
+            dead_reckoning: DeadReckoning::new(),
+            pose_graph: PoseGraphSlam::new(),
+            follower: WaypointFollower::new(),
+            explorer: Explorer::new(cfg),
+            planned_map: Vec::new(),
+            plan_failed: false,
+            observations: Vec::new(),
+            scan: LidarScan::default(),
+            sightings: Vec::new(),
+            gps_fix: None,
+            gyro_bias_history: VecDeque::new(),
+            clock: 0.0,
+            loop_closures: Vec::new(),
+        }
+    }
+
+    // the shared config, with this robot's noise and modes
+    pub fn config(&self, cfg: &Config) -> Config {
+        let scale = self.noise_scale;
+        Config {
+            follow_mode: self.follow_mode,
+            exploration: self.exploration,
+            real_stdev_linear: cfg.real_stdev_linear * scale,
+            real_stdev_angular: cfg.real_stdev_angular * scale,
+            real_stdev_range: cfg.real_stdev_range * scale,
+            real_stdev_bearing: cfg.real_stdev_bearing * scale,
+            est_stdev_linear: cfg.est_stdev_linear * scale,
+            est_stdev_angular: cfg.est_stdev_angular * scale,
+            est_stdev_range: cfg.est_stdev_range * scale,
+            est_stdev_bearing: cfg.est_stdev_bearing * scale,
+            ..cfg.clone()
+        }
+    }
+
+    // takes the estimators' frame into the world's
+    pub fn frame(&self) -> RigidTransform {
+        let (x, y, theta) = self.origin;
+        RigidTransform {
+            angle: theta,
+            translation: vec2(x, y),
+        }
+    }
+
+    // a pose in the estimators' frame, in the world's
+    pub fn to_world(&self, (x, y, theta): Pose) -> Pose {
+        let frame = self.frame();
+        let position = frame.apply(vec2(x, y));
+        (position.x, position.y, theta + frame.angle)
+    }
+
+    // the same for an estimator's landmarks
+    pub fn landmarks_to_world(&self, landmarks: &[MapLandmark]) -> Vec<MapLandmark> {
+        landmarks
+            .iter()
+            .map(|&(id, x, y)| {
+                let (x, y, _) = self.to_world((x, y, 0.0));
+                (id, x, y)
+            })
+            .collect()
+    }
+
+    // the estimator whose map paths are planned on, None for ground truth
+    pub fn estimator(&self, source: MapSource) -> Option<&dyn Slam> {
+        match source {
+            MapSource::GroundTruth => None,
+            MapSource::Ekf => Some(&self.ekf_slam),
+            MapSource::FastSlam => Some(&self.fast_slam),
+            MapSource::DeadReckoning => Some(&self.dead_reckoning),
+        }
+    }
+
+    /*
+     * where the given map puts the robot and its landmarks, in world
+     * coordinates; no landmarks for ground truth
+     */
+    pub fn estimate(&self, source: MapSource) -> (Pose, Option<Vec<MapLandmark>>) {
+        match self.estimator(source) {
+            None => ((self.robot.x, self.robot.y, self.robot.theta), None),
+            Some(estimator) => (
+                self.to_world(estimator.get_state()),
+                Some(self.landmarks_to_world(&estimator.get_landmarks())),
+            ),
+        }
+    }
+
+    // plans to the goal on the chosen map and follows the path once
+    pub fn plan_to(
+        &mut self,
+        goal: Vec2,
+        obstructions: &ObstructionGrid,
+        landmarks: &[Landmark],
+        cfg: &Config,
+    ) {
+        let ((x, y, _), estimates) = self.estimate(cfg.planning_map);
+        let start = vec2(x, y);
+        let (path, map) = plan_path(
+            goal,
+            start,
+            estimates.as_deref(),
+            obstructions,
+            landmarks,
+            cfg,
+        );
+        self.planned_map = map;
+        self.plan_failed = path.is_none();
+        if let Some(path) = path {
+            self.follower.waypoints = path;
+            self.follower.restart(start);
+            self.follow_mode = FollowMode::Once;
+        }
+    }
+
+    // a frame of driving, moving, sensing and estimating
+    pub fn step(
+        &mut self,
+        obstructions: &ObstructionGrid,
+        landmarks: &[Landmark],
+        outages: &[Rect],
+        delta_time: f32,
+        cfg: &Config,
+    ) {
+        let cfg = &self.config(cfg);
+        let (pose, estimates) = self.estimate(cfg.planning_map);
+
+        /*
+         * exploring, the robot picks its own goals and plans to them like X
+         * does; switching the follower off pauses it
+         */
+        if self.exploration != ExplorationStrategy::Off && self.follow_mode != FollowMode::Off {
+            let truth = (self.robot.x, self.robot.y, self.robot.theta);
+            self.explorer.grid.sense(truth, obstructions, cfg);
+
+            let uncertain: Vec<(usize, f32, f32, f32)> = self
+                .ekf_slam
+                .landmark_uncertainties()
+                .into_iter()
+                .map(|(id, x, y, spread)| {
+                    let (x, y, _) = self.to_world((x, y, 0.0));
+                    (id, x, y, spread)
+                })
+                .collect();
+            let start = vec2(pose.0, pose.1);
+            let (planned_map, plan_failed) = (&mut self.planned_map, &mut self.plan_failed);
+            let plan = |goal| {
+                let (path, map) = plan_path(
+                    goal,
+                    start,
+                    estimates.as_deref(),
+                    obstructions,
+                    landmarks,
+                    cfg,
+                );
+                *planned_map = map;
+                *plan_failed = path.is_none();
+                path.map(|path| (start, path))
+            };
+            self.explorer.update(
+                vec2(truth.0, truth.1),
+                &uncertain,
+                &mut self.follower,
+                plan,
+                delta_time,
+                cfg,
+            );
+        }
+
+        // the controller overrides the keys until it runs out of waypoints, and stops the robot then
+        if self.follow_mode != FollowMode::Off {
+            let following = self.follower.target < self.follower.waypoints.len();
+            match self
+                .follower
+                .command(pose, self.follow_mode == FollowMode::Loop, cfg)
+            {
+                Some(command) => self.robot.command = command,
+                None if following => self.robot.command = Vector3::zeros(),
+                None => {}
+            }
+        }
+
+        // ground truth robot update
+        let encoder_ticks = self.robot.update(delta_time, cfg, obstructions);
+        let imu = self.robot.sense_imu(delta_time, cfg);
+
+        // prediction step
+        let control = match cfg.motion_model {
+            MotionModel::Velocity => Control::Velocity {
+                command: self.robot.command,
+                drive: cfg.drive,
+                delta_time,
+            },
+            MotionModel::Odometry => Control::Odometry {
+                motion: OdometryMotion::from_ticks(encoder_ticks, cfg),
+                delta_time,
+            },
+        };
+        self.ekf_slam.predict(&control, cfg);
+        self.gyro_ekf_slam.predict_with_imu(&control, &imu, cfg);
+        self.fast_slam.predict(&control, cfg);
+        self.dead_reckoning.predict(&control, cfg);
+        self.pose_graph.predict(&control, cfg);
+
+        // correction step
+        self.observations = self.robot.sense(landmarks, obstructions, cfg);
+        self.ekf_slam.update(&self.observations, obstructions, cfg);
+        self.gyro_ekf_slam
+            .update(&self.observations, obstructions, cfg);
+        self.fast_slam.update(&self.observations, obstructions, cfg);
+        self.pose_graph
+            .update(&self.observations, obstructions, cfg);
+        self.scan = self.robot.scan(obstructions, cfg);
+        self.pose_graph.update_scan(&self.scan, cfg);
+
+        // absolute position fixes, when there are any, in the estimators' frame
+        if let Some(fix) = self.robot.sense_gps(delta_time, outages, cfg) {
+            let local = self.frame().inverse().apply(vec2(fix.x, fix.y));
+            let local_fix = GpsFix {
+                x: local.x,
+                y: local.y,
+            };
+            self.ekf_slam.update_position(&local_fix, cfg);
+            self.gyro_ekf_slam.update_position(&local_fix, cfg);
+            self.fast_slam.update_position(&local_fix, cfg);
+            self.gps_fix = Some((fix, self.clock));
+        } else if !cfg.use_gps
+            || self
+                .gps_fix
+                .is_some_and(|(_, time)| self.clock - time > 1.5 / cfg.gps_rate)
+        {
+            // gone once the next fix is half a period overdue, as in an outage
+            self.gps_fix = None;
+        }
+
+        self.clock += delta_time;
+        let estimators: [(&'static str, &mut dyn Slam); 5] = [
+            ("EKF", &mut self.ekf_slam),
+            ("gyro EKF", &mut self.gyro_ekf_slam),
+            ("FastSLAM", &mut self.fast_slam),
+            ("dead reckoning", &mut self.dead_reckoning),
+            ("pose graph", &mut self.pose_graph),
+        ];
+        for (name, estimator) in estimators {
+            let color = estimator.color();
+            for event in estimator.take_loop_closures() {
+                self.loop_closures.push(LoggedLoopClosure {
+                    time: self.clock,
+                    estimator: name,
+                    color,
+                    event,
+                });
+            }
+        }
+
+        if let Some(estimated_bias) = self.gyro_ekf_slam.gyro_bias() {
+            if self.gyro_bias_history.len() == GYRO_BIAS_HISTORY {
+                self.gyro_bias_history.pop_front();
+            }
+            self.gyro_bias_history
+                .push_back((self.robot.gyro_bias, estimated_bias));
+        }
+    }
+
+    // looks for the other robots once they've all moved; positions are every robot's, in order
+    pub fn sense_robots(
+        &mut self,
+        index: usize,
+        positions: &[Vec2],
+        obstructions: &ObstructionGrid,
+        cfg: &Config,
+    ) {
+        self.sightings = self
+            .robot
+            .sense_robots(index, positions, obstructions, &self.config(cfg));
+    }
+}
+
+/*
+ * path from the start to the goal, and the obstructions as an estimated map
+ * saw them (none for ground truth), as planner::estimated_obstructions
+ * places them given the estimates
+ */
+fn plan_path(
+    goal: Vec2,
+    start: Vec2,
+    estimates: Option<&[MapLandmark]>,
+    obstructions: &ObstructionGrid,
+    landmarks: &[Landmark],
+    cfg: &Config,
+) -> (Option<Vec<Vec2>>, Vec<Obstruction>) {
+    let Some(estimates) = estimates else {
+        return (
+            planner::plan(cfg.planner, obstructions, start, goal, cfg),
+            Vec::new(),
+        );
+    };
+
+    let map = planner::estimated_obstructions(obstructions.obstructions(), landmarks, estimates);
+    let mut grid = ObstructionGrid::new(cfg.spatial_cell_size);
+    for obstruction in map.iter() {
+        grid.push(obstruction.clone());
+    }
+    (planner::plan(cfg.planner, &grid, start, goal, cfg), map)
+}
@@ -0,0 +1,195 @@
+use crate::app::hud::is_hud_hovered;
+use crate::config::Config;
+use crate::geometry::{Obstruction, ShapeKind};
+use crate::spatial_index::ObstructionGrid;
+use macroquad::prelude::*;
+
+// screen pixels the mouse has to travel before a press counts as a drag
+const DRAG_THRESHOLD: f32 = 4.0;
+
+// world units around a wall (or any edge) that still pick it
+const PICK_TOLERANCE: f32 = 8.0;
+
+enum Drag {
+    Create { start: Vec2 },
+    Move { index: usize, last: Vec2 },
+    Rotate { index: usize, last_angle: f32 },
+}
+
+/*
+ * places, moves, rotates and deletes obstructions with the mouse. a plain
+ * click places a default-sized shape or deletes the one under the cursor;
+ * dragging from empty space drags out the shape's size, dragging a shape
+ * moves it, and right-dragging a shape rotates it. polygons are clicked out
+ * vertex by vertex and closed with enter or a right click
+ */
+pub struct ObstructionEditor {
+    press_screen: Option<Vec2>, // where the left button went down, while held
+    dragging: bool,
+    drag: Option<Drag>,
+    pub polygon: Vec<Vec2>, // vertices of the polygon being placed
+}
+
+impl ObstructionEditor {
+    pub fn new() -> Self {
+        Self {
+            press_screen: None,
+            dragging: false,
+            drag: None,
+            polygon: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, camera: &Camera2D, obstructions: &mut ObstructionGrid, cfg: &Config) {
+        let mouse_screen = Vec2::from(mouse_position());
+        let mouse_world = camera.screen_to_world(mouse_screen);
+
+        // shift places landmarks and control places outages instead
+        let modifier_down = is_key_down(KeyCode::LeftShift)
+            || is_key_down(KeyCode::RightShift)
+            || is_key_down(KeyCode::LeftControl)
+            || is_key_down(KeyCode::RightControl)
+            || is_key_down(KeyCode::LeftAlt)
+            || is_key_down(KeyCode::RightAlt);
+
+        // switching tools drops a half-placed polygon
+        if cfg.obstruction_shape != ShapeKind::Polygon {
+            self.polygon.clear();
+        }
+
+        // a drag outlives its shape if the grid changed under it
+        if let Some(Drag::Move { index, .. } | Drag::Rotate { index, .. }) = self.drag
+            && obstructions.obstructions().get(index).is_none()
+        {
+            self.drag = None;
+            self.press_screen = None;
+        }
+
+        let picked = obstructions.pick(mouse_world, PICK_TOLERANCE);
+
+        // left button: place, move or delete
+        if !modifier_down && !is_hud_hovered() && is_mouse_button_pressed(MouseButton::Left) {
+            self.press_screen = Some(mouse_screen);
+            self.dragging = false;
+            self.drag = Some(match picked {
+                Some(index) if self.polygon.is_empty() => Drag::Move {
+                    index,
+                    last: mouse_world,
+                },
+                _ => Drag::Create { start: mouse_world },
+            });
+        }
+        if let Some(press) = self.press_screen
+            && press.distance(mouse_screen) > DRAG_THRESHOLD
+        {
+            self.dragging = true;
+        }
+        if self.dragging
+            && let Some(Drag::Move { index, last }) = &mut self.drag
+        {
+            let offset = mouse_world - *last;
+            obstructions.modify(*index, |obstruction| obstruction.translate(offset));
+            *last = mouse_world;
+        }
+        if is_mouse_button_released(MouseButton::Left) && self.press_screen.take().is_some() {
+            match self.drag.take() {
+                Some(Drag::Move { index, .. }) if !self.dragging => {
+                    obstructions.remove(index);
+                }
+                Some(Drag::Create { start }) => {
+                    if cfg.obstruction_shape == ShapeKind::Polygon {
+                        self.polygon.push(mouse_world);
+                    } else if self.dragging {
+                        place(obstructions, dragged_shape(start, mouse_world, cfg), cfg);
+                    } else {
+                        place(obstructions, default_shape(mouse_world, cfg), cfg);
+                    }
+                }
+                _ => {}
+            }
+            self.dragging = false;
+        }
+
+        // right button: rotate the shape under the cursor, or close the polygon
+        if !is_hud_hovered() && is_mouse_button_pressed(MouseButton::Right) {
+            match picked {
+                Some(index) if self.polygon.is_empty() => {
+                    let center = obstructions.obstructions()[index].center();
+                    self.drag = Some(Drag::Rotate {
+                        index,
+                        last_angle: (mouse_world - center).to_angle(),
+                    });
+                }
+                _ => self.close_polygon(obstructions, cfg),
+            }
+        }
+        if let Some(Drag::Rotate { index, last_angle }) = &mut self.drag
+            && let Some(obstruction) = obstructions.obstructions().get(*index)
+        {
+            let angle = (mouse_world - obstruction.center()).to_angle();
+            let turn = angle - *last_angle;
+            obstructions.modify(*index, |obstruction| obstruction.rotate(turn));
+            *last_angle = angle;
+            if is_mouse_button_released(MouseButton::Right) {
+                self.drag = None;
+            }
+        }
+
+        if is_key_released(KeyCode::Enter) {
+            self.close_polygon(obstructions, cfg);
+        }
+    }
+
+    // the shape that releasing the mouse would place now, if any
+    pub fn preview(&self, camera: &Camera2D, cfg: &Config) -> Option<Obstruction> {
+        match self.drag {
+            Some(Drag::Create { start })
+                if self.dragging && cfg.obstruction_shape != ShapeKind::Polygon =>
+            {
+                let mouse_world = camera.screen_to_world(Vec2::from(mouse_position()));
+                Some(dragged_shape(start, mouse_world, cfg))
+            }
+            _ => None,
+        }
+    }
+
+    fn close_polygon(&mut self, obstructions: &mut ObstructionGrid, cfg: &Config) {
+        if self.polygon.len() >= 3 {
+            let polygon = Obstruction::Polygon(std::mem::take(&mut self.polygon));
+            place(obstructions, polygon, cfg);
+        }
+    }
+}
+
+// with the selected behavior, so it may move on its own
+fn place(obstructions: &mut ObstructionGrid, obstruction: Obstruction, cfg: &Config) {
+    let motion = cfg.obstacle_behavior.motion(obstruction.center());
+    obstructions.push_moving(obstruction, motion);
+}
+
+fn default_shape(center: Vec2, cfg: &Config) -> Obstruction {
+    let half_size = 0.5 * vec2(cfg.obstruction_width, cfg.obstruction_height);
+    match cfg.obstruction_shape {
+        ShapeKind::Circle => Obstruction::Circle {
+            center,
+            radius: half_size.x,
+        },
+        ShapeKind::Wall => Obstruction::Wall {
+            start: center - vec2(half_size.x, 0.0),
+            end: center + vec2(half_size.x, 0.0),
+        },
+        _ => Obstruction::rectangle(center - half_size, center + half_size),
+    }
+}
+
+// rectangles span the drag, circles are centered where it started, walls follow it
+fn dragged_shape(start: Vec2, end: Vec2, cfg: &Config) -> Obstruction {
+    match cfg.obstruction_shape {
+        ShapeKind::Circle => Obstruction::Circle {
+            center: start,
+            radius: start.distance(end),
+        },
+        ShapeKind::Wall => Obstruction::Wall { start, end },
+        _ => Obstruction::rectangle(start, end),
+    }
+}
@@ -1,6 +1,6 @@
 use super::{FONT_SIZE, LINE_SPACING};
 use crate::app::user_settings::UserSettings;
-use crate::slam::{EkfSlam, FastSlam};
+use crate::slam::{DeadReckoning, EkfSlam, FastSlam, PoseGraphSlam};
 use macroquad::prelude::*;
 
 const COG_X: f32 = 30.0;
@@ -8,11 +8,23 @@ const COG_Y: f32 = 30.0;
 const COG_R: f32 = 8.0;
 const COG_THICKNESS: f32 = 8.0;
 
+// "new world" button, right of the cog
+const BUTTON_X: f32 = 60.0;
+const BUTTON_Y: f32 = 14.0;
+const BUTTON_W: f32 = 120.0;
+const BUTTON_H: f32 = 32.0;
+
 pub fn draw_legend(font: &Font) {
-    let right_offset = screen_width() - 115.0;
+    let right_offset = screen_width() - 175.0;
     let top_offset = screen_height() - 20.0;
 
-    let algorithms = [("FastSLAM", FastSlam::COLOR), ("EKF-SLAM", EkfSlam::COLOR)];
+    let algorithms = [
+        ("Dead reckoning", DeadReckoning::COLOR),
+        ("FastSLAM", FastSlam::COLOR),
+        ("EKF-SLAM", EkfSlam::COLOR),
+        ("EKF + gyro", EkfSlam::GYRO_COLOR),
+        ("Pose graph", PoseGraphSlam::COLOR),
+    ];
 
     for (i, (name, color)) in algorithms.iter().enumerate() {
         draw_text_ex(
@@ -36,24 +48,135 @@ pub fn draw_legend(font: &Font) {
     }
 }
 
+// right-aligned status lines in the top right corner
+pub fn draw_status(font: &Font, lines: &[String]) {
+    for (i, line) in lines.iter().enumerate() {
+        let dimensions = measure_text(line, Some(font), FONT_SIZE, 1.0);
+
+        draw_text_ex(
+            line,
+            screen_width() - dimensions.width - 20.0,
+            (i as f32 + 1.0) * LINE_SPACING,
+            TextParams {
+                font: Some(font),
+                font_size: FONT_SIZE,
+                color: LIGHTGRAY,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+// one line per robot under the buttons, each by a swatch of its color
+pub fn draw_robots(font: &Font, lines: &[(String, Color)]) {
+    let top = BUTTON_Y + BUTTON_H + LINE_SPACING;
+    for (i, (line, color)) in lines.iter().enumerate() {
+        let y = top + i as f32 * LINE_SPACING;
+        draw_rectangle(COG_X - 10.0, y - 15.0, 16.0, 16.0, *color);
+        draw_text_ex(
+            line,
+            COG_X + 14.0,
+            y,
+            TextParams {
+                font: Some(font),
+                font_size: FONT_SIZE,
+                color: LIGHTGRAY,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/*
+ * small line plot in the bottom left corner; every series is scaled to the
+ * shared min and max so they can be compared
+ */
+pub fn draw_plot(font: &Font, title: &str, series: &[(Vec<f32>, Color)]) {
+    let (w, h) = (240.0, 100.0);
+    let left = 20.0;
+    let top = screen_height() - h - 20.0;
+
+    draw_rectangle(left, top, w, h, Color::new(0.05, 0.05, 0.05, 0.7));
+    draw_text_ex(
+        title,
+        left,
+        top - 8.0,
+        TextParams {
+            font: Some(font),
+            font_size: FONT_SIZE,
+            color: LIGHTGRAY,
+            ..Default::default()
+        },
+    );
+
+    let values = series.iter().flat_map(|(values, _)| values.iter().copied());
+    let (min, max) = values.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    if !min.is_finite() {
+        return;
+    }
+
+    // keep a flat line from filling the whole height with noise
+    let span = (max - min).max(1e-3);
+    let to_screen = |i: usize, len: usize, value: f32| {
+        (
+            left + w * i as f32 / (len.max(2) - 1) as f32,
+            top + h * (1.0 - (value - min) / span),
+        )
+    };
+
+    // zero line, if it's in view
+    if min < 0.0 && max > 0.0 {
+        let (_, zero_y) = to_screen(0, 2, 0.0);
+        draw_line(left, zero_y, left + w, zero_y, 1.0, DARKGRAY);
+    }
+
+    for (values, color) in series {
+        for i in 1..values.len() {
+            let (x1, y1) = to_screen(i - 1, values.len(), values[i - 1]);
+            let (x2, y2) = to_screen(i, values.len(), values[i]);
+            draw_line(x1, y1, x2, y2, 1.5, *color);
+        }
+    }
+}
+
 pub fn draw_settings(font: &Font, user_settings: &mut UserSettings) {
     let offset = screen_width() / 4.0;
     let padding = 30.0;
 
-    // panel width, height, position
-    let w = 400.0;
-    let h = 8.0 * LINE_SPACING;
-    let panel_center_x = offset + w / 2.0;
-    let panel_center_y = screen_height() / 2.0;
-
     // text
     let mut text = [
         ("EKF-SLAM pose estimate", &mut user_settings.show_ekf_state),
         ("EKF-SLAM landmarks", &mut user_settings.show_ekf_landmarks),
         ("FastSLAM pose estimate", &mut user_settings.show_fast_state),
         ("FastSLAM landmarks", &mut user_settings.show_fast_landmarks),
+        (
+            "EKF + gyro pose estimate",
+            &mut user_settings.show_gyro_ekf_state,
+        ),
+        (
+            "EKF + gyro landmarks",
+            &mut user_settings.show_gyro_ekf_landmarks,
+        ),
+        (
+            "Dead reckoning pose",
+            &mut user_settings.show_dead_reckoning,
+        ),
+        ("Pose graph pose", &mut user_settings.show_graph_state),
+        ("Pose graph map", &mut user_settings.show_graph_map),
+        ("Sensor returns", &mut user_settings.show_observations),
+        ("Sensor footprint", &mut user_settings.show_sensor_footprint),
+        ("Gyro bias plot", &mut user_settings.show_gyro_bias_plot),
     ];
 
+    // panel width, height, position
+    let w = 400.0;
+    let h = (text.len() as f32 + 4.0) * LINE_SPACING;
+    let panel_center_x = offset + w / 2.0;
+    let panel_center_y = screen_height() / 2.0;
+    let panel_top = panel_center_y - h / 2.0;
+
     draw_rectangle_ex(
         panel_center_x,
         panel_center_y,
@@ -69,7 +192,7 @@ pub fn draw_settings(font: &Font, user_settings: &mut UserSettings) {
     draw_text_ex(
         "Visibility Menu",
         offset + padding + 80.0,
-        panel_center_y - 2.625 * LINE_SPACING - 7.5,
+        panel_top + 1.375 * LINE_SPACING - 7.5,
         TextParams {
             font: Some(font),
             font_size: FONT_SIZE,
@@ -80,7 +203,7 @@ pub fn draw_settings(font: &Font, user_settings: &mut UserSettings) {
     for (i, (label, value)) in text.iter_mut().enumerate() {
         // checkbox position
         let checkbox_x = offset + padding;
-        let checkbox_y = panel_center_y - (2.0 - i as f32) * LINE_SPACING;
+        let checkbox_y = panel_top + (2.0 + i as f32) * LINE_SPACING;
         let checkbox_size = 20.0;
 
         // check if hovered
@@ -122,7 +245,7 @@ pub fn draw_settings(font: &Font, user_settings: &mut UserSettings) {
         draw_text_ex(
             label,
             offset + padding + checkbox_size,
-            panel_center_y - (1.5 - i as f32) * LINE_SPACING - 7.5,
+            checkbox_y + 0.5 * LINE_SPACING - 7.5,
             TextParams {
                 font: Some(font),
                 font_size: FONT_SIZE,
@@ -168,3 +291,40 @@ pub fn is_cog_hovered() -> bool {
         && mouse_x < COG_X + effective_radius
         && mouse_y < COG_Y + effective_radius
 }
+
+pub fn draw_world_button(font: &Font) {
+    let color = if is_world_button_hovered() {
+        DARKGRAY
+    } else {
+        LIGHTGRAY
+    };
+    let label = "New world";
+    let dimensions = measure_text(label, Some(font), FONT_SIZE, 1.0);
+
+    draw_rectangle_lines(BUTTON_X, BUTTON_Y, BUTTON_W, BUTTON_H, 2.0, color);
+    draw_text_ex(
+        label,
+        BUTTON_X + 0.5 * (BUTTON_W - dimensions.width),
+        BUTTON_Y + 0.5 * (BUTTON_H - dimensions.height) + dimensions.offset_y,
+        TextParams {
+            font: Some(font),
+            font_size: FONT_SIZE,
+            color,
+            ..Default::default()
+        },
+    );
+}
+
+// whether the mouse is over a widget, so clicks don't also edit the world
+pub fn is_hud_hovered() -> bool {
+    is_cog_hovered() || is_world_button_hovered()
+}
+
+pub fn is_world_button_hovered() -> bool {
+    let (mouse_x, mouse_y) = mouse_position();
+
+    mouse_x > BUTTON_X
+        && mouse_y > BUTTON_Y
+        && mouse_x < BUTTON_X + BUTTON_W
+        && mouse_y < BUTTON_Y + BUTTON_H
+}
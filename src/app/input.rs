@@ -1,50 +1,103 @@
+use crate::agent::Agent;
 use crate::config::Config;
+use crate::controller::{FollowMode, WaypointFollower};
+use crate::exploration::ExplorationStrategy;
+use crate::kinematics::DriveType;
 use crate::simulation::{Landmark, Robot};
 use macroquad::prelude::*;
 
+/*
+ * W/S (up/down) drive forward and back and A/D (left/right) turn, whatever
+ * the drive: a differential drive speeds one wheel up and slows the other,
+ * ackermann steers. omnidirectional strafes on A/D and turns on Q/E or the
+ * arrows instead
+ */
 pub fn movement_input(robot: &mut Robot, cfg: &Config, delta_time: f32) {
-    // movement
-    if is_key_down(KeyCode::Up) || is_key_down(KeyCode::W) {
-        robot.linear_velocity += cfg.linear_acc * delta_time;
-    }
-    if is_key_down(KeyCode::Down) || is_key_down(KeyCode::S) {
-        robot.linear_velocity -= cfg.linear_acc * delta_time;
-    }
-    if is_key_down(KeyCode::Right) || is_key_down(KeyCode::D) {
-        robot.angular_velocity -= cfg.angular_acc * delta_time;
-    }
-    if is_key_down(KeyCode::Left) || is_key_down(KeyCode::A) {
-        robot.angular_velocity += cfg.angular_acc * delta_time;
+    let axis = |positive: &[KeyCode], negative: &[KeyCode]| {
+        let held = |keys: &[KeyCode]| keys.iter().any(|&key| is_key_down(key));
+        held(positive) as i32 as f32 - held(negative) as i32 as f32
+    };
+    let forward = axis(&[KeyCode::Up, KeyCode::W], &[KeyCode::Down, KeyCode::S]);
+    let linear = cfg.linear_acc * delta_time;
+    let angular = cfg.angular_acc * delta_time;
+
+    match cfg.drive {
+        DriveType::Unicycle => {
+            let turn = axis(&[KeyCode::Left, KeyCode::A], &[KeyCode::Right, KeyCode::D]);
+            robot.command[0] += forward * linear;
+            robot.command[1] += turn * angular;
+        }
+        DriveType::DifferentialDrive => {
+            let turn = axis(&[KeyCode::Left, KeyCode::A], &[KeyCode::Right, KeyCode::D]);
+            robot.command[0] += (forward - turn) * linear;
+            robot.command[1] += (forward + turn) * linear;
+        }
+        DriveType::Ackermann => {
+            let turn = axis(&[KeyCode::Left, KeyCode::A], &[KeyCode::Right, KeyCode::D]);
+            robot.command[0] += forward * linear;
+            robot.command[1] += turn * cfg.steering_rate * delta_time;
+        }
+        DriveType::Omnidirectional => {
+            let strafe = axis(&[KeyCode::A], &[KeyCode::D]);
+            let turn = axis(&[KeyCode::Left, KeyCode::Q], &[KeyCode::Right, KeyCode::E]);
+            robot.command[0] += forward * linear;
+            robot.command[1] += strafe * linear;
+            robot.command[2] += turn * angular;
+        }
     }
 }
 
-pub fn obstructions_input(camera: &Camera2D, obstructions: &mut Vec<Rect>, cfg: &Config) {
+pub fn outages_input(camera: &Camera2D, outages: &mut Vec<Rect>, cfg: &Config) {
     let mouse_screen = mouse_position();
     let mouse_world = camera.screen_to_world(vec2(mouse_screen.0, mouse_screen.1));
 
-    if !(is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift))
+    if (is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl))
         && is_mouse_button_released(MouseButton::Left)
     {
-        // delete the obstruction if mouse is touching it
+        // delete the outage if mouse is touching it
         let mut removed = false;
-        for i in 0..obstructions.len() {
-            if obstructions[i].contains(mouse_world) {
-                obstructions.remove(i);
+        for i in 0..outages.len() {
+            if outages[i].contains(mouse_world) {
+                outages.remove(i);
                 removed = true;
                 break;
             }
         }
         if !removed {
-            obstructions.push(Rect::new(
-                mouse_world.x - cfg.obstruction_width / 2.0,
-                mouse_world.y - cfg.obstruction_height / 2.0,
-                cfg.obstruction_width,
-                cfg.obstruction_height,
+            outages.push(Rect::new(
+                mouse_world.x - cfg.outage_width / 2.0,
+                mouse_world.y - cfg.outage_height / 2.0,
+                cfg.outage_width,
+                cfg.outage_height,
             ));
         }
     }
 }
 
+// alt + click queues a waypoint, or removes the one under the cursor
+pub fn waypoints_input(camera: &Camera2D, follower: &mut WaypointFollower, cfg: &Config) {
+    let mouse_screen = mouse_position();
+    let mouse_world = camera.screen_to_world(vec2(mouse_screen.0, mouse_screen.1));
+
+    if (is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt))
+        && is_mouse_button_released(MouseButton::Left)
+    {
+        let picked = follower
+            .waypoints
+            .iter()
+            .position(|waypoint| waypoint.distance(mouse_world) < cfg.waypoint_tolerance);
+        match picked {
+            Some(index) => {
+                follower.waypoints.remove(index);
+                if follower.target > index {
+                    follower.target -= 1;
+                }
+            }
+            None => follower.waypoints.push(mouse_world),
+        }
+    }
+}
+
 pub fn landmarks_input(gt_camera: &Camera2D, landmarks: &mut Vec<Landmark>, cfg: &Config) {
     let mouse_screen = mouse_position();
     let mouse_world = gt_camera.screen_to_world(vec2(mouse_screen.0, mouse_screen.1));
@@ -70,11 +123,133 @@ pub fn landmarks_input(gt_camera: &Camera2D, landmarks: &mut Vec<Landmark>, cfg:
                 id,
                 x: mouse_world.x,
                 y: mouse_world.y,
+                moving: rand::gen_range(0.0, 1.0) < cfg.moving_landmark_fraction,
+                velocity: (0.0, 0.0),
             });
         }
     }
 }
 
+pub fn resampling_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::R) {
+        cfg.resampling_scheme = cfg.resampling_scheme.next();
+    }
+}
+
+pub fn sensor_mode_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::M) {
+        cfg.sensor_mode = cfg.sensor_mode.next();
+    }
+}
+
+pub fn motion_model_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::O) {
+        cfg.motion_model = cfg.motion_model.next();
+    }
+}
+
+pub fn obstruction_shape_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::T) {
+        cfg.obstruction_shape = cfg.obstruction_shape.next();
+    }
+}
+
+pub fn world_kind_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::N) {
+        cfg.world_kind = cfg.world_kind.next();
+    }
+}
+
+// switching modes starts the path over from the robot's position
+pub fn follow_mode_input(agent: &mut Agent) {
+    if is_key_released(KeyCode::P) {
+        agent.follow_mode = agent.follow_mode.next();
+        agent.follower.restart(vec2(agent.robot.x, agent.robot.y));
+    }
+    if is_key_released(KeyCode::C) {
+        agent.follower.waypoints.clear();
+    }
+}
+
+pub fn planner_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::F) {
+        cfg.planner = cfg.planner.next();
+    }
+    if is_key_released(KeyCode::J) {
+        cfg.planning_map = cfg.planning_map.next();
+    }
+}
+
+pub fn merge_source_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::H) {
+        cfg.merge_source = cfg.merge_source.next();
+    }
+}
+
+pub fn graph_kernel_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::B) {
+        cfg.graph_kernel = cfg.graph_kernel.next();
+    }
+}
+
+// X plans a path to the point under the cursor
+pub fn plan_input(camera: &Camera2D) -> Option<Vec2> {
+    let mouse_screen = mouse_position();
+    is_key_released(KeyCode::X)
+        .then(|| camera.screen_to_world(vec2(mouse_screen.0, mouse_screen.1)))
+}
+
+/*
+ * I cycles the robot's exploration strategy, its autopilot; starting one
+ * sets the follower going and forgets the path it had
+ */
+pub fn exploration_input(agent: &mut Agent) {
+    if is_key_released(KeyCode::I) {
+        agent.exploration = agent.exploration.next();
+        if agent.exploration != ExplorationStrategy::Off {
+            agent.follow_mode = FollowMode::Once;
+            agent.follower.waypoints.clear();
+            agent.follower.restart(vec2(agent.robot.x, agent.robot.y));
+            agent.explorer.goal = None;
+        }
+    }
+}
+
+// tab hands the keys, and the camera, to the next robot
+pub fn focus_input(focus: &mut usize, count: usize) {
+    if is_key_released(KeyCode::Tab) {
+        *focus = (*focus + 1) % count;
+    }
+}
+
+pub fn obstacle_behavior_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::V) {
+        cfg.obstacle_behavior = cfg.obstacle_behavior.next();
+    }
+}
+
+pub fn landmark_motion_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::L) {
+        cfg.move_landmarks = !cfg.move_landmarks;
+    }
+}
+
+pub fn gps_input(cfg: &mut Config) {
+    if is_key_released(KeyCode::G) {
+        cfg.use_gps = !cfg.use_gps;
+    }
+}
+
+// commands mean something else under each drive, so every robot starts over from rest
+pub fn drive_input(agents: &mut [Agent], cfg: &mut Config) {
+    if is_key_released(KeyCode::K) {
+        cfg.drive = cfg.drive.next();
+        for agent in agents {
+            agent.robot.command = Default::default();
+        }
+    }
+}
+
 pub fn zoom_input(
     horizontal_units: &mut f32,
     min_horizontal_units: f32,
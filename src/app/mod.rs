@@ -1,3 +1,4 @@
+pub mod editor;
 pub mod hud;
 pub mod input;
 pub mod renderer;
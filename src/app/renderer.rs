@@ -1,8 +1,21 @@
 use macroquad::prelude::*;
 
 use super::SHADOW_OFFSET;
-use crate::simulation::Landmark;
-use crate::slam::Slam;
+use crate::config::Config;
+use crate::controller::FollowMode;
+use crate::exploration::{Occupancy, OccupancyGrid};
+use crate::geometry::{Obstruction, RigidTransform};
+use crate::map_merge::{MapMerger, MergedLandmark};
+use crate::simulation::{
+    GpsFix, Landmark, LidarScan, Observation, ObservationSource, RobotSighting,
+};
+use crate::slam::PoseGraphSlam;
+use crate::slam::pose_graph::EdgeKind;
+use crate::spatial_index::ObstructionGrid;
+use crate::utils::relative_to_absolute;
+
+// walls have no thickness to the robot or the sensor, only on screen
+const WALL_THICKNESS: f32 = 4.0;
 
 pub fn draw_gridlines(
     robot_x: f32,
@@ -46,32 +59,93 @@ pub fn draw_gridlines(
     }
 }
 
-pub fn draw_obstructions_shadows(obstructions: &[Rect]) {
+pub fn draw_obstructions_shadows(obstructions: &[Obstruction]) {
     for obstruction in obstructions.iter() {
         for i in 0..SHADOW_OFFSET as i32 {
-            draw_rectangle(
-                obstruction.x - (i as f32),
-                obstruction.y - (i as f32),
-                obstruction.w,
-                obstruction.h,
-                Color::new(0.0, 0.0, 0.0, 1.0 / (i as f32 + 1.0)),
-            );
+            let mut shadow = obstruction.clone();
+            shadow.translate(vec2(-(i as f32), -(i as f32)));
+            draw_obstruction(&shadow, Color::new(0.0, 0.0, 0.0, 1.0 / (i as f32 + 1.0)));
         }
     }
 }
 
-pub fn draw_obstructions(obstructions: &[Rect]) {
-    for obstruction in obstructions.iter() {
+pub fn draw_obstructions(obstructions: &ObstructionGrid) {
+    for (index, obstruction) in obstructions.obstructions().iter().enumerate() {
+        let color = if obstructions.is_moving(index) {
+            Color::new(0.75, 0.55, 0.35, 1.0)
+        } else {
+            GRAY
+        };
+        draw_obstruction(obstruction, color);
+    }
+}
+
+// the shape being dragged out and the vertices of a polygon being clicked out
+pub fn draw_obstruction_preview(preview: Option<&Obstruction>, polygon: &[Vec2]) {
+    let color = Color::new(0.8, 0.8, 0.8, 0.6);
+
+    if let Some(obstruction) = preview {
+        draw_obstruction_outline(obstruction, color);
+    }
+    for pair in polygon.windows(2) {
+        draw_line(pair[0].x, pair[0].y, pair[1].x, pair[1].y, 2.0, color);
+    }
+    for vertex in polygon.iter() {
+        draw_circle(vertex.x, vertex.y, 3.0, color);
+    }
+}
+
+fn draw_obstruction(obstruction: &Obstruction, color: Color) {
+    match obstruction {
+        Obstruction::Polygon(_) => {
+            for [a, b, c] in obstruction.triangles() {
+                draw_triangle(a, b, c, color);
+            }
+        }
+        Obstruction::Circle { center, radius } => draw_circle(center.x, center.y, *radius, color),
+        Obstruction::Wall { start, end } => {
+            draw_line(start.x, start.y, end.x, end.y, WALL_THICKNESS, color);
+        }
+    }
+}
+
+fn draw_obstruction_outline(obstruction: &Obstruction, color: Color) {
+    if let Obstruction::Circle { center, radius } = obstruction {
+        draw_circle_lines(center.x, center.y, *radius, 2.0, color);
+    }
+    for (a, b) in obstruction.edges() {
+        draw_line(a.x, a.y, b.x, b.y, 2.0, color);
+    }
+}
+
+// gps outage regions ("tunnels"), drawn under everything else
+pub fn draw_outages(outages: &[Rect]) {
+    for outage in outages.iter() {
         draw_rectangle(
-            obstruction.x,
-            obstruction.y,
-            obstruction.w,
-            obstruction.h,
-            GRAY,
+            outage.x,
+            outage.y,
+            outage.w,
+            outage.h,
+            Color::new(0.2, 0.25, 0.4, 0.35),
+        );
+        draw_rectangle_lines(
+            outage.x,
+            outage.y,
+            outage.w,
+            outage.h,
+            2.0,
+            Color::new(0.4, 0.5, 0.8, 0.6),
         );
     }
 }
 
+pub fn draw_gps_fix(fix: &GpsFix, stdev: f32) {
+    let color = Color::new(0.4, 0.6, 1.0, 0.8);
+    draw_circle_lines(fix.x, fix.y, 2.0 * stdev, 1.0, color);
+    draw_line(fix.x - 4.0, fix.y, fix.x + 4.0, fix.y, 1.5, color);
+    draw_line(fix.x, fix.y - 4.0, fix.x, fix.y + 4.0, 1.5, color);
+}
+
 pub fn draw_landmarks_shadows(landmarks: &[Landmark], landmark_radius: f32) {
     for landmark in landmarks.iter() {
         for i in 0..(SHADOW_OFFSET as i32 / 2) {
@@ -85,9 +159,58 @@ pub fn draw_landmarks_shadows(landmarks: &[Landmark], landmark_radius: f32) {
     }
 }
 
+// the obstructions as the map a path was planned on saw them
+pub fn draw_planned_obstructions(obstructions: &[Obstruction], color: Color) {
+    for obstruction in obstructions.iter() {
+        draw_obstruction_outline(obstruction, color);
+    }
+}
+
+// cells the sensor has swept, frontiers highlighted
+pub fn draw_occupancy(grid: &OccupancyGrid) {
+    let size = grid.resolution();
+    for (corner, occupancy, frontier) in grid.cells() {
+        let color = match occupancy {
+            _ if frontier => Color::new(0.3, 0.7, 0.9, 0.35),
+            Occupancy::Free => Color::new(0.3, 0.5, 0.3, 0.15),
+            Occupancy::Occupied => Color::new(0.8, 0.35, 0.3, 0.35),
+        };
+        draw_rectangle(corner.x, corner.y, size, size, color);
+    }
+}
+
+// the path through the waypoints, closed when looping, with the current target ringed
+pub fn draw_waypoints(waypoints: &[Vec2], target: usize, mode: FollowMode) {
+    let color = Color::new(0.9, 0.8, 0.3, 0.8);
+
+    for pair in waypoints.windows(2) {
+        draw_line(pair[0].x, pair[0].y, pair[1].x, pair[1].y, 2.0, color);
+    }
+    if mode == FollowMode::Loop
+        && let (Some(first), Some(last)) = (waypoints.first(), waypoints.last())
+    {
+        draw_line(last.x, last.y, first.x, first.y, 2.0, color);
+    }
+
+    for waypoint in waypoints.iter() {
+        draw_circle(waypoint.x, waypoint.y, 5.0, color);
+    }
+    if mode != FollowMode::Off
+        && let Some(waypoint) = waypoints.get(target)
+    {
+        draw_circle_lines(waypoint.x, waypoint.y, 10.0, 2.0, color);
+    }
+}
+
+// landmarks that can move are tinted
 pub fn draw_landmarks(landmarks: &[Landmark], landmark_radius: f32) {
     for landmark in landmarks.iter() {
-        draw_circle(landmark.x, landmark.y, landmark_radius, WHITE);
+        let color = if landmark.moving {
+            Color::new(1.0, 0.8, 0.5, 1.0)
+        } else {
+            WHITE
+        };
+        draw_circle(landmark.x, landmark.y, landmark_radius, color);
     }
 }
 
@@ -119,10 +242,10 @@ pub fn draw_robot(x: f32, y: f32, theta: f32, radius: f32, fill_color: Color, ey
     );
 }
 
-pub fn draw_slam_state(slam: &dyn Slam, radius: f32) {
-    let (x, y, theta) = slam.get_state();
+// an estimated pose, already in world coordinates, in the estimator's color
+pub fn draw_slam_state((x, y, theta): (f32, f32, f32), color: Color, radius: f32) {
     let thickness = radius / 2.0;
-    draw_circle_lines(x, y, radius, thickness, slam.color());
+    draw_circle_lines(x, y, radius, thickness, color);
 
     let triangle_radius = 0.3 * thickness; // distance from centroid to vertex
     draw_poly(
@@ -135,8 +258,196 @@ pub fn draw_slam_state(slam: &dyn Slam, radius: f32) {
     );
 }
 
-pub fn draw_slam_landmarks(slam: &dyn Slam, radius: f32) {
-    for landmark in slam.get_landmarks() {
-        draw_circle(landmark.1, landmark.2, radius, slam.color());
+pub fn draw_slam_landmarks(landmarks: &[(usize, f32, f32)], color: Color, radius: f32) {
+    for landmark in landmarks {
+        draw_circle(landmark.1, landmark.2, radius, color);
+    }
+}
+
+/*
+ * a loop closure, from the pose it left the estimate at to each landmark it
+ * closed on, where the map had that landmark before; fades out as it ages,
+ * by the fraction given
+ */
+pub fn draw_loop_closure(pose: Vec2, landmarks: &[Vec2], color: Color, fade: f32) {
+    let color = Color {
+        a: 0.9 * (1.0 - fade),
+        ..color
+    };
+    for landmark in landmarks {
+        draw_line(pose.x, pose.y, landmark.x, landmark.y, 3.0, color);
+        draw_circle_lines(landmark.x, landmark.y, 12.0, 2.0, color);
+    }
+}
+
+/*
+ * the merged map, each landmark ringed by its 2σ ellipse; filled in where
+ * more than one robot's map had it
+ */
+pub fn draw_merged_map(landmarks: &[MergedLandmark], radius: f32) {
+    const SEGMENTS: usize = 24;
+
+    for landmark in landmarks {
+        let (x, y) = (landmark.mean.x, landmark.mean.y);
+        if landmark.robots > 1 {
+            draw_circle(x, y, radius, MapMerger::COLOR);
+        } else {
+            draw_circle_lines(x, y, radius, 2.0, MapMerger::COLOR);
+        }
+
+        // principal axes of the covariance
+        let (a, b, c) = (
+            landmark.covariance[(0, 0)],
+            landmark.covariance[(0, 1)],
+            landmark.covariance[(1, 1)],
+        );
+        let axis = Vec2::from_angle(0.5 * f32::atan2(2.0 * b, a - c));
+        let spread = ((0.5 * (a - c)).powi(2) + b * b).sqrt();
+        let major = 2.0 * (0.5 * (a + c) + spread).max(0.0).sqrt();
+        let minor = 2.0 * (0.5 * (a + c) - spread).max(0.0).sqrt();
+
+        let point = |step: usize| {
+            let angle = step as f32 / SEGMENTS as f32 * std::f32::consts::TAU;
+            vec2(x, y) + axis.rotate(vec2(major * angle.cos(), minor * angle.sin()))
+        };
+        for step in 0..SEGMENTS {
+            let (start, end) = (point(step), point(step + 1));
+            draw_line(start.x, start.y, end.x, end.y, 1.0, MapMerger::COLOR);
+        }
+    }
+}
+
+/*
+ * the pose graph's map: every keyframe's scan where the graph puts the
+ * keyframe, the keyframes themselves, and the edges between them with the
+ * scan matches that closed loops brighter; frame takes the graph into the world
+ */
+pub fn draw_pose_graph(graph: &PoseGraphSlam, frame: RigidTransform) {
+    let color = PoseGraphSlam::COLOR;
+    let place = |index: usize| {
+        let pose = graph.keyframes[index].pose;
+        RigidTransform {
+            angle: pose.z,
+            translation: vec2(pose.x, pose.y),
+        }
+        .then(&frame)
+    };
+
+    for index in 0..graph.keyframes.len() {
+        let transform = place(index);
+        for &point in &graph.keyframes[index].points {
+            let point = transform.apply(point);
+            draw_rectangle(point.x - 1.0, point.y - 1.0, 2.0, 2.0, color);
+        }
+        draw_circle(transform.translation.x, transform.translation.y, 3.0, color);
+    }
+
+    for edge in &graph.edges {
+        let (from, to) = (place(edge.from).translation, place(edge.to).translation);
+        let (thickness, alpha) = match edge.kind {
+            EdgeKind::Odometry => (1.0, 0.5),
+            EdgeKind::ScanMatch => (2.0, 1.0),
+        };
+        draw_line(
+            from.x,
+            from.y,
+            to.x,
+            to.y,
+            thickness,
+            Color { a: alpha, ..color },
+        );
+    }
+}
+
+// where each lidar beam hit, as dots
+pub fn draw_scan(x: f32, y: f32, theta: f32, scan: &LidarScan, cfg: &Config) {
+    for point in scan.points(cfg) {
+        let (end_x, end_y) = relative_to_absolute(x, y, theta, point.length(), point.to_angle());
+        draw_circle(end_x, end_y, 1.5, Color::new(1.0, 1.0, 1.0, 0.5));
+    }
+}
+
+// a line from the robot to each robot it sees
+pub fn draw_sightings(x: f32, y: f32, theta: f32, sightings: &[RobotSighting]) {
+    for sighting in sightings {
+        let (end_x, end_y) = relative_to_absolute(x, y, theta, sighting.range, sighting.bearing);
+        draw_line(x, y, end_x, end_y, 1.0, Color::new(0.6, 1.0, 0.85, 0.4));
+    }
+}
+
+pub fn draw_observations(
+    x: f32,
+    y: f32,
+    theta: f32,
+    observations: &[Observation],
+    sensor_range: f32,
+) {
+    for observation in observations {
+        let color = match observation.ground_truth {
+            ObservationSource::Landmark => Color::new(1.0, 1.0, 1.0, 0.3),
+            ObservationSource::WrongId { .. } => Color::new(1.0, 0.6, 0.0, 0.8),
+            ObservationSource::Clutter => Color::new(1.0, 0.2, 0.2, 0.8),
+        };
+
+        // range-only returns are drawn as circles around the robot
+        let Some(bearing) = observation.bearing else {
+            if let Some(range) = observation.range {
+                draw_circle_lines(x, y, range, 1.0, color);
+            }
+            continue;
+        };
+
+        // bearing-only returns are drawn as rays out to the edge of the sensor
+        let range = observation.range.unwrap_or(sensor_range);
+        let (end_x, end_y) = relative_to_absolute(x, y, theta, range, bearing);
+
+        draw_line(x, y, end_x, end_y, 1.0, color);
+        if observation.range.is_some() {
+            draw_circle(end_x, end_y, 3.0, color);
+        }
+    }
+}
+
+/*
+ * sensing cone: an annular sector between the min and max sensor range,
+ * split into quads along the arc
+ */
+pub fn draw_sensor_footprint(x: f32, y: f32, theta: f32, min_range: f32, max_range: f32, fov: f32) {
+    let color = Color::new(1.0, 1.0, 0.6, 0.06);
+    let segments = ((fov / std::f32::consts::TAU) * 64.0).ceil().max(1.0) as i32;
+    let step = fov / segments as f32;
+    let start = theta - 0.5 * fov;
+
+    let point = |angle: f32, range: f32| vec2(x + range * angle.cos(), y + range * angle.sin());
+
+    for i in 0..segments {
+        let a0 = start + step * i as f32;
+        let a1 = a0 + step;
+
+        let inner0 = point(a0, min_range);
+        let inner1 = point(a1, min_range);
+        let outer0 = point(a0, max_range);
+        let outer1 = point(a1, max_range);
+
+        draw_triangle(inner0, outer0, outer1, color);
+        if min_range > 0.0 {
+            draw_triangle(inner0, outer1, inner1, color);
+        }
+    }
+
+    // outline the cone edges when it isn't a full circle
+    if fov < std::f32::consts::TAU {
+        for angle in [start, start + fov] {
+            let inner = point(angle, min_range);
+            let outer = point(angle, max_range);
+            draw_line(
+                inner.x,
+                inner.y,
+                outer.x,
+                outer.y,
+                1.0,
+                Color::new(1.0, 1.0, 0.6, 0.3),
+            );
+        }
     }
 }
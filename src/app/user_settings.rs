@@ -2,10 +2,24 @@ pub struct UserSettings {
     // states
     pub show_ekf_state: bool,
     pub show_fast_state: bool,
+    pub show_gyro_ekf_state: bool,
+    pub show_dead_reckoning: bool,
+    pub show_graph_state: bool,
 
     // landmark estimates
     pub show_ekf_landmarks: bool,
     pub show_fast_landmarks: bool,
+    pub show_gyro_ekf_landmarks: bool,
+
+    // the pose graph's keyframes, edges, the scans hung on them and its landmarks
+    pub show_graph_map: bool,
+
+    // raw sensor returns, coloured by ground truth
+    pub show_observations: bool,
+    pub show_sensor_footprint: bool,
+
+    // gyro bias learned by the gyro-aided EKF against ground truth
+    pub show_gyro_bias_plot: bool,
 }
 
 impl Default for UserSettings {
@@ -13,8 +27,16 @@ impl Default for UserSettings {
         Self {
             show_ekf_state: true,
             show_fast_state: true,
+            show_gyro_ekf_state: true,
+            show_dead_reckoning: true,
+            show_graph_state: true,
             show_ekf_landmarks: true,
             show_fast_landmarks: true,
+            show_gyro_ekf_landmarks: false,
+            show_graph_map: true,
+            show_observations: false,
+            show_sensor_footprint: true,
+            show_gyro_bias_plot: true,
         }
     }
 }
@@ -0,0 +1,1051 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use macroquad::prelude::{Vec2, vec2};
+use nalgebra::Vector3;
+
+use crate::agent::Agent;
+use crate::config::Config;
+use crate::controller::{FollowMode, WaypointFollower};
+use crate::dynamics::{self, ObstacleBehavior};
+use crate::exploration::{ExplorationStrategy, Explorer};
+use crate::geometry::Obstruction;
+use crate::kinematics::DriveType;
+use crate::map_merge::{MapMerger, MergeSource};
+use crate::planner::{self, MapSource, PlannerKind};
+use crate::simulation::{Observation, ObservationSource, Robot};
+use crate::slam::fast::LandmarkEstimate;
+use crate::slam::pose_graph::{EdgeKind, REJECTED_WEIGHT};
+use crate::slam::{
+    Control, DeadReckoning, EkfSlam, FastSlam, LoopClosure, ResamplingScheme, RobustKernel, Slam,
+};
+use crate::spatial_index::ObstructionGrid;
+use crate::utils::{Rng, available_threads};
+use crate::world_gen::{self, WorldKind};
+
+/*
+ * headless benchmarks; each prints the mean wall time per iteration,
+ * so run them with --release
+ */
+pub fn run(filter: Option<&str>) {
+    let benches: [(&str, fn()); 12] = [
+        ("resample", resample),
+        ("particles", particles),
+        ("occlusion", occlusion),
+        ("worlds", worlds),
+        ("dynamics", dynamics),
+        ("planning", planning),
+        ("navigation", navigation),
+        ("exploration", exploration),
+        ("merging", merging),
+        ("loops", loops),
+        ("posegraph", pose_graph),
+        ("outliers", outliers),
+    ];
+
+    for (name, bench) in benches {
+        if filter.is_none_or(|filter| name.contains(filter)) {
+            println!("== {name} ==");
+            bench();
+        }
+    }
+}
+
+fn time<F: FnMut()>(label: &str, iterations: u32, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let mean = start.elapsed() / iterations;
+
+    println!("{label:<48} {mean:>12.2?}");
+    mean
+}
+
+// observations of landmarks spread in a spiral around the origin
+fn spiral_observations(num_landmarks: usize) -> Vec<Observation> {
+    (0..num_landmarks)
+        .map(|id| Observation {
+            id,
+            range: Some(20.0 + id as f32),
+            bearing: Some((id as f32 * 0.7).sin() * std::f32::consts::PI),
+            ground_truth: ObservationSource::Landmark,
+        })
+        .collect()
+}
+
+/*
+ * cost of resampling 1000 particles with 500 landmarks each, compared to
+ * deep-copying a HashMap per particle
+ */
+fn resample() {
+    const PARTICLES: usize = 1000;
+    const LANDMARKS: usize = 500;
+
+    let cfg = Config::default();
+    let observations = spiral_observations(LANDMARKS);
+    let obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+
+    let mut fast_slam = FastSlam::new(PARTICLES);
+    fast_slam.update(&observations, &obstructions, &cfg);
+    assert_eq!(fast_slam.particles[0].landmarks.len(), LANDMARKS);
+
+    time("resample (shared landmark tree)", 100, || {
+        fast_slam.resample(ResamplingScheme::Systematic)
+    });
+
+    // a handful of re-observed landmarks copies only their paths
+    let revisited = &observations[..10];
+    time("resample + correct 10 landmarks (shared tree)", 20, || {
+        fast_slam.resample(ResamplingScheme::Systematic);
+        fast_slam.update(revisited, &obstructions, &cfg);
+    });
+
+    let hashmap: HashMap<usize, LandmarkEstimate> = fast_slam.particles[0]
+        .landmarks
+        .iter()
+        .map(|(id, landmark)| (id, landmark.clone()))
+        .collect();
+    time("resample (deep-copied HashMap per particle)", 100, || {
+        let copies: Vec<_> = (0..PARTICLES).map(|_| hashmap.clone()).collect();
+        std::hint::black_box(copies);
+    });
+}
+
+/*
+ * FastSLAM predict + update with 50 visible landmarks for increasing particle
+ * and thread counts; speedup is relative to a single thread
+ */
+fn particles() {
+    const LANDMARKS: usize = 50;
+
+    let cfg = Config::default();
+    let observations = spiral_observations(LANDMARKS);
+    let obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+
+    let mut thread_counts = vec![1, 2, 4, available_threads()];
+    thread_counts.sort_unstable();
+    thread_counts.dedup();
+    println!("{} core(s) available", available_threads());
+
+    for num_particles in [100, 1000, 10000] {
+        let mut baseline = None;
+        let mut baseline_state = None;
+
+        for &num_threads in &thread_counts {
+            let mut fast_slam = FastSlam::new(num_particles);
+            fast_slam.num_threads = num_threads;
+            fast_slam.update(&observations, &obstructions, &cfg);
+
+            let mean = time(
+                &format!("{num_particles} particles, {num_threads} thread(s)"),
+                20,
+                || {
+                    fast_slam.predict(
+                        &Control::Velocity {
+                            command: Vector3::new(100.0, 0.5, 0.0),
+                            drive: DriveType::Unicycle,
+                            delta_time: 1.0 / 60.0,
+                        },
+                        &cfg,
+                    );
+                    fast_slam.update(&observations, &obstructions, &cfg);
+                },
+            );
+            let baseline = *baseline.get_or_insert(mean);
+            println!(
+                "{:<48} {:>11.2}x",
+                "  speedup",
+                baseline.as_secs_f64() / mean.as_secs_f64()
+            );
+
+            // per-particle random streams make the result independent of thread count
+            let state = fast_slam.get_state();
+            assert_eq!(
+                *baseline_state.get_or_insert(state),
+                state,
+                "results differ between thread counts"
+            );
+        }
+    }
+}
+
+/*
+ * line-of-sight and collision queries in generated mazes of growing size,
+ * through the spatial index and by testing every obstruction
+ */
+fn occlusion() {
+    const QUERIES: usize = 10000;
+
+    let mut cfg = Config::default();
+    let mut rng = Rng::new(7);
+
+    for world_size in [2000.0, 6000.0, 20000.0] {
+        cfg.world_size = world_size;
+        let world = world_gen::generate(WorldKind::Maze, 1, &cfg);
+        let num_walls = world.obstructions.len();
+        let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+        for obstruction in world.obstructions {
+            obstructions.push(obstruction);
+        }
+
+        // sensor rays from random poses to points within sensor range
+        let half_size = 0.5 * world_size;
+        let rays: Vec<(Vec2, Vec2)> = (0..QUERIES)
+            .map(|_| {
+                let from = vec2(
+                    rng.gen_range(-half_size, half_size),
+                    rng.gen_range(-half_size, half_size),
+                );
+                let to = from
+                    + Vec2::from_angle(rng.gen_range(0.0, 6.3))
+                        * rng.gen_range(0.0, cfg.sensor_range);
+                (from, to)
+            })
+            .collect();
+
+        let mut brute_force_blocked = 0;
+        time(
+            &format!("{num_walls} walls, {QUERIES} rays (brute force)"),
+            5,
+            || {
+                brute_force_blocked = rays
+                    .iter()
+                    .filter(|(from, to)| {
+                        obstructions
+                            .obstructions()
+                            .iter()
+                            .any(|obstruction| obstruction.blocks(*from, *to))
+                    })
+                    .count();
+            },
+        );
+        let mut grid_blocked = 0;
+        time(
+            &format!("{num_walls} walls, {QUERIES} rays (grid)"),
+            5,
+            || {
+                grid_blocked = rays
+                    .iter()
+                    .filter(|(from, to)| obstructions.blocks(*from, *to))
+                    .count();
+            },
+        );
+        assert_eq!(
+            brute_force_blocked, grid_blocked,
+            "grid and brute force disagree"
+        );
+
+        let resolve = |position: Vec2, candidates: &mut dyn Iterator<Item = &Obstruction>| {
+            candidates.fold(position, |position, obstruction| {
+                obstruction.resolve_collision(position, cfg.robot_radius)
+            })
+        };
+        time(
+            &format!("{num_walls} walls, {QUERIES} collisions (brute force)"),
+            5,
+            || {
+                for (position, _) in &rays {
+                    std::hint::black_box(resolve(
+                        *position,
+                        &mut obstructions.obstructions().iter(),
+                    ));
+                }
+            },
+        );
+        time(
+            &format!("{num_walls} walls, {QUERIES} collisions (grid)"),
+            5,
+            || {
+                for (position, _) in &rays {
+                    let near = obstructions.near(*position, cfg.robot_radius);
+                    let mut candidates = near
+                        .iter()
+                        .map(|&index| &obstructions.obstructions()[index]);
+                    std::hint::black_box(resolve(*position, &mut candidates));
+                }
+            },
+        );
+
+        // what the editor does every frame while dragging a shape; the last
+        // wall is an inner one, the first few span the whole maze
+        time(&format!("{num_walls} walls, move one wall"), 1000, || {
+            obstructions.modify(num_walls - 1, |obstruction| {
+                obstruction.translate(vec2(0.1, 0.0))
+            });
+        });
+    }
+}
+
+/*
+ * a full simulation frame (robot, sensor, EKF-SLAM and FastSLAM) while
+ * driving a circle in each kind of generated world
+ */
+fn worlds() {
+    const FRAMES: u32 = 300;
+
+    let cfg = Config::default();
+    let delta_time = 1.0 / 60.0;
+
+    for kind in [
+        WorldKind::Maze,
+        WorldKind::Office,
+        WorldKind::Field,
+        WorldKind::Manhattan,
+    ] {
+        let world = world_gen::generate(kind, 1, &cfg);
+        println!(
+            "{}: {} obstructions, {} landmarks",
+            kind.name(),
+            world.obstructions.len(),
+            world.landmarks.len()
+        );
+        let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+        for obstruction in world.obstructions {
+            obstructions.push(obstruction);
+        }
+
+        let mut robot = Robot::new();
+        let mut ekf_slam = EkfSlam::new();
+        let mut fast_slam = FastSlam::new(100);
+
+        time(&format!("{} frame", kind.name()), FRAMES, || {
+            robot.command = Vector3::new(80.0, 0.6, 0.0);
+            robot.update(delta_time, &cfg, &obstructions);
+
+            let control = Control::Velocity {
+                command: robot.command,
+                drive: cfg.drive,
+                delta_time,
+            };
+            ekf_slam.predict(&control, &cfg);
+            fast_slam.predict(&control, &cfg);
+
+            let observations = robot.sense(&world.landmarks, &obstructions, &cfg);
+            ekf_slam.update(&observations, &obstructions, &cfg);
+            fast_slam.update(&observations, &obstructions, &cfg);
+        });
+    }
+}
+
+/*
+ * how far the pose estimates stray from the truth while driving circles in
+ * a field whose obstacles and landmarks stay put, wander around, or both
+ */
+fn dynamics() {
+    const FRAMES: usize = 3600;
+    const SPEED: f32 = 80.0;
+    const TURN_RATE: f32 = 0.6;
+    const RADIUS: f32 = SPEED / TURN_RATE;
+
+    let delta_time = 1.0 / 60.0;
+
+    for (label, behavior, move_landmarks) in [
+        ("static world", ObstacleBehavior::Static, false),
+        ("wandering obstacles", ObstacleBehavior::Wander, false),
+        ("moving landmarks", ObstacleBehavior::Static, true),
+        ("both", ObstacleBehavior::Wander, true),
+    ] {
+        let cfg = Config {
+            obstacle_behavior: behavior,
+            move_landmarks,
+            ..Config::default()
+        };
+        let world = world_gen::generate(WorldKind::Field, 1, &cfg);
+        let mut landmarks = world.landmarks;
+        let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+
+        // collisions aren't modeled, so only moving obstacles may get in the way
+        let on_path = |obstruction: &Obstruction| {
+            (0..64).any(|i| {
+                let angle = i as f32 / 64.0 * std::f32::consts::TAU;
+                let point = vec2(0.0, RADIUS) + Vec2::from_angle(angle) * RADIUS;
+                obstruction.is_near(point, 2.0 * cfg.robot_radius)
+            })
+        };
+        for obstruction in world.obstructions.into_iter().filter(|o| !on_path(o)) {
+            let motion = cfg.obstacle_behavior.motion(obstruction.center());
+            obstructions.push_moving(obstruction, motion);
+        }
+
+        let mut robot = Robot::new();
+        let mut ekf_slam = EkfSlam::new();
+        let mut fast_slam = FastSlam::new(100);
+
+        let error = |slam: &dyn Slam, robot: &Robot| {
+            let (x, y, _) = slam.get_state();
+            (x - robot.x).hypot(y - robot.y)
+        };
+        let mut errors = (0.0, 0.0);
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            obstructions.step(delta_time, &cfg);
+            if cfg.move_landmarks {
+                dynamics::move_landmarks(&mut landmarks, delta_time, &cfg);
+            }
+
+            robot.command = Vector3::new(SPEED, TURN_RATE, 0.0);
+            robot.update(delta_time, &cfg, &obstructions);
+
+            let control = Control::Velocity {
+                command: robot.command,
+                drive: cfg.drive,
+                delta_time,
+            };
+            ekf_slam.predict(&control, &cfg);
+            fast_slam.predict(&control, &cfg);
+
+            let observations = robot.sense(&landmarks, &obstructions, &cfg);
+            ekf_slam.update(&observations, &obstructions, &cfg);
+            fast_slam.update(&observations, &obstructions, &cfg);
+
+            errors.0 += error(&ekf_slam, &robot) / FRAMES as f32;
+            errors.1 += error(&fast_slam, &robot) / FRAMES as f32;
+        }
+        let mean = start.elapsed() / FRAMES as u32;
+
+        println!(
+            "{label:<24} mean pose error EKF {:>7.1}, FastSLAM {:>7.1} {mean:>10.2?}/frame",
+            errors.0, errors.1
+        );
+    }
+}
+
+// goals spread over the world that a robot could stand at
+fn free_goals(obstructions: &ObstructionGrid, count: usize, cfg: &Config) -> Vec<Vec2> {
+    let mut rng = Rng::new(3);
+    let half_size = 0.4 * cfg.world_size;
+    let mut goals = Vec::new();
+    while goals.len() < count {
+        let goal = vec2(
+            rng.gen_range(-half_size, half_size),
+            rng.gen_range(-half_size, half_size),
+        );
+        if obstructions.pick(goal, 2.0 * cfg.robot_radius).is_none() {
+            goals.push(goal);
+        }
+    }
+    goals
+}
+
+/*
+ * A* and RRT* from the origin to goals across each kind of generated world;
+ * every returned path is checked against the obstructions
+ */
+fn planning() {
+    const GOALS: usize = 10;
+
+    let cfg = Config::default();
+
+    for kind in [
+        WorldKind::Maze,
+        WorldKind::Office,
+        WorldKind::Field,
+        WorldKind::Manhattan,
+    ] {
+        let world = world_gen::generate(kind, 1, &cfg);
+        let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+        for obstruction in world.obstructions {
+            obstructions.push(obstruction);
+        }
+        let goals = free_goals(&obstructions, GOALS, &cfg);
+
+        for planner_kind in [PlannerKind::AStar, PlannerKind::RrtStar] {
+            let mut lengths = Vec::new();
+            time(
+                &format!("{} {}, {GOALS} goals", kind.name(), planner_kind.name()),
+                1,
+                || {
+                    lengths = goals
+                        .iter()
+                        .filter_map(|&goal| {
+                            planner::plan(planner_kind, &obstructions, Vec2::ZERO, goal, &cfg)
+                        })
+                        .map(|path| {
+                            let mut from = Vec2::ZERO;
+                            path.iter()
+                                .map(|&to| {
+                                    assert!(!obstructions.blocks(from, to), "path crosses a wall");
+                                    std::mem::replace(&mut from, to).distance(to)
+                                })
+                                .sum::<f32>()
+                        })
+                        .collect();
+                },
+            );
+            println!(
+                "{:<48} {:>5}/{GOALS} found, mean length {:.0}",
+                "",
+                lengths.len(),
+                lengths.iter().sum::<f32>() / lengths.len().max(1) as f32
+            );
+        }
+    }
+}
+
+/*
+ * drives to goals in an office along paths planned on each map, steering
+ * from that map's pose, and reports how far from each goal the robot really
+ * ends up and how long it spends up against obstructions
+ */
+fn navigation() {
+    const GOALS: usize = 6;
+    const MAX_FRAMES: usize = 60 * 60;
+
+    let delta_time = 1.0 / 60.0;
+    let cfg = Config {
+        world_obstacle_density: 0.5,
+        ..Config::default()
+    };
+    let world = world_gen::generate(WorldKind::Office, 1, &cfg);
+    let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+    for obstruction in world.obstructions {
+        obstructions.push(obstruction);
+    }
+    let goals = free_goals(&obstructions, GOALS, &cfg);
+
+    for source in [
+        MapSource::GroundTruth,
+        MapSource::Ekf,
+        MapSource::FastSlam,
+        MapSource::DeadReckoning,
+    ] {
+        let mut robot = Robot::new();
+        let mut ekf_slam = EkfSlam::new();
+        let mut fast_slam = FastSlam::new(100);
+        let mut dead_reckoning = DeadReckoning::new();
+        let mut follower = WaypointFollower::new();
+
+        let (mut misses, mut failures, mut contact_frames) = (Vec::new(), 0, 0);
+        for &goal in &goals {
+            let estimator: Option<&dyn Slam> = match source {
+                MapSource::GroundTruth => None,
+                MapSource::Ekf => Some(&ekf_slam),
+                MapSource::FastSlam => Some(&fast_slam),
+                MapSource::DeadReckoning => Some(&dead_reckoning),
+            };
+            let (start, map) = match estimator {
+                None => (vec2(robot.x, robot.y), None),
+                Some(estimator) => {
+                    let (x, y, _) = estimator.get_state();
+                    let mut grid = ObstructionGrid::new(cfg.spatial_cell_size);
+                    for obstruction in planner::estimated_obstructions(
+                        obstructions.obstructions(),
+                        &world.landmarks,
+                        &estimator.get_landmarks(),
+                    ) {
+                        grid.push(obstruction);
+                    }
+                    (vec2(x, y), Some(grid))
+                }
+            };
+            let path = planner::plan(
+                PlannerKind::AStar,
+                map.as_ref().unwrap_or(&obstructions),
+                start,
+                goal,
+                &cfg,
+            );
+            let Some(path) = path else {
+                failures += 1;
+                continue;
+            };
+            follower.waypoints = path;
+            follower.restart(start);
+
+            for _ in 0..MAX_FRAMES {
+                let pose = match source {
+                    MapSource::GroundTruth => (robot.x, robot.y, robot.theta),
+                    MapSource::Ekf => ekf_slam.get_state(),
+                    MapSource::FastSlam => fast_slam.get_state(),
+                    MapSource::DeadReckoning => dead_reckoning.get_state(),
+                };
+                let Some(command) = follower.command(pose, false, &cfg) else {
+                    break;
+                };
+                robot.command = command;
+
+                robot.update(delta_time, &cfg, &obstructions);
+                let position = vec2(robot.x, robot.y);
+                let contact = cfg.robot_radius + 0.5;
+                if obstructions
+                    .near(position, contact)
+                    .into_iter()
+                    .any(|index| obstructions.obstructions()[index].is_near(position, contact))
+                {
+                    contact_frames += 1;
+                }
+
+                let control = Control::Velocity {
+                    command: robot.command,
+                    drive: cfg.drive,
+                    delta_time,
+                };
+                ekf_slam.predict(&control, &cfg);
+                fast_slam.predict(&control, &cfg);
+                dead_reckoning.predict(&control, &cfg);
+
+                let observations = robot.sense(&world.landmarks, &obstructions, &cfg);
+                ekf_slam.update(&observations, &obstructions, &cfg);
+                fast_slam.update(&observations, &obstructions, &cfg);
+            }
+            misses.push(vec2(robot.x, robot.y).distance(goal));
+        }
+
+        println!(
+            "{:<16} mean miss {:>6.1}, worst {:>6.1}, no path {failures}/{GOALS}, {:.1}s in contact",
+            source.name(),
+            misses.iter().sum::<f32>() / misses.len().max(1) as f32,
+            misses.iter().cloned().fold(0.0, f32::max),
+            contact_frames as f32 * delta_time
+        );
+    }
+}
+
+/*
+ * lets the robot explore an office on its own for a few minutes with each
+ * strategy, planning on the true map, and reports how much it saw and how
+ * well the EKF kept up: the pose error over the run and the landmarks' spread
+ * at the end
+ */
+fn exploration() {
+    const MINUTES: usize = 5;
+    const FRAMES_PER_MINUTE: usize = 60 * 60;
+
+    let delta_time = 1.0 / 60.0;
+
+    for strategy in [ExplorationStrategy::Frontier, ExplorationStrategy::Revisit] {
+        let cfg = Config {
+            world_obstacle_density: 0.5,
+            exploration: strategy,
+            follow_mode: FollowMode::Once,
+            ..Config::default()
+        };
+        let world = world_gen::generate(WorldKind::Office, 1, &cfg);
+        let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+        for obstruction in world.obstructions {
+            obstructions.push(obstruction);
+        }
+
+        let mut robot = Robot::new();
+        let mut ekf_slam = EkfSlam::new();
+        let mut follower = WaypointFollower::new();
+        let mut explorer = Explorer::new(&cfg);
+
+        let mut seen = Vec::new(); // area seen at the end of each minute
+        let mut error = 0.0;
+        let start = Instant::now();
+        for frame in 0..MINUTES * FRAMES_PER_MINUTE {
+            let pose = (robot.x, robot.y, robot.theta);
+            explorer.grid.sense(pose, &obstructions, &cfg);
+            explorer.update(
+                vec2(robot.x, robot.y),
+                &ekf_slam.landmark_uncertainties(),
+                &mut follower,
+                |goal| {
+                    let start = vec2(robot.x, robot.y);
+                    planner::plan(cfg.planner, &obstructions, start, goal, &cfg)
+                        .map(|path| (start, path))
+                },
+                delta_time,
+                &cfg,
+            );
+            if let Some(command) = follower.command(pose, false, &cfg) {
+                robot.command = command;
+            }
+
+            robot.update(delta_time, &cfg, &obstructions);
+            let control = Control::Velocity {
+                command: robot.command,
+                drive: cfg.drive,
+                delta_time,
+            };
+            ekf_slam.predict(&control, &cfg);
+            let observations = robot.sense(&world.landmarks, &obstructions, &cfg);
+            ekf_slam.update(&observations, &obstructions, &cfg);
+
+            let (x, y, _) = ekf_slam.get_state();
+            error += (x - robot.x).hypot(y - robot.y);
+            if (frame + 1) % FRAMES_PER_MINUTE == 0 {
+                seen.push(explorer.grid.free_area());
+            }
+        }
+        let frames = MINUTES * FRAMES_PER_MINUTE;
+        let mean = start.elapsed() / frames as u32;
+
+        let spreads = ekf_slam.landmark_uncertainties();
+        println!(
+            "{:<10} {} goals{}, seen per minute (k units²) {}",
+            strategy.name(),
+            explorer.goals,
+            if explorer.finished { ", done" } else { "" },
+            seen.iter()
+                .map(|area| format!("{:.0}", area / 1000.0))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        println!(
+            "{:<10} mean pose error EKF {:.1}, {} landmarks with mean spread {:.1} {mean:>10.2?}/frame",
+            "",
+            error / frames as f32,
+            spreads.len(),
+            spreads.iter().map(|spread| spread.3).sum::<f32>() / spreads.len().max(1) as f32
+        );
+    }
+}
+
+/*
+ * three robots exploring the office on their own, their maps merged; how
+ * many of them the merge has linked up, how far the merged landmarks are
+ * from the truth, and what merging costs
+ */
+fn merging() {
+    const MINUTES: usize = 3;
+    const FRAMES_PER_MINUTE: usize = 60 * 60;
+
+    let delta_time = 1.0 / 60.0;
+
+    for source in [MergeSource::Ekf, MergeSource::FastSlam] {
+        let cfg = Config {
+            world_obstacle_density: 0.5,
+            merge_source: source,
+            planning_map: MapSource::GroundTruth,
+            robot_count: 3,
+            ..Config::default()
+        };
+        let world = world_gen::generate(WorldKind::Office, 1, &cfg);
+        let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+        for obstruction in world.obstructions {
+            obstructions.push(obstruction);
+        }
+        let truth: HashMap<usize, Vec2> = world
+            .landmarks
+            .iter()
+            .map(|landmark| (landmark.id, vec2(landmark.x, landmark.y)))
+            .collect();
+
+        let mut agents: Vec<Agent> = (0..cfg.robot_count)
+            .map(|index| Agent::new(index, &cfg))
+            .collect();
+        for agent in agents.iter_mut() {
+            agent.exploration = ExplorationStrategy::Frontier;
+            agent.follow_mode = FollowMode::Once;
+        }
+        let mut merger = MapMerger::new();
+
+        let mut merging = Duration::ZERO;
+        for frame in 0..MINUTES * FRAMES_PER_MINUTE {
+            for agent in agents.iter_mut() {
+                agent.step(&obstructions, &world.landmarks, &[], delta_time, &cfg);
+            }
+            let positions: Vec<Vec2> = agents
+                .iter()
+                .map(|agent| vec2(agent.robot.x, agent.robot.y))
+                .collect();
+            for (index, agent) in agents.iter_mut().enumerate() {
+                agent.sense_robots(index, &positions, &obstructions, &cfg);
+            }
+            let start = Instant::now();
+            merger.update(source, &agents, &cfg);
+            merging += start.elapsed();
+
+            if (frame + 1) % FRAMES_PER_MINUTE == 0 {
+                let mut errors: Vec<f32> = merger
+                    .landmarks
+                    .iter()
+                    .filter_map(|landmark| {
+                        let mean = vec2(landmark.mean.x, landmark.mean.y);
+                        Some(truth.get(&landmark.id)?.distance(mean))
+                    })
+                    .collect();
+                errors.sort_by(f32::total_cmp);
+                println!(
+                    "{:<9} minute {}: {}/{} robots linked ({} links), {} landmarks ({} shared), error mean {:.1} median {:.1}",
+                    source.name(),
+                    (frame + 1) / FRAMES_PER_MINUTE,
+                    merger.frames.iter().flatten().count(),
+                    agents.len(),
+                    merger.links.len(),
+                    merger.landmarks.len(),
+                    merger
+                        .landmarks
+                        .iter()
+                        .filter(|landmark| landmark.robots > 1)
+                        .count(),
+                    errors.iter().sum::<f32>() / errors.len().max(1) as f32,
+                    errors.get(errors.len() / 2).copied().unwrap_or(0.0)
+                );
+            }
+        }
+        let mean = merging / (MINUTES * FRAMES_PER_MINUTE) as u32;
+        println!("{:<9} merging {mean:>10.2?}/frame", source.name());
+    }
+}
+
+/*
+ * one robot exploring the office and revisiting landmarks, without GPS; the
+ * loop closures each estimator reports and the corrections they caused
+ */
+fn loops() {
+    const MINUTES: usize = 4;
+
+    let delta_time = 1.0 / 60.0;
+    let cfg = Config {
+        world_obstacle_density: 0.5,
+        robot_count: 1,
+        exploration: ExplorationStrategy::Revisit,
+        planning_map: MapSource::GroundTruth,
+        use_gps: false,
+        ..Config::default()
+    };
+    let world = world_gen::generate(WorldKind::Office, 1, &cfg);
+    let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+    for obstruction in world.obstructions {
+        obstructions.push(obstruction);
+    }
+
+    let mut agent = Agent::new(0, &cfg);
+    let mut errors = [0.0; 3];
+    let start = Instant::now();
+    for _ in 0..MINUTES * 60 * 60 {
+        agent.step(&obstructions, &world.landmarks, &[], delta_time, &cfg);
+        let estimators: [&dyn Slam; 3] = [&agent.ekf_slam, &agent.gyro_ekf_slam, &agent.fast_slam];
+        for (error, estimator) in errors.iter_mut().zip(estimators) {
+            let (x, y, _) = agent.to_world(estimator.get_state());
+            *error += (x - agent.robot.x).hypot(y - agent.robot.y) / (MINUTES * 60 * 60) as f32;
+        }
+    }
+    let mean = start.elapsed() / (MINUTES * 60 * 60) as u32;
+
+    for (name, error) in ["EKF", "gyro EKF", "FastSLAM"].into_iter().zip(errors) {
+        let events: Vec<&LoopClosure> = agent
+            .loop_closures
+            .iter()
+            .filter(|logged| logged.estimator == name)
+            .map(|logged| &logged.event)
+            .collect();
+        let count = events.len().max(1) as f32;
+        println!(
+            "{name:<9} {} loop closures, correction mean {:.1} max {:.1}, heading {:.3} rad, after {:.0} units / {:.0} s, mean pose error {error:.1}",
+            events.len(),
+            events.iter().map(|event| event.correction).sum::<f32>() / count,
+            events
+                .iter()
+                .map(|event| event.correction)
+                .fold(0.0, f32::max),
+            events
+                .iter()
+                .map(|event| event.heading_correction)
+                .sum::<f32>()
+                / count,
+            events.iter().map(|event| event.distance).sum::<f32>() / count,
+            events.iter().map(|event| event.elapsed).sum::<f32>() / count,
+        );
+    }
+    println!("{mean:>10.2?}/frame");
+}
+
+// a loop from the spawn point through goals in free space and back
+fn patrol(obstructions: &ObstructionGrid, goals: usize, cfg: &Config) -> Vec<Vec2> {
+    let mut patrol = Vec::new();
+    let mut from = Vec2::ZERO;
+    for goal in free_goals(obstructions, goals, cfg)
+        .into_iter()
+        .chain([Vec2::ZERO])
+    {
+        if let Some(path) = planner::plan(PlannerKind::AStar, obstructions, from, goal, cfg) {
+            patrol.extend(path);
+            from = goal;
+        }
+    }
+    patrol
+}
+
+/*
+ * graph SLAM through a long session, patrolling a loop through an office,
+ * once with no landmarks at all, only walls for the lidar, and once with
+ * them: the graph's pose error against dead reckoning on the same controls,
+ * and whether the incremental solver keeps every frame inside the 60 fps
+ * budget as the graph grows
+ */
+fn pose_graph() {
+    const MINUTES: usize = 10;
+    const GOALS: usize = 5;
+
+    let delta_time = 1.0 / 60.0;
+    let budget = Duration::from_secs_f32(delta_time);
+    let cfg = Config {
+        world_obstacle_density: 0.5,
+        robot_count: 1,
+        exploration: ExplorationStrategy::Off,
+        use_gps: false,
+        ..Config::default()
+    };
+    let world = world_gen::generate(WorldKind::Office, 1, &cfg);
+    let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+    for obstruction in world.obstructions {
+        obstructions.push(obstruction);
+    }
+
+    let patrol = patrol(&obstructions, GOALS, &cfg);
+    for (scene, landmarks) in [("lidar only", &[][..]), ("landmarks", &world.landmarks[..])] {
+        let mut agent = Agent::new(0, &cfg);
+        agent.follower.waypoints = patrol.clone();
+        agent.follower.restart(Vec2::ZERO);
+        agent.follow_mode = FollowMode::Loop;
+
+        let (mut graph_error, mut dead_reckoning_error) = (0.0, 0.0);
+        let (mut slowest, mut over_budget) = (Duration::ZERO, 0);
+        let start = Instant::now();
+        for _ in 0..MINUTES * 60 * 60 {
+            let frame = Instant::now();
+            agent.step(&obstructions, landmarks, &[], delta_time, &cfg);
+            let elapsed = frame.elapsed();
+            slowest = slowest.max(elapsed);
+            over_budget += usize::from(elapsed > budget);
+
+            let error = |estimator: &dyn Slam| {
+                let (x, y, _) = agent.to_world(estimator.get_state());
+                (x - agent.robot.x).hypot(y - agent.robot.y) / (MINUTES * 60 * 60) as f32
+            };
+            graph_error += error(&agent.pose_graph);
+            dead_reckoning_error += error(&agent.dead_reckoning);
+        }
+        let mean = start.elapsed() / (MINUTES * 60 * 60) as u32;
+
+        let graph = &agent.pose_graph;
+        let matches = graph
+            .edges
+            .iter()
+            .filter(|edge| edge.kind == EdgeKind::ScanMatch)
+            .count();
+        let closures = agent
+            .loop_closures
+            .iter()
+            .filter(|logged| logged.estimator == "pose graph")
+            .count();
+        let (nonzeros, fill) = graph.factor_size();
+        println!(
+            "{scene}: {} keyframes, {} landmarks, {matches} scan matches in {closures} loop closures, factor {nonzeros} nonzeros ({fill:.1}x since reordering)",
+            graph.keyframes.len(),
+            graph.get_landmarks().len(),
+        );
+        println!(
+            "  mean pose error: pose graph {graph_error:.1}, dead reckoning {dead_reckoning_error:.1}"
+        );
+        println!(
+            "  {mean:>10.2?}/frame, slowest {slowest:.2?}, {over_budget} frames over {budget:.1?}"
+        );
+    }
+}
+
+/*
+ * the graph patrolling an office while its sensor reports the wrong landmark
+ * id now and then, once with every robust kernel and once more confirming
+ * landmarks as the app does: how far its map and pose are off compared to
+ * the same patrol with no wrong ids, and which sightings it threw out
+ * against which were really wrong
+ */
+fn outliers() {
+    const MINUTES: usize = 4;
+    const GOALS: usize = 5;
+    const WRONG_ID_PROBABILITY: f32 = 0.1;
+
+    let delta_time = 1.0 / 60.0;
+    let cfg = Config {
+        world_obstacle_density: 0.5,
+        robot_count: 1,
+        exploration: ExplorationStrategy::Off,
+        use_gps: false,
+        ..Config::default()
+    };
+    let world = world_gen::generate(WorldKind::Office, 1, &cfg);
+    let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+    for obstruction in world.obstructions {
+        obstructions.push(obstruction);
+    }
+    let truth: HashMap<usize, Vec2> = world
+        .landmarks
+        .iter()
+        .map(|landmark| (landmark.id, vec2(landmark.x, landmark.y)))
+        .collect();
+    let patrol = patrol(&obstructions, GOALS, &cfg);
+
+    // nothing held back until confirmed, so the kernels are all that keep wrong ids out
+    let mut runs = vec![("no wrong ids", RobustKernel::Off, 0.0, false)];
+    let mut kernel = RobustKernel::Off;
+    loop {
+        runs.push((kernel.name(), kernel, WRONG_ID_PROBABILITY, false));
+        kernel = kernel.next();
+        if kernel == RobustKernel::Off {
+            break;
+        }
+    }
+    runs.push(("confirmed", cfg.graph_kernel, WRONG_ID_PROBABILITY, true));
+
+    for (label, kernel, wrong_id_probability, graph_confirm_landmarks) in runs {
+        let cfg = Config {
+            graph_kernel: kernel,
+            graph_confirm_landmarks,
+            wrong_id_probability,
+            ..cfg.clone()
+        };
+        let mut agent = Agent::new(0, &cfg);
+        agent.follower.waypoints = patrol.clone();
+        agent.follower.restart(Vec2::ZERO);
+        agent.follow_mode = FollowMode::Loop;
+
+        let mut pose_error = 0.0;
+        let start = Instant::now();
+        for _ in 0..MINUTES * 60 * 60 {
+            agent.step(&obstructions, &world.landmarks, &[], delta_time, &cfg);
+            let (x, y, _) = agent.to_world(agent.pose_graph.get_state());
+            pose_error += (x - agent.robot.x).hypot(y - agent.robot.y) / (MINUTES * 60 * 60) as f32;
+        }
+        let mean = start.elapsed() / (MINUTES * 60 * 60) as u32;
+
+        // landmarks never seen under their own id can't be anywhere near where they are
+        let graph = &agent.pose_graph;
+        let seen: HashSet<usize> = graph
+            .sightings
+            .iter()
+            .filter(|sighting| sighting.observation.ground_truth == ObservationSource::Landmark)
+            .map(|sighting| sighting.observation.id)
+            .collect();
+        let landmarks = agent.landmarks_to_world(&graph.get_landmarks());
+        let mut errors: Vec<f32> = landmarks
+            .iter()
+            .filter(|(id, _, _)| seen.contains(id))
+            .filter_map(|&(id, x, y)| Some(truth.get(&id)?.distance(vec2(x, y))))
+            .collect();
+        errors.sort_by(f32::total_cmp);
+        let named = landmarks.len() - errors.len();
+
+        // (rejected, all) sightings of each kind, leaving out what's all a named-only landmark has
+        let (mut wrong, mut correct) = ((0, 0), (0, 0));
+        for sighting in &graph.sightings {
+            let counts = match sighting.observation.ground_truth {
+                ObservationSource::WrongId { .. } if seen.contains(&sighting.observation.id) => {
+                    &mut wrong
+                }
+                ObservationSource::WrongId { .. } => continue,
+                ObservationSource::Landmark => &mut correct,
+                ObservationSource::Clutter => continue,
+            };
+            counts.0 += usize::from(sighting.weight < REJECTED_WEIGHT);
+            counts.1 += 1;
+        }
+        println!(
+            "{label:<12} {} landmarks (and {named} only named by wrong ids), error mean {:.1} median {:.1} worst {:.1}, pose error {pose_error:.1}",
+            errors.len(),
+            errors.iter().sum::<f32>() / errors.len().max(1) as f32,
+            errors.get(errors.len() / 2).copied().unwrap_or(0.0),
+            errors.last().copied().unwrap_or(0.0),
+        );
+        println!(
+            "             rejected {}/{} wrong-id sightings, {}/{} correct ones, {mean:.2?}/frame",
+            wrong.0, wrong.1, correct.0, correct.1
+        );
+    }
+}
@@ -1,8 +1,100 @@
+use crate::controller::FollowMode;
+use crate::dynamics::ObstacleBehavior;
+use crate::exploration::ExplorationStrategy;
+use crate::geometry::ShapeKind;
+use crate::kinematics::DriveType;
+use crate::map_merge::MergeSource;
+use crate::planner::{MapSource, PlannerKind};
+use crate::simulation::SensorMode;
+use crate::slam::{MotionModel, ResamplingScheme, RobustKernel};
+use crate::world_gen::WorldKind;
+
+#[derive(Clone)]
 pub struct Config {
     pub linear_acc: f32,
     pub angular_acc: f32,
     pub robot_radius: f32,
 
+    // robots sharing the world
+    pub robot_count: usize,
+    pub robot_spacing: f32,           // apart in a row at spawn
+    pub robot_noise_scales: [f32; 4], // one per robot, cycling
+    pub robot_autopilot: bool,        // all but the first start out exploring
+
+    // how commands drive the robot; ackermann steers its front wheels a
+    // wheelbase ahead of the rear axle, within a maximum angle
+    pub drive: DriveType,
+    pub ackermann_wheelbase: f32,
+    pub max_steering_angle: f32,
+    pub steering_rate: f32,
+
+    // waypoint following by pure pursuit
+    pub follow_mode: FollowMode, // each robot's to start with
+    pub pursuit_lookahead: f32,  // how far ahead along the path it aims
+    pub pursuit_speed: f32,      // cruise speed
+    pub waypoint_tolerance: f32, // distance a waypoint counts as reached within
+
+    // path planning on the true or an estimated map; obstructions are kept
+    // the robot radius plus the clearance away, A* searches a grid of the
+    // given resolution reaching the margin past the start and goal, RRT*
+    // grows this many times in steps of this length, sampling from a
+    // generator seeded the same for every plan so plans can be reproduced
+    pub planner: PlannerKind,
+    pub planning_map: MapSource,
+    pub planner_clearance: f32,
+    pub planner_resolution: f32,
+    pub planner_margin: f32,
+    pub rrt_iterations: usize,
+    pub rrt_step: f32,
+    pub rrt_seed: u64,
+
+    // autonomous exploration
+    pub exploration: ExplorationStrategy, // each robot's to start with, but see robot_autopilot
+    pub exploration_resolution: f32,      // coverage grid cell size
+    pub exploration_stall_time: f32,      // seconds without nearing a goal before giving up on it
+    pub revisit_uncertainty: f32,         // landmark spread worth going back for
+
+    // a mapped landmark closes a loop when it's seen again after the robot
+    // has travelled this far, or this many seconds have passed, since it was
+    // last seen; the renderer flashes each one for a while
+    pub loop_closure_distance: f32,
+    pub loop_closure_time: f32,
+    pub loop_closure_flash_time: f32,
+
+    // whose maps are merged into one, and how many landmarks two maps need in
+    // common before they're lined up by those rather than by robots meeting
+    pub merge_source: MergeSource,
+    pub merge_min_shared: usize,
+
+    // pose graph keyframes and scan matching
+    pub keyframe_distance: f32,      // a keyframe every so far
+    pub keyframe_angle: f32,         // or so much turning
+    pub loop_search_radius: f32,     // loops are looked for among keyframes this near
+    pub loop_min_separation: usize,  // keyframes back a loop has to reach at least
+    pub loop_search_interval: usize, // keyframes between searches
+    pub icp_iterations: usize,
+    pub icp_max_distance: f32, // scan points pair up within this
+    pub icp_max_error: f32,    // rms error a match is accepted at
+    pub icp_min_overlap: f32,  // share of the scan that has to pair up
+    pub scan_match_stdev_position: f32,
+    pub scan_match_stdev_angle: f32,
+
+    // pose graph solving, see PoseGraphSlam
+    pub graph_relinearize_distance: f32, // a variable moving this far is relinearized
+    pub graph_relinearize_angle: f32,    // or turning this much
+    pub graph_fill_limit: f32,           // factor fill-in, times over, that reorders
+    pub graph_rotation_limit: usize,     // work (sparse entries) a keyframe's rotation reorders at
+    pub graph_batch_budget: usize,       // work a frame spends on relinearizing
+
+    // pose graph guards against wrong associations, see RobustKernel
+    pub graph_kernel: RobustKernel,
+    pub graph_kernel_width: f32, // stdevs Huber and Cauchy start discounting at
+    pub graph_dcs_phi: f32,
+    pub graph_switch_stdev: f32,       // of the prior holding a switch on
+    pub graph_landmark_gate: f32,      // sightings this close agree on a landmark
+    pub graph_confirm_landmarks: bool, // landmarks only go in once two keyframes agree
+    pub graph_confirm_keyframes: usize, // how long an unconfirmed sighting waits for another
+
     // speed caps
     pub max_linear_speed: f32,
     pub max_angular_speed: f32,
@@ -11,11 +103,53 @@ pub struct Config {
     pub real_stdev_linear: f32,
     pub real_stdev_angular: f32,
 
+    // wheel encoders on a left and right wheel a wheel base apart, plus an
+    // unpowered lateral wheel; true wheel radius is off from the nominal one
+    // by the bias fraction, slip is a fraction of each wheel's travel
+    pub wheel_base: f32,
+    pub encoder_ticks_per_unit: f32,
+    pub real_wheel_radius_bias_left: f32,
+    pub real_wheel_radius_bias_right: f32,
+    pub real_wheel_slip: f32,
+
+    // imu; gyro white noise (rad/s) and bias random walk (rad/s per sqrt(s)),
+    // accelerometer white noise
+    pub real_stdev_gyro: f32,
+    pub real_gyro_bias_walk: f32,
+    pub use_accelerometer: bool,
+    pub real_stdev_accelerometer: f32,
+
+    // absolute position fixes; rate in Hz, white noise per axis. FastSLAM only
+    // reweights its particles by them, it never moves them toward the fix
+    pub use_gps: bool,
+    pub gps_rate: f32,
+    pub real_stdev_gps: f32,
+
+    // which components the sensor reports
+    pub sensor_mode: SensorMode,
+
     // sensor constants
     pub sensor_range: f32,
-    pub real_stdev_range: f32,
+    pub sensor_min_range: f32,
+    pub sensor_fov: f32, // full opening angle in radians, TAU for a 360 degree sensor
+    pub real_stdev_range: f32, // range noise up close
+    pub real_stdev_range_quadratic: f32, // and its growth per squared range
     pub real_stdev_bearing: f32,
 
+    // lidar, for the pose graph; beams spread over the field of view, their
+    // reach and range noise
+    pub lidar_beams: usize,
+    pub lidar_fov: f32,
+    pub lidar_range: f32,
+    pub real_stdev_lidar: f32,
+
+    // false positives and negatives, all off by default; mean spurious
+    // returns per scan, chance of missing a visible landmark, chance of
+    // reporting the wrong id
+    pub clutter_rate: f32,
+    pub missed_detection_probability: f32,
+    pub wrong_id_probability: f32,
+
     // decay factor (friction) scalings
     pub drag_linear: f32,
     pub drag_angular: f32,
@@ -23,14 +157,49 @@ pub struct Config {
     // landmark size
     pub landmark_radius: f32,
 
-    // obstruction size
+    // size of an obstruction placed with a plain click (circles take the
+    // width as diameter, walls as length), and the shape the editor places
     pub obstruction_width: f32,
     pub obstruction_height: f32,
+    pub obstruction_shape: ShapeKind,
+
+    // moving obstacles; how the editor's next shape moves and how fast,
+    // patrols go back and forth this far, wanderers turn at random (rad per
+    // sqrt(s)) and stay about this close to where they were placed
+    pub obstacle_behavior: ObstacleBehavior,
+    pub obstacle_speed: f32,
+    pub patrol_distance: f32,
+    pub wander_turn_rate: f32,
+    pub wander_range: f32,
+
+    // landmarks that break the static-world assumption; the fraction that
+    // can move, whether they do, and their typical speed
+    pub moving_landmark_fraction: f32,
+    pub move_landmarks: bool,
+    pub landmark_speed: f32,
+
+    // gps outage ("tunnel") size
+    pub outage_width: f32,
+    pub outage_height: f32,
 
     // camera
     pub min_horizontal_units: f32, // number of units horizontally for camera viewport
     pub max_horizontal_units: f32,
 
+    // cell size of the spatial index over the obstructions
+    pub spatial_cell_size: f32,
+
+    // procedural worlds; a square of the given size around the origin, laid
+    // out on cells (corridor or street width), with doors of the given width,
+    // how full of obstacles it is (0 to 1), and how far apart landmarks are
+    pub world_kind: WorldKind,
+    pub world_seed: u64, // seed of the next generated world
+    pub world_size: f32,
+    pub world_cell_size: f32,
+    pub world_door_width: f32,
+    pub world_obstacle_density: f32,
+    pub world_landmark_spacing: f32,
+
     // grid
     pub grid_unit: f32,
 
@@ -38,7 +207,38 @@ pub struct Config {
     pub est_stdev_linear: f32,
     pub est_stdev_angular: f32,
     pub est_stdev_range: f32,
+    pub est_stdev_range_quadratic: f32,
     pub est_stdev_bearing: f32,
+
+    // which motion model the estimators predict with, and the rot1-trans-rot2
+    // noise parameters alpha 1 through 4 from Probabilistic Robotics section 5.4
+    pub motion_model: MotionModel,
+    pub est_odometry_alphas: [f32; 4],
+
+    // belief of the imu noise for the gyro-aided EKF, and of the bias before any
+    // has been learned
+    pub est_stdev_gyro: f32,
+    pub est_gyro_bias_walk: f32,
+    pub est_stdev_gyro_bias: f32,
+    pub est_stdev_accelerometer: f32,
+    pub est_stdev_gps: f32,
+
+    // FastSLAM resampling; resample when ESS drops below threshold * particle count
+    pub resampling_threshold: f32,
+    pub resampling_scheme: ResamplingScheme,
+
+    // landmark lifecycle, see LandmarkExistence
+    pub landmark_confirmations: u32, // sightings (updates that saw it) to confirm
+    pub landmark_log_odds_hit: f32,  // per second seen
+    pub landmark_log_odds_miss: f32, // per second missed
+    pub landmark_removal_probability: f32, // existence below which it's removed
+    pub landmark_tentative_timeout: f32, // seconds unseen before an EKF tentative is dropped
+
+    // bearing-only initialization; EKF waits for this much parallax (radians)
+    // before triangulating, FastSLAM spaces its gaussian sum by this ratio
+    // (within 0.01 to 0.9)
+    pub triangulation_min_parallax: f32,
+    pub gaussian_sum_spread: f32,
 }
 
 impl Config {
@@ -47,25 +247,147 @@ impl Config {
             linear_acc: 96.0,
             angular_acc: 6.0,
             robot_radius: 24.0,
+            robot_count: 1,
+            robot_spacing: 80.0,
+            robot_noise_scales: [1.0, 1.5, 0.7, 2.0],
+            robot_autopilot: false,
+            drive: DriveType::Unicycle,
+            ackermann_wheelbase: 60.0,
+            max_steering_angle: 0.6,
+            steering_rate: 1.5,
+            follow_mode: FollowMode::Off,
+            pursuit_lookahead: 80.0,
+            pursuit_speed: 100.0,
+            waypoint_tolerance: 20.0,
+            planner: PlannerKind::AStar,
+            planning_map: MapSource::GroundTruth,
+            planner_clearance: 2.0,
+            planner_resolution: 10.0,
+            planner_margin: 1000.0,
+            rrt_iterations: 3000,
+            rrt_step: 40.0,
+            rrt_seed: 1,
+            exploration: ExplorationStrategy::Off,
+            exploration_resolution: 20.0,
+            exploration_stall_time: 5.0,
+            revisit_uncertainty: 10.0,
+            loop_closure_distance: 600.0,
+            loop_closure_time: 30.0,
+            loop_closure_flash_time: 1.5,
+            merge_source: MergeSource::Ekf,
+            merge_min_shared: 3,
+            keyframe_distance: 40.0,
+            keyframe_angle: 0.5,
+            loop_search_radius: 100.0,
+            loop_min_separation: 15,
+            loop_search_interval: 5,
+            icp_iterations: 30,
+            icp_max_distance: 10.0,
+            icp_max_error: 4.0,
+            icp_min_overlap: 0.6,
+            scan_match_stdev_position: 2.0,
+            scan_match_stdev_angle: 0.02,
+            graph_relinearize_distance: 20.0,
+            graph_relinearize_angle: 0.05,
+            graph_fill_limit: 3.0,
+            graph_rotation_limit: 500_000,
+            graph_batch_budget: 100_000,
+            graph_kernel: RobustKernel::Dcs,
+            graph_kernel_width: 4.0,
+            graph_dcs_phi: 25.0,
+            graph_switch_stdev: 0.1,
+            graph_landmark_gate: 25.0,
+            graph_confirm_landmarks: true,
+            graph_confirm_keyframes: 10,
             max_linear_speed: 150.0,
             max_angular_speed: 1.5,
             real_stdev_linear: 0.03,
             real_stdev_angular: 0.01,
+            wheel_base: 40.0,
+            encoder_ticks_per_unit: 2.0,
+            real_wheel_radius_bias_left: 0.01,
+            real_wheel_radius_bias_right: -0.005,
+            real_wheel_slip: 0.02,
+            real_stdev_gyro: 0.02,
+            real_gyro_bias_walk: 0.005,
+            use_accelerometer: false,
+            real_stdev_accelerometer: 2.0,
+            use_gps: false,
+            gps_rate: 1.0,
+            real_stdev_gps: 5.0,
+            sensor_mode: SensorMode::RangeBearing,
             sensor_range: 200.0,
+            sensor_min_range: 0.0,
+            sensor_fov: std::f32::consts::TAU,
             real_stdev_range: 5.0,
+            real_stdev_range_quadratic: 2.5e-5,
             real_stdev_bearing: 0.05,
+            lidar_beams: 120,
+            lidar_fov: std::f32::consts::TAU,
+            lidar_range: 400.0,
+            real_stdev_lidar: 1.0,
+            clutter_rate: 0.0,
+            missed_detection_probability: 0.0,
+            wrong_id_probability: 0.0,
             drag_linear: 1.9,
             drag_angular: 4.0,
             landmark_radius: 6.0,
             obstruction_width: 50.0,
             obstruction_height: 50.0,
+            obstruction_shape: ShapeKind::Rectangle,
+            obstacle_behavior: ObstacleBehavior::Static,
+            obstacle_speed: 40.0,
+            patrol_distance: 250.0,
+            wander_turn_rate: 1.5,
+            wander_range: 300.0,
+            moving_landmark_fraction: 0.3,
+            move_landmarks: false,
+            landmark_speed: 8.0,
+            outage_width: 300.0,
+            outage_height: 150.0,
             min_horizontal_units: 500.0,
             max_horizontal_units: 2500.0,
+            spatial_cell_size: 100.0,
+            world_kind: WorldKind::Maze,
+            world_seed: 1,
+            world_size: 2000.0,
+            world_cell_size: 200.0,
+            world_door_width: 90.0,
+            world_obstacle_density: 0.8,
+            world_landmark_spacing: 120.0,
             grid_unit: 50.0,
             est_stdev_linear: 0.03,
             est_stdev_angular: 0.01,
             est_stdev_range: 5.0,
+            est_stdev_range_quadratic: 2.5e-5,
             est_stdev_bearing: 0.05,
+            motion_model: MotionModel::Velocity,
+            est_odometry_alphas: [0.002, 4e-6, 0.003, 0.1],
+            est_stdev_gyro: 0.02,
+            est_gyro_bias_walk: 0.005,
+            est_stdev_gyro_bias: 0.1,
+            est_stdev_accelerometer: 2.0,
+            est_stdev_gps: 5.0,
+            resampling_threshold: 0.5,
+            resampling_scheme: ResamplingScheme::Systematic,
+            landmark_confirmations: 3,
+            landmark_log_odds_hit: 54.0,
+            landmark_log_odds_miss: -24.0,
+            landmark_removal_probability: 0.05,
+            landmark_tentative_timeout: 30.0,
+            triangulation_min_parallax: 0.15,
+            gaussian_sum_spread: 0.25,
         }
     }
+
+    // standard deviation of range returns at the given distance, growing
+    // quadratically as returns weaken
+    pub fn real_stdev_range_at(&self, range: f32) -> f32 {
+        self.real_stdev_range + self.real_stdev_range_quadratic * range * range
+    }
+
+    // the estimators' belief of the same
+    pub fn est_stdev_range_at(&self, range: f32) -> f32 {
+        self.est_stdev_range + self.est_stdev_range_quadratic * range * range
+    }
 }
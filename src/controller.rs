@@ -0,0 +1,207 @@
+use macroquad::prelude::{Vec2, vec2};
+use nalgebra::Vector3;
+
+use crate::config::Config;
+use crate::geometry::closest_point_on_segment;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FollowMode {
+    Off,  // driven by the keys
+    Once, // through the waypoints, then stop
+    Loop, // around the waypoints as a closed loop, forever
+}
+
+impl FollowMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Once,
+            Self::Once => Self::Loop,
+            Self::Loop => Self::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Once => "once",
+            Self::Loop => "loop",
+        }
+    }
+}
+
+/*
+ * pure pursuit along a path of waypoints: each frame the robot steers onto
+ * the arc through the point a lookahead distance further along the path,
+ * turning on the spot first when that point is behind it (if it can).
+ * it steers from whichever pose it's given: the true one gives the same run
+ * whatever the estimators make of it, an estimate drives the way a real
+ * robot would
+ * https://www.ri.cmu.edu/pub_files/pub3/coulter_r_craig_1992_1/coulter_r_craig_1992_1.pdf
+ */
+pub struct WaypointFollower {
+    pub waypoints: Vec<Vec2>,
+    pub target: usize, // waypoint the current leg leads to
+    start: Vec2,       // where the current leg began
+}
+
+impl WaypointFollower {
+    pub fn new() -> Self {
+        Self {
+            waypoints: Vec::new(),
+            target: 0,
+            start: Vec2::ZERO,
+        }
+    }
+
+    /*
+     * waypoints from a text file, one "x y" (or "x, y") per line; blank lines
+     * and lines starting with # are skipped
+     */
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let invalid = |line: usize| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{path}:{line}: expected \"x y\""),
+            )
+        };
+
+        let mut follower = Self::new();
+        for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let coordinates: Vec<f32> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|field| !field.is_empty())
+                .map(|field| field.parse().map_err(|_| invalid(number + 1)))
+                .collect::<Result<_, _>>()?;
+            match coordinates[..] {
+                [x, y] => follower.waypoints.push(vec2(x, y)),
+                _ => return Err(invalid(number + 1)),
+            }
+        }
+        Ok(follower)
+    }
+
+    // starts over from the first waypoint
+    pub fn restart(&mut self, position: Vec2) {
+        self.target = 0;
+        self.start = position;
+    }
+
+    /*
+     * commands for the robot's drive this frame, or None once the last
+     * waypoint is reached (never when looping). a loop through a single
+     * waypoint drives there and holds
+     */
+    pub fn command(
+        &mut self,
+        (x, y, theta): (f32, f32, f32),
+        looping: bool,
+        cfg: &Config,
+    ) -> Option<Vector3<f32>> {
+        let position = vec2(x, y);
+        let closest = self.advance(position, looping, cfg)?;
+        let final_leg = self.on_final_leg(looping);
+        if looping && final_leg && position.distance(self.waypoints[0]) <= cfg.waypoint_tolerance {
+            return Some(Vector3::zeros());
+        }
+
+        /*
+         * the goal is where the leg, followed on from the closest point, leaves
+         * the lookahead circle around the robot, or the waypoint once that's
+         * inside it. not looking past the waypoint keeps the robot from cutting
+         * corners a path was planned around
+         */
+        let end = self.waypoints[self.target];
+        let goal = if end.distance(position) >= cfg.pursuit_lookahead {
+            closest + (end - closest) * exit_fraction(closest, end, position, cfg.pursuit_lookahead)
+        } else {
+            end
+        };
+
+        // the goal in the robot's frame
+        let offset = goal - position;
+        let distance = offset.length().max(f32::EPSILON);
+        let local = Vec2::from_angle(-theta).rotate(offset);
+
+        // behind the robot, turn on the spot if the drive can, otherwise on a half circle
+        let kinematics = cfg.drive.model();
+        let curvature = if local.x >= 0.0 {
+            2.0 * local.y / distance.powi(2)
+        } else {
+            let spin = kinematics.command(0.0, cfg.max_angular_speed * local.y.signum(), cfg);
+            if kinematics.body_velocity(spin, cfg)[2] != 0.0 {
+                return Some(spin);
+            }
+            2.0 * local.y.signum() / distance
+        };
+
+        // ease into the last waypoint, and slow down where the turn rate can't keep up
+        let mut speed = cfg.pursuit_speed.min(cfg.max_linear_speed);
+        if final_leg {
+            speed *= (distance / cfg.pursuit_lookahead).min(1.0);
+        }
+        speed = speed.min(cfg.max_angular_speed / curvature.abs().max(f32::EPSILON));
+
+        Some(kinematics.command(speed, speed * curvature, cfg))
+    }
+
+    /*
+     * moves on to the next leg once the robot reaches the waypoint the current
+     * one leads to, or is closer to the next leg (cutting the corner), and
+     * returns the closest point on the current leg
+     */
+    fn advance(&mut self, position: Vec2, looping: bool, cfg: &Config) -> Option<Vec2> {
+        let count = self.waypoints.len();
+        for _ in 0..count {
+            let end = *self.waypoints.get(self.target)?;
+            let closest = closest_point_on_segment(position, self.start, end);
+
+            let reached = position.distance(end) <= cfg.waypoint_tolerance;
+            if self.on_final_leg(looping) {
+                let leg = end - self.start;
+                let passed = (position - self.start).dot(leg) >= leg.length_squared();
+                if !looping && (reached || passed) {
+                    self.target = count;
+                    return None;
+                }
+                return Some(closest);
+            }
+
+            let after = self.waypoints[(self.target + 1) % count];
+            let closer_to_next = closest_point_on_segment(position, end, after).distance(position)
+                < closest.distance(position);
+            if !reached && !closer_to_next {
+                return Some(closest);
+            }
+
+            self.start = end;
+            self.target = (self.target + 1) % count;
+        }
+        None
+    }
+
+    // heading for the last waypoint, which a loop only has when it's the only one
+    fn on_final_leg(&self, looping: bool) -> bool {
+        self.target + 1 == self.waypoints.len() && (!looping || self.waypoints.len() == 1)
+    }
+}
+
+/*
+ * how far along from `from` to `to` the segment leaves the circle, with `to`
+ * outside it; if the segment misses the circle, its point closest to the center
+ */
+fn exit_fraction(from: Vec2, to: Vec2, center: Vec2, radius: f32) -> f32 {
+    let direction = to - from;
+    let offset = from - center;
+    let a = direction.length_squared();
+    let b = offset.dot(direction);
+    let c = offset.length_squared() - radius * radius;
+    if a == 0.0 {
+        return 0.0;
+    }
+    ((-b + (b * b - a * c).max(0.0).sqrt()) / a).clamp(0.0, 1.0)
+}
@@ -0,0 +1,102 @@
+use macroquad::prelude::{Vec2, rand};
+
+use crate::config::Config;
+use crate::simulation::Landmark;
+use crate::utils::sample_normal;
+
+// how the editor's next obstruction moves
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ObstacleBehavior {
+    Static,
+    Patrol, // back and forth along a line
+    Wander, // random walk around where it was placed
+}
+
+impl ObstacleBehavior {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Static => Self::Patrol,
+            Self::Patrol => Self::Wander,
+            Self::Wander => Self::Static,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Static => "static",
+            Self::Patrol => "patrol",
+            Self::Wander => "wander",
+        }
+    }
+
+    // motion for a shape placed at the given point, if it moves at all
+    pub fn motion(self, origin: Vec2) -> Option<ObstacleMotion> {
+        match self {
+            Self::Static => None,
+            Self::Patrol => Some(ObstacleMotion::Patrol {
+                direction: Vec2::from_angle(rand::gen_range(0.0, std::f32::consts::PI)),
+                travelled: 0.0,
+            }),
+            Self::Wander => Some(ObstacleMotion::Wander {
+                origin,
+                heading: rand::gen_range(0.0, std::f32::consts::TAU),
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ObstacleMotion {
+    // travelled runs from 0 to twice the patrol distance, out and back
+    Patrol { direction: Vec2, travelled: f32 },
+    Wander { origin: Vec2, heading: f32 },
+}
+
+impl ObstacleMotion {
+    // how far the obstacle at the given center moves this frame
+    pub fn step(&mut self, center: Vec2, delta_time: f32, cfg: &Config) -> Vec2 {
+        let distance = cfg.obstacle_speed * delta_time;
+
+        match self {
+            Self::Patrol {
+                direction,
+                travelled,
+            } => {
+                let outbound = *travelled < cfg.patrol_distance;
+                *travelled = (*travelled + distance) % (2.0 * cfg.patrol_distance);
+                if outbound {
+                    *direction * distance
+                } else {
+                    -*direction * distance
+                }
+            }
+            Self::Wander { origin, heading } => {
+                *heading += sample_normal(0.0, cfg.wander_turn_rate * delta_time.sqrt());
+
+                // past the range, steer back towards where it was placed
+                let home = *origin - center;
+                if home.length() > cfg.wander_range {
+                    *heading = home.to_angle();
+                }
+                Vec2::from_angle(*heading) * distance
+            }
+        }
+    }
+}
+
+/*
+ * moving landmarks follow an Ornstein-Uhlenbeck velocity, so they wander at
+ * about the configured speed without running off
+ */
+pub fn move_landmarks(landmarks: &mut [Landmark], delta_time: f32, cfg: &Config) {
+    const REVERSION_RATE: f32 = 0.5; // 1/s, how quickly velocity forgets its past
+
+    let stdev = cfg.landmark_speed * (2.0 * REVERSION_RATE * delta_time).sqrt();
+    for landmark in landmarks.iter_mut().filter(|landmark| landmark.moving) {
+        let decay = 1.0 - REVERSION_RATE * delta_time;
+        landmark.velocity.0 = decay * landmark.velocity.0 + sample_normal(0.0, stdev);
+        landmark.velocity.1 = decay * landmark.velocity.1 + sample_normal(0.0, stdev);
+        landmark.x += landmark.velocity.0 * delta_time;
+        landmark.y += landmark.velocity.1 * delta_time;
+    }
+}
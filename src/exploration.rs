@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use macroquad::prelude::{Vec2, vec2};
+
+use crate::config::Config;
+use crate::controller::WaypointFollower;
+use crate::spatial_index::ObstructionGrid;
+
+type Cell = (i32, i32);
+
+// frontiers planned to in a frame before waiting for the next
+const PLAN_ATTEMPTS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExplorationStrategy {
+    Off,      // goals come from the user
+    Frontier, // nearest edge of the explored area
+    Revisit,  // frontiers, detouring to the least certain landmark in between
+}
+
+impl ExplorationStrategy {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Frontier,
+            Self::Frontier => Self::Revisit,
+            Self::Revisit => Self::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Frontier => "frontier",
+            Self::Revisit => "revisit",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Occupancy {
+    Free,
+    Occupied,
+}
+
+/*
+ * coarse occupancy grid of what the sensor has swept; cells never seen are
+ * unknown. it's built from the true pose and serves as a record of coverage
+ * rather than a map to plan on (see planner::estimated_obstructions for that)
+ */
+pub struct OccupancyGrid {
+    cells: HashMap<Cell, Occupancy>,
+    resolution: f32,
+}
+
+impl OccupancyGrid {
+    pub fn new(resolution: f32) -> Self {
+        Self {
+            cells: HashMap::new(),
+            resolution,
+        }
+    }
+
+    pub fn resolution(&self) -> f32 {
+        self.resolution
+    }
+
+    // lower corner, state and whether it's on the frontier, for every known cell
+    pub fn cells(&self) -> impl Iterator<Item = (Vec2, Occupancy, bool)> + '_ {
+        self.cells.iter().map(|(&cell, &occupancy)| {
+            let corner = vec2(cell.0 as f32, cell.1 as f32) * self.resolution;
+            (corner, occupancy, self.is_frontier(cell))
+        })
+    }
+
+    pub fn free_area(&self) -> f32 {
+        let free = self
+            .cells
+            .values()
+            .filter(|&&occupancy| occupancy == Occupancy::Free)
+            .count();
+        free as f32 * self.resolution * self.resolution
+    }
+
+    /*
+     * marks the cells in the sensor's footprint. a cell is free when the
+     * sensor sees its center and no obstruction touches it, occupied when it
+     * sees the side of the obstruction in it that faces the robot. cells are
+     * looked at again every time, so moving obstacles clear up behind them
+     */
+    pub fn sense(
+        &mut self,
+        (x, y, theta): (f32, f32, f32),
+        obstructions: &ObstructionGrid,
+        cfg: &Config,
+    ) {
+        let position = vec2(x, y);
+        let half_diagonal = self.resolution * std::f32::consts::FRAC_1_SQRT_2;
+        let reach = (cfg.sensor_range / self.resolution).ceil() as i32;
+        let origin = self.cell(position);
+
+        for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                let cell = (origin.0 + dx, origin.1 + dy);
+                let center = self.center(cell);
+                let local = Vec2::from_angle(-theta).rotate(center - position);
+                let range = local.length();
+                if range >= cfg.sensor_range || range < cfg.sensor_min_range {
+                    continue;
+                }
+                if range > half_diagonal && local.y.atan2(local.x).abs() > 0.5 * cfg.sensor_fov {
+                    continue;
+                }
+
+                let touching =
+                    obstructions
+                        .near(center, half_diagonal)
+                        .into_iter()
+                        .find(|&index| {
+                            obstructions.obstructions()[index].is_near(center, half_diagonal)
+                        });
+                let seen = match touching {
+                    None => (!obstructions.blocks(position, center)).then_some(Occupancy::Free),
+                    Some(index) => {
+                        let surface = obstructions.obstructions()[index].closest_point(center);
+                        let facing = surface + (position - surface).clamp_length_max(1.0);
+                        (!obstructions.blocks(position, facing)).then_some(Occupancy::Occupied)
+                    }
+                };
+                if let Some(occupancy) = seen {
+                    self.cells.insert(cell, occupancy);
+                }
+            }
+        }
+    }
+
+    // a free cell next to an unknown one
+    pub fn is_frontier(&self, cell: Cell) -> bool {
+        self.cells.get(&cell) == Some(&Occupancy::Free)
+            && [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                .iter()
+                .any(|(dx, dy)| !self.cells.contains_key(&(cell.0 + dx, cell.1 + dy)))
+    }
+
+    // centers of the frontier cells reachable through free ones, nearest first
+    pub fn frontiers(&self, from: Vec2) -> Vec<Vec2> {
+        let start = self.cell(from);
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        let mut frontiers = Vec::new();
+
+        while let Some(cell) = queue.pop_front() {
+            if self.is_frontier(cell) {
+                frontiers.push(self.center(cell));
+            }
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let neighbour = (cell.0 + dx, cell.1 + dy);
+                    if self.cells.get(&neighbour) == Some(&Occupancy::Free)
+                        && visited.insert(neighbour)
+                    {
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+        frontiers
+    }
+
+    fn cell(&self, point: Vec2) -> Cell {
+        (
+            (point.x / self.resolution).floor() as i32,
+            (point.y / self.resolution).floor() as i32,
+        )
+    }
+
+    fn center(&self, (x, y): Cell) -> Vec2 {
+        (vec2(x as f32, y as f32) + Vec2::splat(0.5)) * self.resolution
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExplorationGoal {
+    Frontier(Vec2),
+    Landmark(usize, Vec2), // a mapped landmark to close the loop on, where the EKF has it
+}
+
+impl ExplorationGoal {
+    pub fn point(self) -> Vec2 {
+        match self {
+            Self::Frontier(point) | Self::Landmark(_, point) => point,
+        }
+    }
+}
+
+/*
+ * active SLAM: picks goals for the waypoint follower on its own, so long
+ * runs need no driver. frontier exploration heads for the nearest edge of
+ * the explored area (Yamauchi); revisiting goes back to the landmark the EKF
+ * is least sure of whenever one passes a threshold, one between every two
+ * frontiers, so that re-observing it closes the loop
+ * https://doi.org/10.1109/CIRA.1997.613851
+ */
+pub struct Explorer {
+    pub grid: OccupancyGrid,
+    pub goal: Option<ExplorationGoal>,
+    pub finished: bool, // no frontier left that could be reached
+    pub goals: usize,   // goals set so far
+    tried: Vec<Vec2>,   // goals reached or given up on, not picked again
+    best_distance: f32, // closest the robot has come to the goal
+    stalled: f32,       // seconds since it last got closer
+    revisited: bool,    // whether the last goal was a landmark
+}
+
+impl Explorer {
+    pub fn new(cfg: &Config) -> Self {
+        Self {
+            grid: OccupancyGrid::new(cfg.exploration_resolution),
+            goal: None,
+            finished: false,
+            goals: 0,
+            tried: Vec::new(),
+            best_distance: f32::INFINITY,
+            stalled: 0.0,
+            revisited: false,
+        }
+    }
+
+    /*
+     * moves on from the goal once the follower reaches it, it's no longer on
+     * the frontier, or the robot stops getting closer, and picks a new one.
+     * landmarks are the mapped ones with their spread (see
+     * EkfSlam::landmark_uncertainties), `plan` gives the start and path to a
+     * goal on the planning map, if any
+     */
+    pub fn update(
+        &mut self,
+        position: Vec2,
+        landmarks: &[(usize, f32, f32, f32)],
+        follower: &mut WaypointFollower,
+        mut plan: impl FnMut(Vec2) -> Option<(Vec2, Vec<Vec2>)>,
+        delta_time: f32,
+        cfg: &Config,
+    ) {
+        if let Some(goal) = self.goal {
+            let distance = position.distance(goal.point());
+            if distance < self.best_distance - cfg.waypoint_tolerance {
+                self.best_distance = distance;
+                self.stalled = 0.0;
+            } else {
+                self.stalled += delta_time;
+            }
+
+            let reached = follower.target >= follower.waypoints.len();
+            let explored = match goal {
+                ExplorationGoal::Frontier(point) => !self.grid.is_frontier(self.grid.cell(point)),
+                ExplorationGoal::Landmark(..) => false,
+            };
+            if reached || self.stalled > cfg.exploration_stall_time {
+                self.tried.push(goal.point());
+            }
+            if reached || explored || self.stalled > cfg.exploration_stall_time {
+                self.goal = None;
+            }
+        }
+
+        if self.goal.is_none() {
+            self.goal = self.choose(position, landmarks, &mut plan, follower, cfg);
+            self.goals += self.goal.is_some() as usize;
+            self.best_distance = f32::INFINITY;
+            self.stalled = 0.0;
+        }
+    }
+
+    fn choose(
+        &mut self,
+        position: Vec2,
+        landmarks: &[(usize, f32, f32, f32)],
+        plan: &mut impl FnMut(Vec2) -> Option<(Vec2, Vec<Vec2>)>,
+        follower: &mut WaypointFollower,
+        cfg: &Config,
+    ) -> Option<ExplorationGoal> {
+        let mut follow = |(start, path): (Vec2, Vec<Vec2>)| {
+            follower.waypoints = path;
+            follower.restart(start);
+        };
+
+        if cfg.exploration == ExplorationStrategy::Revisit && !self.revisited {
+            let uncertain = landmarks
+                .iter()
+                .copied()
+                .filter(|&(_, x, y, spread)| {
+                    spread > cfg.revisit_uncertainty && !self.was_tried(vec2(x, y))
+                })
+                .max_by(|a, b| a.3.total_cmp(&b.3));
+            if let Some((id, x, y, _)) = uncertain {
+                let point = vec2(x, y);
+                match plan(point) {
+                    Some(path) => {
+                        follow(path);
+                        self.revisited = true;
+                        return Some(ExplorationGoal::Landmark(id, point));
+                    }
+                    None => self.tried.push(point),
+                }
+            }
+        }
+        self.revisited = false;
+
+        let frontiers: Vec<Vec2> = self
+            .grid
+            .frontiers(position)
+            .into_iter()
+            .filter(|&frontier| !self.was_tried(frontier))
+            .collect();
+        self.finished = frontiers.is_empty();
+
+        for frontier in frontiers.into_iter().take(PLAN_ATTEMPTS) {
+            match plan(frontier) {
+                Some(path) => {
+                    follow(path);
+                    return Some(ExplorationGoal::Frontier(frontier));
+                }
+                None => self.tried.push(frontier),
+            }
+        }
+        None
+    }
+
+    fn was_tried(&self, point: Vec2) -> bool {
+        let radius = 2.0 * self.grid.resolution;
+        self.tried
+            .iter()
+            .any(|tried| tried.distance(point) < radius)
+    }
+}
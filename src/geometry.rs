@@ -0,0 +1,429 @@
+use macroquad::prelude::{Vec2, vec2};
+
+// shape the editor places next
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShapeKind {
+    Rectangle,
+    Circle,
+    Wall,    // line segment
+    Polygon, // clicked out vertex by vertex
+}
+
+impl ShapeKind {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Rectangle => Self::Circle,
+            Self::Circle => Self::Wall,
+            Self::Wall => Self::Polygon,
+            Self::Polygon => Self::Rectangle,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rectangle => "rectangle",
+            Self::Circle => "circle",
+            Self::Wall => "wall",
+            Self::Polygon => "polygon",
+        }
+    }
+}
+
+/*
+ * something the robot can't drive through and the sensor can't see through.
+ * polygons may be concave, walls have no thickness
+ */
+#[derive(Clone, Debug)]
+pub enum Obstruction {
+    Polygon(Vec<Vec2>), // vertices in order, either winding
+    Circle { center: Vec2, radius: f32 },
+    Wall { start: Vec2, end: Vec2 },
+}
+
+impl Obstruction {
+    // axis-aligned rectangle between two opposite corners
+    pub fn rectangle(corner: Vec2, opposite: Vec2) -> Self {
+        let (min, max) = (corner.min(opposite), corner.max(opposite));
+        Self::Polygon(vec![min, vec2(max.x, min.y), max, vec2(min.x, max.y)])
+    }
+
+    // the point shapes rotate about; vertex mean for polygons
+    pub fn center(&self) -> Vec2 {
+        match self {
+            Self::Polygon(vertices) => {
+                vertices.iter().copied().sum::<Vec2>() / vertices.len() as f32
+            }
+            Self::Circle { center, .. } => *center,
+            Self::Wall { start, end } => 0.5 * (*start + *end),
+        }
+    }
+
+    // even-odd rule, so concave polygons work; walls contain nothing
+    pub fn contains(&self, point: Vec2) -> bool {
+        match self {
+            Self::Polygon(_) => {
+                let mut inside = false;
+                for (a, b) in self.edges() {
+                    if (a.y > point.y) != (b.y > point.y)
+                        && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                    {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+            Self::Circle { center, radius } => center.distance_squared(point) < radius * radius,
+            Self::Wall { .. } => false,
+        }
+    }
+
+    // closest point on the boundary
+    pub fn closest_point(&self, point: Vec2) -> Vec2 {
+        match self {
+            Self::Polygon(_) => self
+                .edges()
+                .map(|(a, b)| closest_point_on_segment(point, a, b))
+                .min_by(|p, q| {
+                    p.distance_squared(point)
+                        .total_cmp(&q.distance_squared(point))
+                })
+                .unwrap_or(point),
+            Self::Circle { center, radius } => {
+                let offset = point - *center;
+                if offset.length_squared() > 0.0 {
+                    *center + offset.normalize() * *radius
+                } else {
+                    *center + vec2(*radius, 0.0)
+                }
+            }
+            Self::Wall { start, end } => closest_point_on_segment(point, *start, *end),
+        }
+    }
+
+    // whether a click at the point picks this shape
+    pub fn is_near(&self, point: Vec2, tolerance: f32) -> bool {
+        self.contains(point) || self.closest_point(point).distance(point) < tolerance
+    }
+
+    /*
+     * where a disc of the given radius centered at the point ends up after
+     * being pushed out of the shape; unchanged if they don't overlap
+     */
+    pub fn resolve_collision(&self, point: Vec2, radius: f32) -> Vec2 {
+        let closest = self.closest_point(point);
+        let offset = point - closest;
+        let distance = offset.length();
+
+        if self.contains(point) {
+            // center is inside, so leave through the nearest edge
+            if distance > 0.0 {
+                closest - offset / distance * radius
+            } else {
+                point
+            }
+        } else if distance < radius && distance > 0.0 {
+            closest + offset / distance * radius
+        } else {
+            point
+        }
+    }
+
+    // whether the shape cuts the line of sight between two points
+    pub fn blocks(&self, from: Vec2, to: Vec2) -> bool {
+        match self {
+            Self::Polygon(_) => {
+                self.contains(from)
+                    || self.contains(to)
+                    || self
+                        .edges()
+                        .any(|(a, b)| segments_intersect(from, to, a, b))
+            }
+            Self::Circle { center, radius } => {
+                closest_point_on_segment(*center, from, to).distance_squared(*center)
+                    < radius * radius
+            }
+            Self::Wall { start, end } => segments_intersect(from, to, *start, *end),
+        }
+    }
+
+    /*
+     * how far along the segment from one point to the other it first meets
+     * the shape, as a fraction of its length; 0 if it starts inside
+     */
+    pub fn ray_hit(&self, from: Vec2, to: Vec2) -> Option<f32> {
+        match self {
+            Self::Polygon(_) if self.contains(from) => Some(0.0),
+            Self::Circle { center, radius } => {
+                // smaller root of |from + t d - center|² = r²
+                let direction = to - from;
+                let offset = from - *center;
+                let a = direction.length_squared();
+                let b = offset.dot(direction);
+                let c = offset.length_squared() - radius * radius;
+                if c <= 0.0 {
+                    return Some(0.0);
+                }
+                let discriminant = b * b - a * c;
+                if a == 0.0 || discriminant < 0.0 {
+                    return None;
+                }
+                let t = (-b - discriminant.sqrt()) / a;
+                (0.0..=1.0).contains(&t).then_some(t)
+            }
+            _ => self
+                .edges()
+                .filter_map(|(a, b)| segment_intersection(from, to, a, b))
+                .min_by(f32::total_cmp),
+        }
+    }
+
+    pub fn translate(&mut self, offset: Vec2) {
+        match self {
+            Self::Polygon(vertices) => vertices.iter_mut().for_each(|vertex| *vertex += offset),
+            Self::Circle { center, .. } => *center += offset,
+            Self::Wall { start, end } => {
+                *start += offset;
+                *end += offset;
+            }
+        }
+    }
+
+    // counterclockwise about the center, in radians
+    pub fn rotate(&mut self, angle: f32) {
+        let pivot = self.center();
+        let rotation = Vec2::from_angle(angle);
+        let turn = |point: &mut Vec2| *point = pivot + rotation.rotate(*point - pivot);
+
+        match self {
+            Self::Polygon(vertices) => vertices.iter_mut().for_each(turn),
+            Self::Circle { .. } => {}
+            Self::Wall { start, end } => {
+                turn(start);
+                turn(end);
+            }
+        }
+    }
+
+    // consecutive vertex pairs around a polygon, or the wall itself
+    pub fn edges(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        let vertices: &[Vec2] = match self {
+            Self::Polygon(vertices) => vertices,
+            _ => &[],
+        };
+        let wall = match self {
+            Self::Wall { start, end } => Some((*start, *end)),
+            _ => None,
+        };
+
+        (0..vertices.len())
+            .map(|i| (vertices[i], vertices[(i + 1) % vertices.len()]))
+            .chain(wall)
+    }
+
+    /*
+     * ear clipping, so concave polygons can be filled with triangles. a
+     * self-intersecting outline has no ears left at some point, and the rest
+     * is fanned out
+     */
+    pub fn triangles(&self) -> Vec<[Vec2; 3]> {
+        let Self::Polygon(vertices) = self else {
+            return Vec::new();
+        };
+
+        // counterclockwise order makes convex corners turn left
+        let mut remaining: Vec<Vec2> = vertices.clone();
+        if signed_area(&remaining) < 0.0 {
+            remaining.reverse();
+        }
+
+        let mut triangles = Vec::with_capacity(remaining.len().saturating_sub(2));
+        while remaining.len() > 3 {
+            let n = remaining.len();
+            let ear = (0..n).find(|&i| {
+                let (a, b, c) = (
+                    remaining[(i + n - 1) % n],
+                    remaining[i],
+                    remaining[(i + 1) % n],
+                );
+                cross(b - a, c - b) > 0.0
+                    && remaining
+                        .iter()
+                        .all(|&p| p == a || p == b || p == c || !in_triangle(p, a, b, c))
+            });
+
+            let Some(i) = ear else {
+                break;
+            };
+            triangles.push([
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            ]);
+            remaining.remove(i);
+        }
+
+        for i in 1..remaining.len().saturating_sub(1) {
+            triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+        }
+
+        triangles
+    }
+}
+
+// rotation about the origin, then a translation
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RigidTransform {
+    pub angle: f32,
+    pub translation: Vec2,
+}
+
+impl RigidTransform {
+    pub const IDENTITY: Self = Self {
+        angle: 0.0,
+        translation: Vec2::ZERO,
+    };
+
+    /*
+     * least squares fit taking the first point of each pair onto the second
+     * (2D Procrustes); None with fewer than two pairs
+     */
+    pub fn fit(pairs: &[(Vec2, Vec2)]) -> Option<Self> {
+        if pairs.len() < 2 {
+            return None;
+        }
+
+        let count = pairs.len() as f32;
+        let from_centroid = pairs.iter().map(|pair| pair.0).sum::<Vec2>() / count;
+        let to_centroid = pairs.iter().map(|pair| pair.1).sum::<Vec2>() / count;
+        let (dot, cross) = pairs.iter().fold((0.0, 0.0), |(dot, cross), &(from, to)| {
+            let (a, b) = (from - from_centroid, to - to_centroid);
+            (dot + a.dot(b), cross + a.perp_dot(b))
+        });
+        let angle = cross.atan2(dot);
+        Some(Self {
+            angle,
+            translation: to_centroid - Vec2::from_angle(angle).rotate(from_centroid),
+        })
+    }
+
+    pub fn apply(&self, point: Vec2) -> Vec2 {
+        Vec2::from_angle(self.angle).rotate(point) + self.translation
+    }
+
+    // this transform, then the other
+    pub fn then(&self, other: &Self) -> Self {
+        Self {
+            angle: self.angle + other.angle,
+            translation: other.apply(self.translation),
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            angle: -self.angle,
+            translation: -Vec2::from_angle(-self.angle).rotate(self.translation),
+        }
+    }
+}
+
+pub fn closest_point_on_segment(point: Vec2, start: Vec2, end: Vec2) -> Vec2 {
+    let direction = end - start;
+    let length_sq = direction.length_squared();
+    if length_sq == 0.0 {
+        return start;
+    }
+
+    let t = ((point - start).dot(direction) / length_sq).clamp(0.0, 1.0);
+    start + t * direction
+}
+
+// proper crossings and touching endpoints both count
+pub fn segments_intersect(p1: Vec2, p2: Vec2, q1: Vec2, q2: Vec2) -> bool {
+    let d1 = cross(q2 - q1, p1 - q1);
+    let d2 = cross(q2 - q1, p2 - q1);
+    let d3 = cross(p2 - p1, q1 - p1);
+    let d4 = cross(p2 - p1, q2 - p1);
+
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+    {
+        return true;
+    }
+
+    // collinear or touching; the point lies on the other segment
+    let on_segment = |point: Vec2, start: Vec2, end: Vec2| {
+        point.cmpge(start.min(end)).all() && point.cmple(start.max(end)).all()
+    };
+    (d1 == 0.0 && on_segment(p1, q1, q2))
+        || (d2 == 0.0 && on_segment(p2, q1, q2))
+        || (d3 == 0.0 && on_segment(q1, p1, p2))
+        || (d4 == 0.0 && on_segment(q2, p1, p2))
+}
+
+// fraction of the way along the first segment where it crosses the second, if it does
+pub fn segment_intersection(p1: Vec2, p2: Vec2, q1: Vec2, q2: Vec2) -> Option<f32> {
+    let (r, s) = (p2 - p1, q2 - q1);
+    let denominator = cross(r, s);
+    if denominator == 0.0 {
+        return None; // parallel; grazing along an edge doesn't count as a hit
+    }
+    let t = cross(q1 - p1, s) / denominator;
+    let u = cross(q1 - p1, r) / denominator;
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some(t)
+}
+
+fn cross(a: Vec2, b: Vec2) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+// shoelace formula; positive for counterclockwise vertices
+fn signed_area(vertices: &[Vec2]) -> f32 {
+    0.5 * (0..vertices.len())
+        .map(|i| cross(vertices[i], vertices[(i + 1) % vertices.len()]))
+        .sum::<f32>()
+}
+
+// inclusive of the edges, for a counterclockwise triangle
+fn in_triangle(point: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    cross(b - a, point - a) >= 0.0
+        && cross(c - b, point - b) >= 0.0
+        && cross(a - c, point - c) >= 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_recovers_a_transform() {
+        let transform = RigidTransform {
+            angle: 2.5,
+            translation: vec2(-40.0, 15.0),
+        };
+        let points = [vec2(0.0, 0.0), vec2(100.0, 20.0), vec2(-30.0, 60.0)];
+        let pairs: Vec<(Vec2, Vec2)> = points
+            .iter()
+            .map(|&point| (point, transform.apply(point)))
+            .collect();
+
+        let fitted = RigidTransform::fit(&pairs).unwrap();
+        assert!((fitted.angle - transform.angle).abs() < 1e-4);
+        assert!(fitted.translation.distance(transform.translation) < 1e-3);
+        assert!(RigidTransform::fit(&pairs[..1]).is_none());
+    }
+
+    #[test]
+    fn inverse_and_then_undo_each_other() {
+        let a = RigidTransform {
+            angle: 0.7,
+            translation: vec2(10.0, -5.0),
+        };
+        let b = RigidTransform {
+            angle: -2.0,
+            translation: vec2(3.0, 8.0),
+        };
+        let point = vec2(12.0, 34.0);
+        assert!(a.then(&b).apply(point).distance(b.apply(a.apply(point))) < 1e-4);
+        assert!(a.then(&a.inverse()).apply(point).distance(point) < 1e-4);
+    }
+}
@@ -0,0 +1,273 @@
+use nalgebra::{Matrix3, Vector3};
+
+use crate::config::Config;
+
+/*
+ * maps a robot's drive commands to its velocity in the body frame (forward,
+ * lateral, angular). commands are three numbers whose meaning depends on the
+ * model; unused ones stay zero
+ */
+pub trait Kinematics {
+    fn body_velocity(&self, command: Vector3<f32>, cfg: &Config) -> Vector3<f32>;
+
+    // jacobian of the body velocity with respect to the commands
+    fn jacobian(&self, command: Vector3<f32>, cfg: &Config) -> Matrix3<f32>;
+
+    // commands that drive forward and turn at the given rates, without strafing
+    fn command(&self, forward: f32, angular: f32, cfg: &Config) -> Vector3<f32>;
+
+    // commands bounded to what the drive can do
+    fn clamp(&self, command: Vector3<f32>, cfg: &Config) -> Vector3<f32>;
+
+    // per-command decay rates (friction, self-centering steering)
+    fn drag(&self, cfg: &Config) -> Vector3<f32>;
+
+    // per-command noise, scaled by the given linear and angular factors
+    fn command_stdevs(
+        &self,
+        command: Vector3<f32>,
+        stdev_linear: f32,
+        stdev_angular: f32,
+    ) -> Vector3<f32>;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DriveType {
+    Unicycle,          // linear velocity, angular velocity
+    DifferentialDrive, // left wheel speed, right wheel speed
+    Ackermann,         // speed, steering angle
+    Omnidirectional,   // forward velocity, lateral velocity, angular velocity
+}
+
+impl DriveType {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Unicycle => Self::DifferentialDrive,
+            Self::DifferentialDrive => Self::Ackermann,
+            Self::Ackermann => Self::Omnidirectional,
+            Self::Omnidirectional => Self::Unicycle,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unicycle => "unicycle",
+            Self::DifferentialDrive => "differential drive",
+            Self::Ackermann => "ackermann",
+            Self::Omnidirectional => "omnidirectional",
+        }
+    }
+
+    pub fn model(self) -> &'static dyn Kinematics {
+        match self {
+            Self::Unicycle => &Unicycle,
+            Self::DifferentialDrive => &DifferentialDrive,
+            Self::Ackermann => &Ackermann,
+            Self::Omnidirectional => &Omnidirectional,
+        }
+    }
+}
+
+pub struct Unicycle;
+
+impl Kinematics for Unicycle {
+    fn body_velocity(&self, command: Vector3<f32>, _cfg: &Config) -> Vector3<f32> {
+        Vector3::new(command[0], 0.0, command[1])
+    }
+
+    fn jacobian(&self, _command: Vector3<f32>, _cfg: &Config) -> Matrix3<f32> {
+        Matrix3::new(1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0)
+    }
+
+    fn command(&self, forward: f32, angular: f32, _cfg: &Config) -> Vector3<f32> {
+        Vector3::new(forward, angular, 0.0)
+    }
+
+    fn clamp(&self, command: Vector3<f32>, cfg: &Config) -> Vector3<f32> {
+        Vector3::new(
+            command[0].clamp(-cfg.max_linear_speed, cfg.max_linear_speed),
+            command[1].clamp(-cfg.max_angular_speed, cfg.max_angular_speed),
+            0.0,
+        )
+    }
+
+    fn drag(&self, cfg: &Config) -> Vector3<f32> {
+        Vector3::new(cfg.drag_linear, cfg.drag_angular, 0.0)
+    }
+
+    fn command_stdevs(
+        &self,
+        command: Vector3<f32>,
+        stdev_linear: f32,
+        stdev_angular: f32,
+    ) -> Vector3<f32> {
+        Vector3::new(
+            stdev_linear * command[0].abs(),
+            stdev_angular * command[1].abs(),
+            0.0,
+        )
+    }
+}
+
+/*
+ * two independently driven wheels a wheel base apart; the difference in
+ * their speeds turns the robot
+ */
+pub struct DifferentialDrive;
+
+impl Kinematics for DifferentialDrive {
+    fn body_velocity(&self, command: Vector3<f32>, cfg: &Config) -> Vector3<f32> {
+        let (left, right) = (command[0], command[1]);
+        Vector3::new(0.5 * (left + right), 0.0, (right - left) / cfg.wheel_base)
+    }
+
+    fn jacobian(&self, _command: Vector3<f32>, cfg: &Config) -> Matrix3<f32> {
+        let inverse_base = 1.0 / cfg.wheel_base;
+        Matrix3::new(
+            0.5,
+            0.5,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            -inverse_base,
+            inverse_base,
+            0.0,
+        )
+    }
+
+    fn command(&self, forward: f32, angular: f32, cfg: &Config) -> Vector3<f32> {
+        let half_difference = 0.5 * angular * cfg.wheel_base;
+        Vector3::new(forward - half_difference, forward + half_difference, 0.0)
+    }
+
+    fn clamp(&self, command: Vector3<f32>, cfg: &Config) -> Vector3<f32> {
+        Vector3::new(
+            command[0].clamp(-cfg.max_linear_speed, cfg.max_linear_speed),
+            command[1].clamp(-cfg.max_linear_speed, cfg.max_linear_speed),
+            0.0,
+        )
+    }
+
+    fn drag(&self, cfg: &Config) -> Vector3<f32> {
+        Vector3::new(cfg.drag_linear, cfg.drag_linear, 0.0)
+    }
+
+    fn command_stdevs(
+        &self,
+        command: Vector3<f32>,
+        stdev_linear: f32,
+        _stdev_angular: f32,
+    ) -> Vector3<f32> {
+        Vector3::new(
+            stdev_linear * command[0].abs(),
+            stdev_linear * command[1].abs(),
+            0.0,
+        )
+    }
+}
+
+/*
+ * kinematic bicycle referenced at the rear axle; the turn rate is the speed
+ * times the tangent of the steering angle over the wheelbase
+ */
+pub struct Ackermann;
+
+impl Kinematics for Ackermann {
+    fn body_velocity(&self, command: Vector3<f32>, cfg: &Config) -> Vector3<f32> {
+        let (speed, steering) = (command[0], command[1]);
+        Vector3::new(speed, 0.0, speed * steering.tan() / cfg.ackermann_wheelbase)
+    }
+
+    fn jacobian(&self, command: Vector3<f32>, cfg: &Config) -> Matrix3<f32> {
+        let (speed, steering) = (command[0], command[1]);
+        Matrix3::new(
+            1.0,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            steering.tan() / cfg.ackermann_wheelbase,
+            speed / (cfg.ackermann_wheelbase * steering.cos().powi(2)),
+            0.0,
+        )
+    }
+
+    // standing still, any steering angle gives no turn; keep the wheels straight
+    fn command(&self, forward: f32, angular: f32, cfg: &Config) -> Vector3<f32> {
+        let steering = if forward.abs() > f32::EPSILON {
+            (angular * cfg.ackermann_wheelbase / forward).atan()
+        } else {
+            0.0
+        };
+        Vector3::new(forward, steering, 0.0)
+    }
+
+    fn clamp(&self, command: Vector3<f32>, cfg: &Config) -> Vector3<f32> {
+        Vector3::new(
+            command[0].clamp(-cfg.max_linear_speed, cfg.max_linear_speed),
+            command[1].clamp(-cfg.max_steering_angle, cfg.max_steering_angle),
+            0.0,
+        )
+    }
+
+    fn drag(&self, cfg: &Config) -> Vector3<f32> {
+        Vector3::new(cfg.drag_linear, cfg.drag_angular, 0.0)
+    }
+
+    fn command_stdevs(
+        &self,
+        command: Vector3<f32>,
+        stdev_linear: f32,
+        stdev_angular: f32,
+    ) -> Vector3<f32> {
+        Vector3::new(
+            stdev_linear * command[0].abs(),
+            stdev_angular * command[1].abs(),
+            0.0,
+        )
+    }
+}
+
+// holonomic drive (mecanum or omni wheels), commanded directly in the body frame
+pub struct Omnidirectional;
+
+impl Kinematics for Omnidirectional {
+    fn body_velocity(&self, command: Vector3<f32>, _cfg: &Config) -> Vector3<f32> {
+        command
+    }
+
+    fn jacobian(&self, _command: Vector3<f32>, _cfg: &Config) -> Matrix3<f32> {
+        Matrix3::identity()
+    }
+
+    fn command(&self, forward: f32, angular: f32, _cfg: &Config) -> Vector3<f32> {
+        Vector3::new(forward, 0.0, angular)
+    }
+
+    fn clamp(&self, command: Vector3<f32>, cfg: &Config) -> Vector3<f32> {
+        Vector3::new(
+            command[0].clamp(-cfg.max_linear_speed, cfg.max_linear_speed),
+            command[1].clamp(-cfg.max_linear_speed, cfg.max_linear_speed),
+            command[2].clamp(-cfg.max_angular_speed, cfg.max_angular_speed),
+        )
+    }
+
+    fn drag(&self, cfg: &Config) -> Vector3<f32> {
+        Vector3::new(cfg.drag_linear, cfg.drag_linear, cfg.drag_angular)
+    }
+
+    fn command_stdevs(
+        &self,
+        command: Vector3<f32>,
+        stdev_linear: f32,
+        stdev_angular: f32,
+    ) -> Vector3<f32> {
+        Vector3::new(
+            stdev_linear * command[0].abs(),
+            stdev_linear * command[1].abs(),
+            stdev_angular * command[2].abs(),
+        )
+    }
+}
@@ -1,18 +1,36 @@
 use macroquad::prelude::*;
 
+mod agent;
 mod app;
+mod bench;
 mod config;
+mod controller;
+mod dynamics;
+mod exploration;
+mod geometry;
+mod kinematics;
+mod map_merge;
+mod planner;
 mod simulation;
 mod slam;
+mod spatial_index;
 mod utils;
+mod world_gen;
 
+use agent::Agent;
 use app::{hud, renderer, user_settings};
 use config::Config;
+use controller::{FollowMode, WaypointFollower};
+use exploration::{ExplorationGoal, ExplorationStrategy};
+use map_merge::{Link, MapMerger};
 use simulation::Landmark;
-use slam::{EkfSlam, FastSlam, Slam};
+use slam::{EkfSlam, Slam};
+use spatial_index::ObstructionGrid;
 use user_settings::UserSettings;
 
-use crate::app::{hud::is_cog_hovered, input};
+use crate::app::editor::ObstructionEditor;
+use crate::app::hud::{is_cog_hovered, is_world_button_hovered};
+use crate::app::input;
 
 // loads font
 const FONT_BYTES: &[u8] = include_bytes!("../assets/fonts/GoogleSansCode-Medium.ttf");
@@ -29,9 +47,42 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let cfg = Config::default();
+fn main() {
+    /*
+     * `cargo run --release -- --bench [name]` runs the headless benchmarks
+     * instead of the window, `--waypoints <file>` starts the first robot with
+     * a path to follow
+     */
+    let mut args = std::env::args().skip(1);
+    let follower = match args.next().as_deref() {
+        Some("--bench") => {
+            bench::run(args.next().as_deref());
+            return;
+        }
+        Some("--waypoints") => {
+            let path = args.next().unwrap_or_default();
+            match WaypointFollower::load(&path) {
+                Ok(follower) => follower,
+                Err(error) => {
+                    eprintln!("couldn't load waypoints from {path:?}: {error}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => WaypointFollower::new(),
+    };
+
+    macroquad::Window::from_config(window_conf(), run(follower));
+}
+
+fn spawn_agents(cfg: &Config) -> Vec<Agent> {
+    (0..cfg.robot_count)
+        .map(|index| Agent::new(index, cfg))
+        .collect()
+}
+
+async fn run(follower: WaypointFollower) {
+    let mut cfg = Config::default();
 
     // settings
     let mut pause = false;
@@ -41,12 +92,16 @@ async fn main() {
     let font = load_ttf_font_from_bytes(FONT_BYTES).unwrap();
 
     // rectangles and landmarks
-    let mut obstructions: Vec<Rect> = Vec::new();
+    let mut obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+    let mut editor = ObstructionEditor::new();
+    let mut outages: Vec<Rect> = Vec::new(); // regions without gps
     let mut landmarks: Vec<Landmark> = Vec::new();
 
-    let mut robot = simulation::Robot::new();
-    let mut ekf_slam = EkfSlam::new();
-    let mut fast_slam = FastSlam::new(100);
+    // the robots; the keys drive the one in focus, and the camera follows it
+    let mut agents = spawn_agents(&cfg);
+    agents[0].follower = follower;
+    let mut focus = 0;
+    let mut merger = MapMerger::new();
 
     let mut horizontal_units = cfg.min_horizontal_units;
 
@@ -57,8 +112,9 @@ async fn main() {
         let viewport_height = screen_height();
         let viewport_width = screen_width();
 
+        let focused = &agents[focus].robot;
         let gt_camera = Camera2D {
-            target: vec2(robot.x, robot.y),
+            target: vec2(focused.x, focused.y),
             zoom: vec2(
                 2.0 / horizontal_units,
                 2.0 / -horizontal_units * viewport_width / viewport_height,
@@ -75,10 +131,51 @@ async fn main() {
             || is_key_released(KeyCode::Escape)
         {
             pause = !pause;
+        } else if is_world_button_hovered() && is_mouse_button_released(MouseButton::Left) {
+            // a fresh world starts everything over from the spawn points
+            let world = world_gen::generate(cfg.world_kind, cfg.world_seed, &cfg);
+            cfg.world_seed += 1;
+
+            obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+            for obstruction in world.obstructions {
+                obstructions.push(obstruction);
+            }
+            landmarks = world.landmarks;
+            outages.clear();
+
+            agents = spawn_agents(&cfg);
+            focus = 0;
+            merger = MapMerger::new();
+            editor = ObstructionEditor::new();
         } else if !pause {
-            input::movement_input(&mut robot, &cfg, delta_time);
-            input::obstructions_input(&gt_camera, &mut obstructions, &cfg);
+            input::focus_input(&mut focus, agents.len());
+            let agent = &mut agents[focus];
+
+            input::movement_input(&mut agent.robot, &cfg, delta_time);
+            editor.update(&gt_camera, &mut obstructions, &cfg);
+            input::waypoints_input(&gt_camera, &mut agent.follower, &cfg);
             input::landmarks_input(&gt_camera, &mut landmarks, &cfg);
+            input::outages_input(&gt_camera, &mut outages, &cfg);
+            input::resampling_input(&mut cfg);
+            input::sensor_mode_input(&mut cfg);
+            input::motion_model_input(&mut cfg);
+            input::obstruction_shape_input(&mut cfg);
+            input::world_kind_input(&mut cfg);
+            input::obstacle_behavior_input(&mut cfg);
+            input::landmark_motion_input(&mut cfg);
+            input::gps_input(&mut cfg);
+            input::follow_mode_input(agent);
+            input::exploration_input(agent);
+            input::planner_input(&mut cfg);
+            input::merge_source_input(&mut cfg);
+            input::graph_kernel_input(&mut cfg);
+
+            // plan from where the chosen map thinks the robot is, then follow the path once
+            if let Some(goal) = input::plan_input(&gt_camera) {
+                agent.plan_to(goal, &obstructions, &landmarks, &cfg);
+            }
+
+            input::drive_input(&mut agents, &mut cfg);
         }
         input::zoom_input(
             &mut horizontal_units,
@@ -90,27 +187,25 @@ async fn main() {
          * update logic
          */
         if !pause {
-            // ground truth robot update
-            robot.update(delta_time, &cfg, &obstructions);
-
-            // ekf prediction step
-            ekf_slam.predict(
-                robot.linear_velocity,
-                robot.angular_velocity,
-                delta_time,
-                &cfg,
-            );
-            fast_slam.predict(
-                robot.linear_velocity,
-                robot.angular_velocity,
-                delta_time,
-                &cfg,
-            );
+            // the world moves first, then the robots in it
+            obstructions.step(delta_time, &cfg);
+            if cfg.move_landmarks {
+                dynamics::move_landmarks(&mut landmarks, delta_time, &cfg);
+            }
 
-            // ekf correction step
-            let observations = robot.sense(&landmarks, &obstructions, &cfg);
-            ekf_slam.update(&observations, &cfg);
-            fast_slam.update(&observations, &cfg);
+            for agent in agents.iter_mut() {
+                agent.step(&obstructions, &landmarks, &outages, delta_time, &cfg);
+            }
+
+            // robots spot each other once they've all moved, and their maps are merged
+            let positions: Vec<Vec2> = agents
+                .iter()
+                .map(|agent| vec2(agent.robot.x, agent.robot.y))
+                .collect();
+            for (index, agent) in agents.iter_mut().enumerate() {
+                agent.sense_robots(index, &positions, &obstructions, &cfg);
+            }
+            merger.update(cfg.merge_source, &agents, &cfg);
         }
 
         /*
@@ -120,43 +215,176 @@ async fn main() {
         set_camera(&gt_camera);
 
         // gridlines
+        let focused = &agents[focus];
         renderer::draw_gridlines(
-            robot.x,
-            robot.y,
+            focused.robot.x,
+            focused.robot.y,
             viewport_width,
             viewport_height,
             horizontal_units,
             cfg.grid_unit,
         );
 
+        renderer::draw_outages(&outages);
+        for agent in agents.iter() {
+            if agent.exploration != ExplorationStrategy::Off {
+                renderer::draw_occupancy(&agent.explorer.grid);
+            }
+        }
+
+        // sensing cones
+        if user_settings.show_sensor_footprint {
+            for agent in agents.iter() {
+                renderer::draw_sensor_footprint(
+                    agent.robot.x,
+                    agent.robot.y,
+                    agent.robot.theta,
+                    cfg.sensor_min_range,
+                    cfg.sensor_range,
+                    cfg.sensor_fov,
+                );
+            }
+        }
+
         // shadows
         renderer::draw_landmarks_shadows(&landmarks, cfg.landmark_radius);
-        renderer::draw_robot_shadow(robot.x, robot.y, cfg.robot_radius);
-        renderer::draw_obstructions_shadows(&obstructions);
+        for agent in agents.iter() {
+            renderer::draw_robot_shadow(agent.robot.x, agent.robot.y, cfg.robot_radius);
+        }
+        renderer::draw_obstructions_shadows(obstructions.obstructions());
 
         // draw obstructions and landmarks
         renderer::draw_obstructions(&obstructions);
+        renderer::draw_obstruction_preview(
+            editor.preview(&gt_camera, &cfg).as_ref(),
+            &editor.polygon,
+        );
         renderer::draw_landmarks(&landmarks, cfg.landmark_radius);
+        for agent in agents.iter() {
+            if let Some(estimator) = agent.estimator(cfg.planning_map) {
+                renderer::draw_planned_obstructions(&agent.planned_map, estimator.color());
+            }
+            renderer::draw_waypoints(
+                &agent.follower.waypoints,
+                agent.follower.target,
+                agent.follow_mode,
+            );
+        }
 
-        // draw "robot"
-        renderer::draw_robot(robot.x, robot.y, robot.theta, cfg.robot_radius, BLUE, WHITE);
+        // raw sensor returns
+        if user_settings.show_observations {
+            for agent in agents.iter() {
+                renderer::draw_observations(
+                    agent.robot.x,
+                    agent.robot.y,
+                    agent.robot.theta,
+                    &agent.observations,
+                    cfg.sensor_range,
+                );
+                renderer::draw_sightings(
+                    agent.robot.x,
+                    agent.robot.y,
+                    agent.robot.theta,
+                    &agent.sightings,
+                );
+                renderer::draw_scan(
+                    agent.robot.x,
+                    agent.robot.y,
+                    agent.robot.theta,
+                    &agent.scan,
+                    &cfg,
+                );
+                if let Some((fix, _)) = &agent.gps_fix {
+                    renderer::draw_gps_fix(fix, cfg.real_stdev_gps * agent.noise_scale);
+                }
+            }
+        }
 
-        // SLAM "ghosts"
-        if user_settings.show_ekf_state {
-            renderer::draw_slam_state(&ekf_slam, cfg.robot_radius * 1.5)
-        };
-        if user_settings.show_fast_state {
-            renderer::draw_slam_state(&fast_slam, cfg.robot_radius * 1.5)
-        };
+        // the pose graphs' scan maps, under the robots
+        if user_settings.show_graph_map {
+            for agent in agents.iter() {
+                renderer::draw_pose_graph(&agent.pose_graph, agent.frame());
+            }
+        }
 
-        // draw landmark estimates
-        if user_settings.show_ekf_landmarks {
-            renderer::draw_slam_landmarks(&ekf_slam, cfg.landmark_radius);
+        // draw "robots"
+        for agent in agents.iter() {
+            let (x, y, theta) = (agent.robot.x, agent.robot.y, agent.robot.theta);
+            renderer::draw_robot(x, y, theta, cfg.robot_radius, agent.color, WHITE);
         }
-        if user_settings.show_fast_landmarks {
-            renderer::draw_slam_landmarks(&fast_slam, cfg.landmark_radius);
+
+        // SLAM "ghosts", and their landmark estimates, in the world's coordinates
+        let radius = cfg.robot_radius * 1.5;
+        for agent in agents.iter() {
+            let estimators: [(&dyn Slam, bool, bool); 5] = [
+                (
+                    &agent.dead_reckoning,
+                    user_settings.show_dead_reckoning,
+                    false,
+                ),
+                (
+                    &agent.ekf_slam,
+                    user_settings.show_ekf_state,
+                    user_settings.show_ekf_landmarks,
+                ),
+                (
+                    &agent.fast_slam,
+                    user_settings.show_fast_state,
+                    user_settings.show_fast_landmarks,
+                ),
+                (
+                    &agent.gyro_ekf_slam,
+                    user_settings.show_gyro_ekf_state,
+                    user_settings.show_gyro_ekf_landmarks,
+                ),
+                (
+                    &agent.pose_graph,
+                    user_settings.show_graph_state,
+                    user_settings.show_graph_map,
+                ),
+            ];
+            for (estimator, show_state, show_landmarks) in estimators {
+                if show_state {
+                    let pose = agent.to_world(estimator.get_state());
+                    renderer::draw_slam_state(pose, estimator.color(), radius);
+                }
+                if show_landmarks {
+                    let estimates = agent.landmarks_to_world(&estimator.get_landmarks());
+                    renderer::draw_slam_landmarks(
+                        &estimates,
+                        estimator.color(),
+                        cfg.landmark_radius,
+                    );
+                }
+            }
+        }
+
+        // loop closures flash for a moment, in their estimator's color
+        for agent in agents.iter() {
+            for logged in agent.loop_closures.iter().rev() {
+                let age = agent.clock - logged.time;
+                if age >= cfg.loop_closure_flash_time {
+                    break;
+                }
+                let frame = agent.frame();
+                let (x, y, _) = logged.event.pose;
+                let landmarks: Vec<Vec2> = logged
+                    .event
+                    .landmarks
+                    .iter()
+                    .map(|&(_, (x, y))| frame.apply(vec2(x, y)))
+                    .collect();
+                renderer::draw_loop_closure(
+                    frame.apply(vec2(x, y)),
+                    &landmarks,
+                    logged.color,
+                    age / cfg.loop_closure_flash_time,
+                );
+            }
         }
 
+        renderer::draw_merged_map(&merger.landmarks, cfg.landmark_radius * 1.5);
+
         /*
          * HUD
          */
@@ -166,8 +394,176 @@ async fn main() {
             hud::draw_settings(&font, &mut user_settings);
         }
         hud::draw_legend(&font);
+        hud::draw_robots(
+            &font,
+            &agents
+                .iter()
+                .enumerate()
+                .map(|(index, agent)| {
+                    let mode = if agent.exploration != ExplorationStrategy::Off {
+                        "explore"
+                    } else if agent.follow_mode != FollowMode::Off {
+                        "follow"
+                    } else {
+                        "keys"
+                    };
+                    let (x, y, _) = agent.to_world(agent.ekf_slam.get_state());
+                    let error = (x - agent.robot.x).hypot(y - agent.robot.y);
+                    (
+                        format!(
+                            "{}{}: {mode}, noise x{:.1}, off {error:.0}",
+                            if index == focus { "> " } else { "" },
+                            index + 1,
+                            agent.noise_scale
+                        ),
+                        agent.color,
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let focused = &agents[focus];
+        if user_settings.show_gyro_bias_plot {
+            hud::draw_plot(
+                &font,
+                "Gyro bias (rad/s)",
+                &[
+                    (
+                        focused
+                            .gyro_bias_history
+                            .iter()
+                            .map(|sample| sample.0)
+                            .collect(),
+                        LIGHTGRAY,
+                    ),
+                    (
+                        focused
+                            .gyro_bias_history
+                            .iter()
+                            .map(|sample| sample.1)
+                            .collect(),
+                        EkfSlam::GYRO_COLOR,
+                    ),
+                ],
+            );
+        }
+        hud::draw_status(
+            &font,
+            &[
+                format!(
+                    "World: {} (next seed {})",
+                    cfg.world_kind.name(),
+                    cfg.world_seed
+                ),
+                format!("Drive: {}", cfg.drive.name()),
+                format!(
+                    "Waypoints: {} ({})",
+                    focused.follower.waypoints.len(),
+                    focused.follow_mode.name()
+                ),
+                format!(
+                    "Planner: {} on {}{}",
+                    cfg.planner.name(),
+                    cfg.planning_map.name(),
+                    if focused.plan_failed {
+                        " (no path)"
+                    } else {
+                        ""
+                    }
+                ),
+                format!(
+                    "Exploration: {}{}",
+                    focused.exploration.name(),
+                    match focused.explorer.goal {
+                        _ if focused.exploration == ExplorationStrategy::Off => String::new(),
+                        Some(ExplorationGoal::Landmark(id, _)) => format!(" (revisiting {id})"),
+                        Some(ExplorationGoal::Frontier(_)) => format!(
+                            " ({:.0}k units² seen)",
+                            focused.explorer.grid.free_area() / 1000.0
+                        ),
+                        None if focused.explorer.finished => " (done)".to_owned(),
+                        None => String::new(),
+                    }
+                ),
+                format!(
+                    "Map merge: {}{}",
+                    cfg.merge_source.name(),
+                    if merger.frames.is_empty() {
+                        String::new()
+                    } else {
+                        format!(
+                            " ({}/{} robots, {} landmarks{})",
+                            merger.frames.iter().flatten().count(),
+                            agents.len(),
+                            merger.landmarks.len(),
+                            merger
+                                .links
+                                .iter()
+                                .map(|&((a, b), link)| format!(
+                                    ", {}-{} {}",
+                                    a + 1,
+                                    b + 1,
+                                    match link {
+                                        Link::Encounter => "met",
+                                        Link::Shared => "shared",
+                                    }
+                                ))
+                                .collect::<String>()
+                        )
+                    }
+                ),
+                format!(
+                    "Loop closures: {}{}",
+                    focused.loop_closures.len(),
+                    focused
+                        .loop_closures
+                        .last()
+                        .map(|last| format!(
+                            " (last {}, corrected {:.1} after {:.0} units)",
+                            last.estimator, last.event.correction, last.event.distance
+                        ))
+                        .unwrap_or_default()
+                ),
+                format!(
+                    "Graph kernel: {} ({} rejected)",
+                    cfg.graph_kernel.name(),
+                    focused.pose_graph.rejected()
+                ),
+                format!("Sensor: {}", cfg.sensor_mode.name()),
+                format!(
+                    "Obstruction: {} ({})",
+                    cfg.obstruction_shape.name(),
+                    cfg.obstacle_behavior.name()
+                ),
+                format!(
+                    "Landmark motion: {}",
+                    if cfg.move_landmarks { "on" } else { "off" }
+                ),
+                format!("Motion model: {}", cfg.motion_model.name()),
+                format!(
+                    "GPS: {}",
+                    if !cfg.use_gps {
+                        "off"
+                    } else if outages
+                        .iter()
+                        .any(|outage| outage.contains(vec2(focused.robot.x, focused.robot.y)))
+                    {
+                        "outage"
+                    } else {
+                        "on (FastSLAM reweights only)"
+                    }
+                ),
+                format!(
+                    "FastSLAM ESS: {:.0}/{} ({})",
+                    focused.fast_slam.effective_sample_size(),
+                    focused.fast_slam.num_particles,
+                    cfg.resampling_scheme.name()
+                ),
+            ],
+        );
 
         hud::draw_cog();
+        hud::draw_world_button(&font);
 
         next_frame().await
     }
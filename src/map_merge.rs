@@ -0,0 +1,322 @@
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+
+use macroquad::prelude::{Color, Vec2, vec2};
+use nalgebra::{Matrix2, Vector2};
+
+use crate::agent::Agent;
+use crate::config::Config;
+use crate::geometry::RigidTransform;
+use crate::slam::Slam;
+
+type Pair = (usize, usize); // robot indices, lower first
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MergeSource {
+    Off,
+    Ekf,
+    FastSlam,
+}
+
+impl MergeSource {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Ekf,
+            Self::Ekf => Self::FastSlam,
+            Self::FastSlam => Self::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Ekf => "EKF",
+            Self::FastSlam => "FastSLAM",
+        }
+    }
+}
+
+// how two robots' frames were tied together
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Link {
+    Encounter, // they saw each other
+    Shared,    // their maps have enough landmarks in common
+}
+
+pub struct MergedLandmark {
+    pub id: usize,
+    pub mean: Vector2<f32>,
+    pub covariance: Matrix2<f32>,
+    pub robots: usize, // maps it was fused from
+}
+
+/*
+ * one map out of every robot's. each robot maps in its own frame, so pairs of
+ * frames are tied together either by a mutual sighting, which pins down the
+ * whole relative pose at once, or by landmarks both maps hold, lined up by
+ * least squares. the links form a graph; frames reachable from the first
+ * robot's are brought into it, and the landmarks fused by adding up their
+ * information. the maps' errors are treated as independent and the links as
+ * exact, so the merged covariance is on the optimistic side
+ */
+pub struct MapMerger {
+    encounters: HashMap<Pair, RigidTransform>, // the higher robot's frame into the lower's, at their latest meeting
+    pub links: Vec<(Pair, Link)>,
+    pub frames: Vec<Option<RigidTransform>>, // each robot's frame into the first's, if linked
+    pub landmarks: Vec<MergedLandmark>,      // in the world's coordinates
+}
+
+impl MapMerger {
+    pub const COLOR: Color = Color::new(0.6, 1.0, 0.85, 0.8);
+
+    pub fn new() -> Self {
+        Self {
+            encounters: HashMap::new(),
+            links: Vec::new(),
+            frames: Vec::new(),
+            landmarks: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, source: MergeSource, agents: &[Agent], cfg: &Config) {
+        let estimators: Vec<&dyn Slam> = match source {
+            MergeSource::Off => {
+                *self = Self::new();
+                return;
+            }
+            MergeSource::Ekf => agents.iter().map(|agent| &agent.ekf_slam as _).collect(),
+            MergeSource::FastSlam => agents.iter().map(|agent| &agent.fast_slam as _).collect(),
+        };
+        self.record_encounters(agents, &estimators);
+
+        let maps: Vec<HashMap<usize, Vec2>> = estimators
+            .iter()
+            .map(|estimator| {
+                estimator
+                    .get_landmarks()
+                    .into_iter()
+                    .map(|(id, x, y)| (id, vec2(x, y)))
+                    .collect()
+            })
+            .collect();
+        let transforms = self.link(&maps, cfg);
+        self.frames = spanning_frames(agents.len(), &transforms);
+
+        let covariances = agents.iter().map(|agent| match source {
+            MergeSource::FastSlam => agent.fast_slam.landmark_covariances(),
+            _ => agent.ekf_slam.landmark_covariances(),
+        });
+        let world = agents[0].frame();
+        self.landmarks = fuse(
+            self.frames
+                .iter()
+                .zip(covariances)
+                .filter_map(|(frame, landmarks)| Some((frame.as_ref()?.then(&world), landmarks))),
+        );
+    }
+
+    /*
+     * relative pose from robots seeing each other at the same moment: each
+     * one's bearing to the other fixes the other's heading, and the range its
+     * position
+     */
+    fn record_encounters(&mut self, agents: &[Agent], estimators: &[&dyn Slam]) {
+        for (a, agent) in agents.iter().enumerate() {
+            for seen in agent.sightings.iter().filter(|seen| seen.robot > a) {
+                let b = seen.robot;
+                let Some(back) = agents[b].sightings.iter().find(|back| back.robot == a) else {
+                    continue;
+                };
+
+                let (a_x, a_y, a_theta) = estimators[a].get_state();
+                let (b_x, b_y, b_theta) = estimators[b].get_state();
+                let direction = a_theta + seen.bearing;
+                let range = 0.5 * (seen.range + back.range);
+                let b_in_a = vec2(a_x, a_y) + Vec2::from_angle(direction) * range;
+                let angle = direction + PI - back.bearing - b_theta;
+                let rotation = Vec2::from_angle(angle);
+
+                self.encounters.insert(
+                    (a, b),
+                    RigidTransform {
+                        angle: rotation.y.atan2(rotation.x),
+                        translation: b_in_a - rotation.rotate(vec2(b_x, b_y)),
+                    },
+                );
+            }
+        }
+    }
+
+    // the best transform for every linked pair, preferring shared landmarks over encounters
+    fn link(
+        &mut self,
+        maps: &[HashMap<usize, Vec2>],
+        cfg: &Config,
+    ) -> HashMap<Pair, RigidTransform> {
+        let mut transforms = HashMap::new();
+        self.links.clear();
+
+        for a in 0..maps.len() {
+            for b in a + 1..maps.len() {
+                let pairs: Vec<(Vec2, Vec2)> = maps[b]
+                    .iter()
+                    .filter_map(|(id, &in_b)| Some((in_b, *maps[a].get(id)?)))
+                    .collect();
+                let shared = (pairs.len() >= cfg.merge_min_shared)
+                    .then(|| RigidTransform::fit(&pairs))
+                    .flatten();
+
+                let (transform, link) = match (shared, self.encounters.get(&(a, b))) {
+                    (Some(transform), _) => (transform, Link::Shared),
+                    (None, Some(&transform)) => (transform, Link::Encounter),
+                    (None, None) => continue,
+                };
+                transforms.insert((a, b), transform);
+                self.links.push(((a, b), link));
+            }
+        }
+        transforms
+    }
+}
+
+// each robot's frame into the first's, walking the links outward from it
+fn spanning_frames(
+    count: usize,
+    transforms: &HashMap<Pair, RigidTransform>,
+) -> Vec<Option<RigidTransform>> {
+    let mut frames = vec![None; count];
+    if count == 0 {
+        return frames;
+    }
+    frames[0] = Some(RigidTransform::IDENTITY);
+    let mut queue = VecDeque::from([(0, RigidTransform::IDENTITY)]);
+
+    while let Some((robot, into_first)) = queue.pop_front() {
+        for (other, frame) in frames.iter_mut().enumerate() {
+            if frame.is_some() {
+                continue;
+            }
+            let into_robot = if robot < other {
+                transforms.get(&(robot, other)).copied()
+            } else {
+                transforms.get(&(other, robot)).map(RigidTransform::inverse)
+            };
+            if let Some(into_robot) = into_robot {
+                let into_first = into_robot.then(&into_first);
+                *frame = Some(into_first);
+                queue.push_back((other, into_first));
+            }
+        }
+    }
+    frames
+}
+
+/*
+ * information filter fusion of every map's landmarks once moved into the
+ * common frame, where a covariance turns with the map: R Σ Rᵀ
+ */
+fn fuse(
+    maps: impl Iterator<Item = (RigidTransform, Vec<(usize, Vector2<f32>, Matrix2<f32>)>)>,
+) -> Vec<MergedLandmark> {
+    let mut information: HashMap<usize, (Matrix2<f32>, Vector2<f32>, usize)> = HashMap::new();
+
+    for (frame, landmarks) in maps {
+        let rotation = Matrix2::new(
+            frame.angle.cos(),
+            -frame.angle.sin(),
+            frame.angle.sin(),
+            frame.angle.cos(),
+        );
+        for (id, mean, covariance) in landmarks {
+            let Some(inverse) = (rotation * covariance * rotation.transpose()).try_inverse() else {
+                continue;
+            };
+            let moved = frame.apply(vec2(mean.x, mean.y));
+            let (matrix, vector, robots) = information.entry(id).or_default();
+            *matrix += inverse;
+            *vector += inverse * Vector2::new(moved.x, moved.y);
+            *robots += 1;
+        }
+    }
+
+    let mut landmarks: Vec<MergedLandmark> = information
+        .into_iter()
+        .filter_map(|(id, (matrix, vector, robots))| {
+            let covariance = matrix.try_inverse()?;
+            Some(MergedLandmark {
+                id,
+                mean: covariance * vector,
+                covariance,
+                robots,
+            })
+        })
+        .collect();
+    landmarks.sort_by_key(|landmark| landmark.id);
+    landmarks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spanning_frames_walk_links_either_way() {
+        let two_into_zero = RigidTransform {
+            angle: 0.5,
+            translation: vec2(10.0, 0.0),
+        };
+        let two_into_one = RigidTransform {
+            angle: -1.0,
+            translation: vec2(0.0, 20.0),
+        };
+        // the pair (a, b) takes b's frame into a's, so reaching the second
+        // robot through the third walks the link backwards
+        let transforms = HashMap::from([((0, 2), two_into_zero), ((1, 2), two_into_one)]);
+
+        let frames = spanning_frames(4, &transforms);
+        assert_eq!(frames[0], Some(RigidTransform::IDENTITY));
+        assert_eq!(frames[2], Some(two_into_zero));
+        let point = vec2(5.0, 7.0);
+        let expected = two_into_zero.apply(two_into_one.inverse().apply(point));
+        assert!(frames[1].unwrap().apply(point).distance(expected) < 1e-4);
+        assert_eq!(frames[3], None);
+    }
+
+    #[test]
+    fn fuse_weights_landmarks_by_their_covariance() {
+        let turn = RigidTransform {
+            angle: std::f32::consts::FRAC_PI_2,
+            translation: vec2(100.0, 0.0),
+        };
+        let narrow_in_x = Matrix2::new(1.0, 0.0, 0.0, 100.0);
+        let maps = [
+            (
+                RigidTransform::IDENTITY,
+                vec![
+                    (3, Vector2::new(0.0, 0.0), narrow_in_x),
+                    (4, Vector2::new(50.0, 50.0), narrow_in_x),
+                ],
+            ),
+            // lands at (100, 10), and its narrow axis turns to y
+            (turn, vec![(3, Vector2::new(10.0, 0.0), narrow_in_x)]),
+        ];
+
+        let merged = fuse(maps.into_iter());
+        assert_eq!(merged.len(), 2);
+        let fused = &merged[0];
+        assert_eq!((fused.id, fused.robots), (3, 2));
+        // each map pins down the axis it's sure of
+        assert!(
+            (fused.mean.x - 100.0 / 101.0).abs() < 1e-3,
+            "{}",
+            fused.mean
+        );
+        assert!(
+            (fused.mean.y - 1000.0 / 101.0).abs() < 1e-3,
+            "{}",
+            fused.mean
+        );
+        assert!(fused.covariance[(0, 0)] < 1.0 && fused.covariance[(1, 1)] < 1.0);
+        assert_eq!((merged[1].id, merged[1].robots), (4, 1));
+    }
+}
@@ -0,0 +1,380 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use macroquad::prelude::{Vec2, vec2};
+
+use crate::config::Config;
+use crate::geometry::{Obstruction, RigidTransform};
+use crate::simulation::Landmark;
+use crate::spatial_index::ObstructionGrid;
+use crate::utils::Rng;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlannerKind {
+    AStar,   // 8-connected grid search, shortcut afterwards
+    RrtStar, // rewiring random tree
+}
+
+impl PlannerKind {
+    pub fn next(self) -> Self {
+        match self {
+            Self::AStar => Self::RrtStar,
+            Self::RrtStar => Self::AStar,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AStar => "A*",
+            Self::RrtStar => "RRT*",
+        }
+    }
+}
+
+// where paths are planned, and which pose the waypoint controller steers from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapSource {
+    GroundTruth,
+    Ekf,
+    FastSlam,
+    DeadReckoning,
+}
+
+impl MapSource {
+    pub fn next(self) -> Self {
+        match self {
+            Self::GroundTruth => Self::Ekf,
+            Self::Ekf => Self::FastSlam,
+            Self::FastSlam => Self::DeadReckoning,
+            Self::DeadReckoning => Self::GroundTruth,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::GroundTruth => "ground truth",
+            Self::Ekf => "EKF",
+            Self::FastSlam => "FastSLAM",
+            Self::DeadReckoning => "dead reckoning",
+        }
+    }
+}
+
+/*
+ * the obstructions where an estimated map would put them. the estimators
+ * only map landmarks, so the obstructions are carried along with the rigid
+ * transform that best lines the true landmarks up with their estimates
+ * (least squares, needs two in common); otherwise they stay where they are
+ */
+pub fn estimated_obstructions(
+    obstructions: &[Obstruction],
+    landmarks: &[Landmark],
+    estimates: &[(usize, f32, f32)],
+) -> Vec<Obstruction> {
+    let pairs: Vec<(Vec2, Vec2)> = estimates
+        .iter()
+        .filter_map(|&(id, x, y)| {
+            let landmark = landmarks.iter().find(|landmark| landmark.id == id)?;
+            Some((vec2(landmark.x, landmark.y), vec2(x, y)))
+        })
+        .collect();
+    let Some(transform) = RigidTransform::fit(&pairs) else {
+        return obstructions.to_vec();
+    };
+
+    obstructions
+        .iter()
+        .map(|obstruction| {
+            // rotate about the origin: about the shape's own center, then move the center
+            let mut moved = obstruction.clone();
+            let center = moved.center();
+            moved.rotate(transform.angle);
+            moved.translate(transform.apply(center) - center);
+            moved
+        })
+        .collect()
+}
+
+/*
+ * collision-free path from start to goal for a disc of the robot's radius
+ * plus the planner's clearance, as waypoints after the start; None if the
+ * goal is blocked or out of reach
+ */
+pub fn plan(
+    kind: PlannerKind,
+    obstructions: &ObstructionGrid,
+    start: Vec2,
+    goal: Vec2,
+    cfg: &Config,
+) -> Option<Vec<Vec2>> {
+    let space = Space {
+        obstructions,
+        start,
+        radius: cfg.robot_radius + cfg.planner_clearance,
+        step: 0.5 * cfg.planner_resolution,
+        min: start.min(goal) - Vec2::splat(cfg.planner_margin),
+        max: start.max(goal) + Vec2::splat(cfg.planner_margin),
+    };
+    if !space.is_free(goal) {
+        return None;
+    }
+
+    let path = match kind {
+        PlannerKind::AStar => a_star(&space, start, goal, cfg)?,
+        PlannerKind::RrtStar => rrt_star(&space, start, goal, cfg)?,
+    };
+    Some(space.shortcut(path).into_iter().skip(1).collect())
+}
+
+// configuration space: the plane minus the obstructions inflated by the radius
+struct Space<'a> {
+    obstructions: &'a ObstructionGrid,
+    start: Vec2,
+    radius: f32,
+    step: f32, // spacing of the samples along a segment
+    min: Vec2, // bounds the search stays within
+    max: Vec2,
+}
+
+impl Space<'_> {
+    /*
+     * the robot may already be closer than the clearance to an obstruction,
+     * so around the start it only has to stay out of them
+     */
+    fn is_free(&self, point: Vec2) -> bool {
+        let radius = if point.distance(self.start) < self.radius {
+            0.0
+        } else {
+            self.radius
+        };
+        self.obstructions
+            .near(point, radius)
+            .into_iter()
+            .all(|index| !self.obstructions.obstructions()[index].is_near(point, radius))
+    }
+
+    // samples along the segment, and no crossing walls between them
+    fn is_segment_free(&self, from: Vec2, to: Vec2) -> bool {
+        let samples = (from.distance(to) / self.step).ceil().max(1.0) as usize;
+        !self.obstructions.blocks(from, to)
+            && (0..=samples).all(|i| self.is_free(from.lerp(to, i as f32 / samples as f32)))
+    }
+
+    // drops every waypoint the one before it can see past
+    fn shortcut(&self, path: Vec<Vec2>) -> Vec<Vec2> {
+        let mut shortened = vec![path[0]];
+        let mut from = 0;
+        while from < path.len() - 1 {
+            let to = (from + 1..path.len())
+                .rev()
+                .find(|&to| self.is_segment_free(path[from], path[to]))
+                .unwrap_or(from + 1);
+            shortened.push(path[to]);
+            from = to;
+        }
+        shortened
+    }
+}
+
+/*
+ * A* over grid cells of the planner's resolution, moving to any of the 8
+ * neighbours without cutting blocked corners. cells are tested lazily, only
+ * once the search reaches them
+ */
+fn a_star(space: &Space, start: Vec2, goal: Vec2, cfg: &Config) -> Option<Vec<Vec2>> {
+    let resolution = cfg.planner_resolution;
+    let size = ((space.max - space.min) / resolution).ceil();
+    let (width, height) = (size.x as i32, size.y as i32);
+
+    let cell = |point: Vec2| {
+        let offset = (point - space.min) / resolution;
+        (offset.x as i32, offset.y as i32)
+    };
+    let center =
+        |(x, y): (i32, i32)| space.min + (vec2(x as f32, y as f32) + Vec2::splat(0.5)) * resolution;
+    let index = |(x, y): (i32, i32)| (y * width + x) as usize;
+    let heuristic = |(x, y): (i32, i32), (gx, gy): (i32, i32)| {
+        let (dx, dy) = ((x - gx).abs() as f32, (y - gy).abs() as f32);
+        resolution * (dx.max(dy) + (std::f32::consts::SQRT_2 - 1.0) * dx.min(dy))
+    };
+
+    let (start_cell, goal_cell) = (cell(start), cell(goal));
+    let cells = (width * height) as usize;
+    let mut free: Vec<Option<bool>> = vec![None; cells];
+    let mut cost = vec![f32::INFINITY; cells];
+    let mut closed = vec![false; cells];
+    let mut parent: Vec<Option<(i32, i32)>> = vec![None; cells];
+    let mut is_free = |cell: (i32, i32)| {
+        if cell.0 < 0 || cell.1 < 0 || cell.0 >= width || cell.1 >= height {
+            return false;
+        }
+        *free[index(cell)].get_or_insert_with(|| cell == start_cell || space.is_free(center(cell)))
+    };
+
+    // non-negative floats order the same as their bit patterns
+    let mut open = BinaryHeap::new();
+    cost[index(start_cell)] = 0.0;
+    open.push(Reverse((
+        heuristic(start_cell, goal_cell).to_bits(),
+        start_cell,
+    )));
+
+    while let Some(Reverse((_, current))) = open.pop() {
+        if std::mem::replace(&mut closed[index(current)], true) {
+            continue; // already expanded through a cheaper entry
+        }
+        if current == goal_cell {
+            let mut path = vec![goal];
+            let mut cell = parent[index(current)];
+            while let Some(previous) = cell {
+                path.push(center(previous));
+                cell = parent[index(previous)];
+            }
+            path.pop(); // the start cell's center, replaced by the start itself
+            path.push(start);
+            path.reverse();
+            return Some(path);
+        }
+
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let neighbour = (current.0 + dx, current.1 + dy);
+                if (dx, dy) == (0, 0) || !is_free(neighbour) {
+                    continue;
+                }
+                if dx != 0
+                    && dy != 0
+                    && !(is_free((current.0 + dx, current.1))
+                        && is_free((current.0, current.1 + dy)))
+                {
+                    continue;
+                }
+
+                let step = if dx != 0 && dy != 0 {
+                    std::f32::consts::SQRT_2 * resolution
+                } else {
+                    resolution
+                };
+                let new_cost = cost[index(current)] + step;
+                if new_cost < cost[index(neighbour)] {
+                    cost[index(neighbour)] = new_cost;
+                    parent[index(neighbour)] = Some(current);
+                    let priority = new_cost + heuristic(neighbour, goal_cell);
+                    open.push(Reverse((priority.to_bits(), neighbour)));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+struct Node {
+    point: Vec2,
+    parent: usize,
+    cost: f32, // path length from the start
+    children: Vec<usize>,
+}
+
+/*
+ * RRT*: grows a tree from the start towards random samples (and sometimes
+ * the goal), connecting each new node through whichever neighbour gives the
+ * shortest path and rewiring neighbours through it when that's shorter
+ * https://arxiv.org/abs/1105.1186
+ */
+fn rrt_star(space: &Space, start: Vec2, goal: Vec2, cfg: &Config) -> Option<Vec<Vec2>> {
+    const GOAL_BIAS: f32 = 0.1;
+
+    let neighbourhood = 2.0 * cfg.rrt_step;
+    let mut rng = Rng::new(cfg.rrt_seed);
+    let mut nodes = vec![Node {
+        point: start,
+        parent: 0,
+        cost: 0.0,
+        children: Vec::new(),
+    }];
+
+    for _ in 0..cfg.rrt_iterations {
+        let sample = if rng.gen_range(0.0, 1.0) < GOAL_BIAS {
+            goal
+        } else {
+            vec2(
+                rng.gen_range(space.min.x, space.max.x),
+                rng.gen_range(space.min.y, space.max.y),
+            )
+        };
+
+        let nearest = (0..nodes.len())
+            .min_by(|&a, &b| {
+                let distance = |node: usize| nodes[node].point.distance_squared(sample);
+                distance(a).total_cmp(&distance(b))
+            })
+            .unwrap();
+        let from = nodes[nearest].point;
+        let point = from + (sample - from).clamp_length_max(cfg.rrt_step);
+        if !space.is_segment_free(from, point) {
+            continue;
+        }
+
+        // cheapest way in through a neighbour that can see the new point; the
+        // nearest node always can, and segments are only checked when they'd help
+        let mut neighbours: Vec<(usize, f32)> = (0..nodes.len())
+            .filter(|&node| nodes[node].point.distance(point) <= neighbourhood)
+            .map(|node| (node, nodes[node].cost + nodes[node].point.distance(point)))
+            .collect();
+        neighbours.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let (parent, cost) = *neighbours
+            .iter()
+            .find(|&&(node, _)| node == nearest || space.is_segment_free(nodes[node].point, point))
+            .unwrap();
+
+        let new = nodes.len();
+        nodes.push(Node {
+            point,
+            parent,
+            cost,
+            children: Vec::new(),
+        });
+        nodes[parent].children.push(new);
+
+        // rewire neighbours that get cheaper through the new node
+        for &(node, _) in &neighbours {
+            let through = cost + point.distance(nodes[node].point);
+            if node == parent
+                || through >= nodes[node].cost
+                || !space.is_segment_free(point, nodes[node].point)
+            {
+                continue;
+            }
+
+            let old_parent = nodes[node].parent;
+            nodes[old_parent].children.retain(|&child| child != node);
+            nodes[node].parent = new;
+            nodes[new].children.push(node);
+
+            let saving = nodes[node].cost - through;
+            let mut subtree = vec![node];
+            while let Some(descendant) = subtree.pop() {
+                nodes[descendant].cost -= saving;
+                subtree.extend_from_slice(&nodes[descendant].children);
+            }
+        }
+    }
+
+    // rewiring keeps changing costs, so the cheapest way to the goal is picked at the end
+    let mut node = (0..nodes.len())
+        .filter(|&node| nodes[node].point.distance(goal) <= cfg.rrt_step)
+        .map(|node| (node, nodes[node].cost + nodes[node].point.distance(goal)))
+        .filter(|&(node, _)| space.is_segment_free(nodes[node].point, goal))
+        .min_by(|a, b| a.1.total_cmp(&b.1))?
+        .0;
+
+    let mut path = vec![goal];
+    while node != 0 {
+        path.push(nodes[node].point);
+        node = nodes[node].parent;
+    }
+    path.push(start);
+    path.reverse();
+    Some(path)
+}
@@ -1,19 +1,134 @@
 use crate::config::Config;
-use crate::utils::sample_normal;
+use crate::spatial_index::ObstructionGrid;
+use crate::utils::{sample_normal, sample_poisson};
 use macroquad::prelude::*;
+use nalgebra::Vector3;
 
 pub struct Robot {
     pub x: f32,
     pub y: f32,
     pub theta: f32,
-    pub linear_velocity: f32,
-    pub angular_velocity: f32,
-    prev_linear_velocity: f32,
-    prev_angular_velocity: f32,
+    pub command: Vector3<f32>, // meaning depends on the drive, see kinematics
+    prev_velocity: Vector3<f32>, // noisy body velocity on the last frame
+    next_clutter_id: usize,
+    encoder_residual: (f32, f32, f32), // fractions of a tick not yet reported, left, right and lateral
+    pub gyro_bias: f32,                // ground truth, drifts as a random walk
+    angular_rate: f32,                 // true motion over the last frame, for the imu
+    velocity: (f32, f32),              // forward and lateral
+    acceleration: (f32, f32),          // forward and lateral
+    gps_timer: f32,                    // time since the last gps fix
 }
 
+// clutter cycles through ids from here up, so it never aliases a real
+// landmark; the range is small enough to keep the estimators' id-keyed maps
+// shallow, and large enough that what they kept of an id has aged out by the
+// time it comes around again
+const CLUTTER_ID_BASE: usize = 1 << 20;
+const CLUTTER_IDS: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug)]
 pub struct Observation {
     pub id: usize,
+    pub range: Option<f32>,   // None for sensors that can't measure range
+    pub bearing: Option<f32>, // None for sensors that can't measure bearing
+    pub ground_truth: ObservationSource, // for scoring only; estimators must not read this
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ObservationSource {
+    Landmark,                   // correct detection
+    WrongId { true_id: usize }, // real landmark reported under another landmark's id
+    Clutter,                    // spurious return with no landmark behind it
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SensorMode {
+    RangeBearing, // lidar/stereo style, full relative position
+    BearingOnly,  // monocular camera style, no usable range
+    RangeOnly,    // radio beacon style (uwb), no usable bearing
+}
+
+impl SensorMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::RangeBearing => Self::BearingOnly,
+            Self::BearingOnly => Self::RangeOnly,
+            Self::RangeOnly => Self::RangeBearing,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::RangeBearing => "range-bearing",
+            Self::BearingOnly => "bearing-only",
+            Self::RangeOnly => "range-only",
+        }
+    }
+
+    fn measures_range(self) -> bool {
+        self != Self::BearingOnly
+    }
+
+    fn measures_bearing(self) -> bool {
+        self != Self::RangeOnly
+    }
+}
+
+// whole encoder ticks counted by each wheel since the last read
+#[derive(Clone, Copy, Default, Debug)]
+pub struct EncoderTicks {
+    pub left: i32,
+    pub right: i32,
+    pub lateral: i32,
+}
+
+// one imu sample; the accelerometer is optional
+#[derive(Clone, Copy, Debug)]
+pub struct ImuReading {
+    pub angular_velocity: f32,
+    pub acceleration: Option<(f32, f32)>, // forward and lateral, in the robot frame
+    pub delta_time: f32,
+}
+
+// absolute position fix
+#[derive(Clone, Copy, Debug)]
+pub struct GpsFix {
+    pub x: f32,
+    pub y: f32,
+}
+
+/*
+ * one lidar sweep; beams are spread evenly over the lidar's field of view,
+ * centered on the heading, and report None when nothing is in reach
+ */
+#[derive(Clone, Debug, Default)]
+pub struct LidarScan {
+    pub ranges: Vec<Option<f32>>,
+}
+
+impl LidarScan {
+    // direction of a beam relative to the heading
+    pub fn angle(beam: usize, cfg: &Config) -> f32 {
+        (beam as f32 + 0.5) / cfg.lidar_beams as f32 * cfg.lidar_fov - 0.5 * cfg.lidar_fov
+    }
+
+    // where the beams hit, in the robot's frame
+    pub fn points(&self, cfg: &Config) -> Vec<Vec2> {
+        self.ranges
+            .iter()
+            .enumerate()
+            .filter_map(|(beam, range)| Some(Vec2::from_angle(Self::angle(beam, cfg)) * (*range)?))
+            .collect()
+    }
+}
+
+/*
+ * another robot in view, identified by a tag on its body. robots are tracked
+ * with range and bearing whatever the landmark sensor measures
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct RobotSighting {
+    pub robot: usize, // index of the robot seen
     pub range: f32,
     pub bearing: f32,
 }
@@ -22,6 +137,8 @@ pub struct Landmark {
     pub id: usize,
     pub x: f32,
     pub y: f32,
+    pub moving: bool,         // drifts when landmark motion is on
+    pub velocity: (f32, f32), // ground truth, for moving landmarks
 }
 
 impl Robot {
@@ -30,71 +147,181 @@ impl Robot {
             x: 0.0,
             y: 0.0,
             theta: 0.0,
-            linear_velocity: 0.0,
-            angular_velocity: 0.0,
-            prev_linear_velocity: 0.0,
-            prev_angular_velocity: 0.0,
+            command: Vector3::zeros(),
+            prev_velocity: Vector3::zeros(),
+            next_clutter_id: CLUTTER_ID_BASE,
+            encoder_residual: (0.0, 0.0, 0.0),
+            gyro_bias: 0.0,
+            angular_rate: 0.0,
+            velocity: (0.0, 0.0),
+            acceleration: (0.0, 0.0),
+            gps_timer: 0.0,
+        }
+    }
+
+    // at rest at the given pose
+    pub fn at(x: f32, y: f32, theta: f32) -> Self {
+        Self {
+            x,
+            y,
+            theta,
+            ..Self::new()
         }
     }
 
-    pub fn update(&mut self, delta_time: f32, cfg: &Config, obstructions: &[Rect]) {
-        // bound velocity
-        self.linear_velocity = self
-            .linear_velocity
-            .clamp(-cfg.max_linear_speed, cfg.max_linear_speed);
-        self.angular_velocity = self
-            .angular_velocity
-            .clamp(-cfg.max_angular_speed, cfg.max_angular_speed);
+    // moves the robot and returns what its wheel encoders counted on the way
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        cfg: &Config,
+        obstructions: &ObstructionGrid,
+    ) -> EncoderTicks {
+        let kinematics = cfg.drive.model();
+
+        // bound commands
+        self.command = kinematics.clamp(self.command, cfg);
 
         // apply decay
-        self.linear_velocity *= (-cfg.drag_linear * delta_time).exp();
-        self.angular_velocity *= (-cfg.drag_angular * delta_time).exp();
+        let drag = kinematics.drag(cfg);
+        for i in 0..3 {
+            self.command[i] *= (-drag[i] * delta_time).exp();
+        }
+
+        // add noise to commands; uses separate variable to keep struct's commands clean
+        let stdevs =
+            kinematics.command_stdevs(self.command, cfg.real_stdev_linear, cfg.real_stdev_angular);
+        let noisy_command = self.command + stdevs.map(|stdev| sample_normal(0.0, stdev));
+        let noisy_velocity = kinematics.body_velocity(noisy_command, cfg);
 
-        // add noise to velocity; uses separate variable to keep struct's velocities clean
-        let noisy_linear_velocity = self.linear_velocity
-            + sample_normal(0.0, cfg.real_stdev_linear * self.linear_velocity.abs());
-        let noisy_angular_velocity = self.angular_velocity
-            + sample_normal(0.0, cfg.real_stdev_angular * self.angular_velocity.abs());
+        let travel = 0.5 * (noisy_velocity + self.prev_velocity) * delta_time;
+        let (distance, lateral_distance, rotation) = (travel[0], travel[1], travel[2]);
 
         // update direction
-        self.theta += 0.5 * (noisy_angular_velocity + self.prev_angular_velocity) * delta_time;
+        self.theta += rotation;
         self.theta = f32::atan2(self.theta.sin(), self.theta.cos()); // normalize to (-PI, PI]
 
         // update position
-        self.x += (0.5 * (noisy_linear_velocity + self.prev_linear_velocity) * delta_time)
-            * self.theta.cos();
-        self.y += (0.5 * (noisy_linear_velocity + self.prev_linear_velocity) * delta_time)
-            * self.theta.sin();
+        self.x += distance * self.theta.cos() - lateral_distance * self.theta.sin();
+        self.y += distance * self.theta.sin() + lateral_distance * self.theta.cos();
 
         // detect obstruction
-        for obstruction in obstructions.iter() {
-            let closest_x = self.x.clamp(obstruction.x, obstruction.x + obstruction.w);
-            let closest_y = self.y.clamp(obstruction.y, obstruction.y + obstruction.h);
+        for index in obstructions.near(vec2(self.x, self.y), cfg.robot_radius) {
+            let resolved = obstructions.obstructions()[index]
+                .resolve_collision(vec2(self.x, self.y), cfg.robot_radius);
+            (self.x, self.y) = (resolved.x, resolved.y);
+        }
 
-            // distance from closest point on obstruction to center of robot
-            let distance_x = self.x - closest_x;
-            let distance_y = self.y - closest_y;
-            let distance_sq = distance_x * distance_x + distance_y * distance_y;
+        // needed for calculating x, y, and dir on next frame
+        self.prev_velocity = noisy_velocity;
+
+        // what an imu strapped to the robot would feel, including the rotating frame's terms
+        if delta_time > 0.0 {
+            let velocity = (distance / delta_time, lateral_distance / delta_time);
+            self.angular_rate = rotation / delta_time;
+            self.acceleration = (
+                (velocity.0 - self.velocity.0) / delta_time - velocity.1 * self.angular_rate,
+                (velocity.1 - self.velocity.1) / delta_time + velocity.0 * self.angular_rate,
+            );
+            self.velocity = velocity;
+        }
 
-            if distance_sq < cfg.robot_radius * cfg.robot_radius {
-                let distance = distance_sq.sqrt();
+        // wheels turn by the commanded motion even when an obstruction stops the robot
+        self.read_encoders(distance, lateral_distance, rotation, cfg)
+    }
 
-                if distance > 0.0 {
-                    self.x = closest_x + cfg.robot_radius * (distance_x / distance);
-                    self.y = closest_y + cfg.robot_radius * (distance_y / distance);
-                }
-            }
+    /*
+     * left and right wheel encoders, and a lateral wheel through the robot's
+     * center that only turns when it moves sideways. each wheel's travel is
+     * scaled by how far its true radius is off from the nominal one, perturbed
+     * by slip, and quantized to whole ticks; the leftover fraction carries to
+     * the next read
+     */
+    fn read_encoders(
+        &mut self,
+        distance: f32,
+        lateral_distance: f32,
+        rotation: f32,
+        cfg: &Config,
+    ) -> EncoderTicks {
+        let count = |residual: &mut f32, travel: f32, radius_bias: f32| {
+            let measured = travel / (1.0 + radius_bias)
+                + sample_normal(0.0, cfg.real_wheel_slip * travel.abs());
+            *residual += measured * cfg.encoder_ticks_per_unit;
+
+            let ticks = residual.trunc();
+            *residual -= ticks;
+            ticks as i32
+        };
+
+        let half_base = 0.5 * cfg.wheel_base;
+        EncoderTicks {
+            left: count(
+                &mut self.encoder_residual.0,
+                distance - half_base * rotation,
+                cfg.real_wheel_radius_bias_left,
+            ),
+            right: count(
+                &mut self.encoder_residual.1,
+                distance + half_base * rotation,
+                cfg.real_wheel_radius_bias_right,
+            ),
+            lateral: count(&mut self.encoder_residual.2, lateral_distance, 0.0),
         }
+    }
 
-        // needed for calculating x, y, and dir on next frame
-        self.prev_linear_velocity = noisy_linear_velocity;
-        self.prev_angular_velocity = noisy_angular_velocity;
+    /*
+     * gyroscope with white noise on top of a bias that drifts as a random
+     * walk, and optionally a two-axis accelerometer with white noise
+     */
+    pub fn sense_imu(&mut self, delta_time: f32, cfg: &Config) -> ImuReading {
+        self.gyro_bias += sample_normal(0.0, cfg.real_gyro_bias_walk * delta_time.sqrt());
+
+        ImuReading {
+            angular_velocity: self.angular_rate
+                + self.gyro_bias
+                + sample_normal(0.0, cfg.real_stdev_gyro),
+            acceleration: cfg.use_accelerometer.then(|| {
+                (
+                    self.acceleration.0 + sample_normal(0.0, cfg.real_stdev_accelerometer),
+                    self.acceleration.1 + sample_normal(0.0, cfg.real_stdev_accelerometer),
+                )
+            }),
+            delta_time,
+        }
+    }
+
+    /*
+     * absolute position at the configured rate, with white noise; there is
+     * no fix while the robot is inside an outage region
+     */
+    pub fn sense_gps(&mut self, delta_time: f32, outages: &[Rect], cfg: &Config) -> Option<GpsFix> {
+        if !cfg.use_gps {
+            return None;
+        }
+
+        self.gps_timer += delta_time;
+        if self.gps_timer < 1.0 / cfg.gps_rate {
+            return None;
+        }
+        self.gps_timer = 0.0;
+
+        if outages
+            .iter()
+            .any(|outage| outage.contains(vec2(self.x, self.y)))
+        {
+            return None;
+        }
+
+        Some(GpsFix {
+            x: self.x + sample_normal(0.0, cfg.real_stdev_gps),
+            y: self.y + sample_normal(0.0, cfg.real_stdev_gps),
+        })
     }
 
     pub fn sense(
         &mut self,
         landmarks: &[Landmark],
-        obstructions: &[Rect],
+        obstructions: &ObstructionGrid,
         cfg: &Config,
     ) -> Vec<Observation> {
         let mut observations = Vec::new();
@@ -105,20 +332,7 @@ impl Robot {
 
             let gt_range = (distance_x * distance_x + distance_y * distance_y).sqrt();
 
-            if gt_range < cfg.sensor_range {
-                let mut blocked = false; // flag for if current landmark is out of line of sight
-
-                for obstruction in obstructions.iter() {
-                    if self.liang_barsky(landmark, obstruction) {
-                        blocked = true;
-                        break;
-                    }
-                }
-
-                if blocked {
-                    continue;
-                }
-
+            if gt_range < cfg.sensor_range && gt_range >= cfg.sensor_min_range {
                 // absolute angle of landmark from robot
                 let absolute_angle = f32::atan2(distance_y, distance_x);
                 let relative_angle = absolute_angle - self.theta;
@@ -126,71 +340,133 @@ impl Robot {
                 // normalize ground truth bearing to (-PI, PI]
                 let gt_bearing = f32::atan2(relative_angle.sin(), relative_angle.cos());
 
-                let noisy_range = (gt_range + sample_normal(0.0, cfg.real_stdev_range)).max(0.0);
+                if gt_bearing.abs() > 0.5 * cfg.sensor_fov {
+                    continue;
+                }
+
+                // current landmark is out of line of sight
+                let blocked =
+                    obstructions.blocks(vec2(self.x, self.y), vec2(landmark.x, landmark.y));
+
+                if blocked || rand::gen_range(0.0, 1.0) < cfg.missed_detection_probability {
+                    continue;
+                }
+
+                let noisy_range =
+                    (gt_range + sample_normal(0.0, cfg.real_stdev_range_at(gt_range))).max(0.0);
                 let mut noisy_bearing = gt_bearing + sample_normal(0.0, cfg.real_stdev_bearing);
                 noisy_bearing = f32::atan2(noisy_bearing.sin(), noisy_bearing.cos()); // normalization
 
+                // occasionally report another landmark's id
+                let (id, ground_truth) = if landmarks.len() > 1
+                    && rand::gen_range(0.0, 1.0) < cfg.wrong_id_probability
+                {
+                    let mut wrong = landmarks[rand::gen_range(0, landmarks.len())].id;
+                    while wrong == landmark.id {
+                        wrong = landmarks[rand::gen_range(0, landmarks.len())].id;
+                    }
+                    (
+                        wrong,
+                        ObservationSource::WrongId {
+                            true_id: landmark.id,
+                        },
+                    )
+                } else {
+                    (landmark.id, ObservationSource::Landmark)
+                };
+
                 observations.push(Observation {
-                    id: landmark.id,
-                    range: noisy_range,
-                    bearing: noisy_bearing,
+                    id,
+                    range: cfg.sensor_mode.measures_range().then_some(noisy_range),
+                    bearing: cfg.sensor_mode.measures_bearing().then_some(noisy_bearing),
+                    ground_truth,
                 })
             }
         }
 
+        observations.extend(self.clutter(cfg));
+
         observations
     }
 
-    /*
-     * Liang Barsky algorithm to check if segment intersects rectangle
-     * https://en.wikipedia.org/wiki/Liang%E2%80%93Barsky_algorithm
-     */
-    fn liang_barsky(&self, landmark: &Landmark, rect: &Rect) -> bool {
-        let x_min = rect.x;
-        let y_min = rect.y;
-        let x_max = rect.x + rect.w;
-        let y_max = rect.y + rect.h;
-
-        let x1 = self.x;
-        let y1 = self.y;
-        let x2 = landmark.x;
-        let y2 = landmark.y;
-
-        let p = [-(x2 - x1), x2 - x1, -(y2 - y1), y2 - y1];
-        let q = [x1 - x_min, x_max - x1, y1 - y_min, y_max - y1];
-
-        let mut u1: f32 = 0.0;
-        let mut u2: f32 = 1.0;
-
-        for i in 0..4 {
-            let p_current = p[i];
-            let q_current = q[i];
-
-            if p_current == 0.0 {
-                if q_current < 0.0 {
-                    return false;
-                }
-            } else {
-                let t = q_current / p_current;
+    // a lidar sweep against the obstructions; landmarks are too thin to return anything
+    pub fn scan(&self, obstructions: &ObstructionGrid, cfg: &Config) -> LidarScan {
+        let position = vec2(self.x, self.y);
+        let ranges = (0..cfg.lidar_beams)
+            .map(|beam| {
+                let direction = Vec2::from_angle(self.theta + LidarScan::angle(beam, cfg));
+                let range =
+                    obstructions.raycast(position, position + direction * cfg.lidar_range)?;
+                Some((range + sample_normal(0.0, cfg.real_stdev_lidar)).max(0.0))
+            })
+            .collect();
+        LidarScan { ranges }
+    }
 
-                if p_current < 0.0 {
-                    if t > u2 {
-                        return false;
-                    }
-                    if t > u1 {
-                        u1 = t;
-                    }
-                } else {
-                    if t < u1 {
-                        return false;
-                    }
-                    if t < u2 {
-                        u2 = t;
-                    }
+    // the other robots in view, given every robot's position and this one's index
+    pub fn sense_robots(
+        &self,
+        index: usize,
+        positions: &[Vec2],
+        obstructions: &ObstructionGrid,
+        cfg: &Config,
+    ) -> Vec<RobotSighting> {
+        let position = vec2(self.x, self.y);
+        positions
+            .iter()
+            .enumerate()
+            .filter(|&(other, _)| other != index)
+            .filter_map(|(other, &target)| {
+                let offset = target - position;
+                let gt_range = offset.length();
+                let relative_angle = offset.y.atan2(offset.x) - self.theta;
+                let gt_bearing = f32::atan2(relative_angle.sin(), relative_angle.cos());
+                if gt_range >= cfg.sensor_range
+                    || gt_range < cfg.sensor_min_range
+                    || gt_bearing.abs() > 0.5 * cfg.sensor_fov
+                    || obstructions.blocks(position, target)
+                {
+                    return None;
                 }
-            }
-        }
 
-        u1 <= u2
+                let bearing = gt_bearing + sample_normal(0.0, cfg.real_stdev_bearing);
+                Some(RobotSighting {
+                    robot: other,
+                    range: (gt_range + sample_normal(0.0, cfg.real_stdev_range_at(gt_range)))
+                        .max(0.0),
+                    bearing: f32::atan2(bearing.sin(), bearing.cos()),
+                })
+            })
+            .collect()
+    }
+
+    /*
+     * spurious returns, Poisson distributed in number and uniform over the
+     * area of the sensor footprint (an annular sector)
+     */
+    fn clutter(&mut self, cfg: &Config) -> Vec<Observation> {
+        let count = sample_poisson(cfg.clutter_rate);
+        let min_range_sq = cfg.sensor_min_range.powi(2);
+        let max_range_sq = cfg.sensor_range.powi(2);
+
+        (0..count)
+            .map(|_| {
+                let id = self.next_clutter_id;
+                self.next_clutter_id = CLUTTER_ID_BASE + (id + 1 - CLUTTER_ID_BASE) % CLUTTER_IDS;
+
+                Observation {
+                    id,
+                    range: cfg.sensor_mode.measures_range().then(|| {
+                        (min_range_sq + rand::gen_range(0.0, 1.0) * (max_range_sq - min_range_sq))
+                            .sqrt()
+                    }),
+                    bearing: cfg
+                        .sensor_mode
+                        .measures_bearing()
+                        .then(|| rand::gen_range(-0.5 * cfg.sensor_fov, 0.5 * cfg.sensor_fov)),
+                    ground_truth: ObservationSource::Clutter,
+                }
+            })
+            .collect()
     }
 }
@@ -0,0 +1,63 @@
+use macroquad::prelude::Color;
+
+use crate::config::Config;
+use crate::simulation::{GpsFix, Observation};
+use crate::slam::Slam;
+use crate::slam::loop_closure::LoopClosure;
+use crate::slam::motion::Control;
+use crate::spatial_index::ObstructionGrid;
+
+/*
+ * integrates the controls alone and ignores the sensor, to show how far the
+ * selected motion model drifts without any correction
+ */
+pub struct DeadReckoning {
+    pub x: f32,
+    pub y: f32,
+    pub theta: f32,
+}
+
+impl DeadReckoning {
+    pub const COLOR: Color = Color::new(0.7, 0.7, 0.7, 0.5);
+
+    pub fn new() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            theta: 0.0,
+        }
+    }
+}
+
+impl Slam for DeadReckoning {
+    fn predict(&mut self, control: &Control, cfg: &Config) {
+        (self.x, self.y, self.theta) = control.apply((self.x, self.y, self.theta), cfg);
+    }
+
+    fn update(
+        &mut self,
+        _observations: &[Observation],
+        _obstructions: &ObstructionGrid,
+        _cfg: &Config,
+    ) {
+    }
+
+    fn update_position(&mut self, _fix: &GpsFix, _cfg: &Config) {}
+
+    fn get_state(&self) -> (f32, f32, f32) {
+        (self.x, self.y, self.theta)
+    }
+
+    fn get_landmarks(&self) -> Vec<(usize, f32, f32)> {
+        Vec::new()
+    }
+
+    // without a map there's no loop to close
+    fn take_loop_closures(&mut self) -> Vec<LoopClosure> {
+        Vec::new()
+    }
+
+    fn color(&self) -> Color {
+        Self::COLOR
+    }
+}
@@ -1,44 +1,589 @@
 use macroquad::prelude::Color;
-use nalgebra::{DMatrix, DVector, Matrix2, Matrix2x3, Vector2, stack};
-use std::collections::HashMap;
+use nalgebra::{DMatrix, DVector, Matrix2, Matrix2x3, Vector2};
+use std::collections::{HashMap, HashSet};
 
 use crate::config::Config;
-use crate::simulation::Observation;
+use crate::simulation::{GpsFix, ImuReading, Observation};
 use crate::slam::Slam;
-use crate::utils::{absolute_to_relative, relative_to_absolute};
+use crate::slam::existence::{LandmarkExistence, is_expected_visible};
+use crate::slam::loop_closure::{LoopClosure, LoopClosureDetector};
+use crate::slam::measurement::linearize;
+use crate::slam::motion::Control;
+use crate::spatial_index::ObstructionGrid;
+use crate::utils::{absolute_to_relative, relative_to_absolute, triangulate, trilaterate};
+
+// bearing rays and range circles kept per tentative landmark for delayed
+// initialization, and the distance the robot has to move before another one
+// is recorded
+const MAX_SIGHTINGS: usize = 32;
+const MIN_SIGHTING_SPACING: f32 = 5.0;
 
 pub struct EkfSlam {
     pub state: DVector<f32>,
     pub covariance: DMatrix<f32>,
     pub observed_landmarks: HashMap<usize, usize>, // maps ids to state index
+    pub landmark_existence: HashMap<usize, LandmarkExistence>, // existence of landmarks in the state
+    pub tentative_landmarks: HashMap<usize, TentativeLandmark>, // seen, but not yet in the state
+    pub estimates_gyro_bias: bool, // if set, the gyro bias follows the pose in the state
+    pub loop_closures: LoopClosureDetector,
+    since_update: f32, // seconds the next update's evidence covers
+}
+
+/*
+ * landmark that hasn't been seen enough times to be added to the state. the
+ * position is only a best guess for negative evidence; landmarks seen without
+ * a range or bearing also keep the rays or circles they were seen on, and wait
+ * for enough parallax to triangulate or trilaterate them before joining the
+ * state
+ */
+pub struct TentativeLandmark {
+    pub x: f32,
+    pub y: f32,
+    pub rays: Vec<(f32, f32, f32)>, // estimated robot x, y and absolute bearing
+    pub circles: Vec<(f32, f32, f32)>, // estimated robot x, y and measured range
+    pub existence: LandmarkExistence,
+    pub unseen: f32, // seconds since the last sighting
+}
+
+// records a sighting unless the robot hasn't moved far enough since the last one
+fn record_sighting(sightings: &mut Vec<(f32, f32, f32)>, sighting: (f32, f32, f32)) {
+    let far_enough = sightings.last().is_none_or(|last| {
+        (sighting.0 - last.0).powi(2) + (sighting.1 - last.1).powi(2)
+            >= MIN_SIGHTING_SPACING.powi(2)
+    });
+    if far_enough {
+        if sightings.len() == MAX_SIGHTINGS {
+            sightings.remove(0);
+        }
+        sightings.push(sighting);
+    }
+}
+
+fn angle_between(a: f32, b: f32) -> f32 {
+    f32::atan2((a - b).sin(), (a - b).cos()).abs()
+}
+
+// largest angle between the directions from the latest sighting and any other to a point
+fn parallax(sightings: &[(f32, f32, f32)], x: f32, y: f32) -> f32 {
+    let Some(&(latest_x, latest_y, _)) = sightings.last() else {
+        return 0.0;
+    };
+    let latest_direction = (y - latest_y).atan2(x - latest_x);
+
+    sightings
+        .iter()
+        .map(|&(origin_x, origin_y, _)| {
+            angle_between((y - origin_y).atan2(x - origin_x), latest_direction)
+        })
+        .fold(0.0, f32::max)
+}
+
+impl TentativeLandmark {
+    /*
+     * delayed triangulation of the bearing rays; returns the position and the
+     * largest angle the ray origins subtend at it. the parallax is measured
+     * from the baseline rather than the noisy bearings themselves, and every
+     * ray has to point at the result, so noise alone can't produce a landmark
+     */
+    fn triangulate(&self, cfg: &Config) -> Option<((f32, f32), f32)> {
+        let &(latest_x, latest_y, latest_angle) = self.rays.last()?;
+
+        // the bearings must spread out more than noise would, otherwise the
+        // rays are just as consistent with a landmark at infinity
+        let mean_angle = f32::atan2(
+            self.rays.iter().map(|ray| ray.2.sin()).sum(),
+            self.rays.iter().map(|ray| ray.2.cos()).sum(),
+        );
+        let mean_sq_deviation = self
+            .rays
+            .iter()
+            .map(|ray| angle_between(ray.2, mean_angle).powi(2))
+            .sum::<f32>()
+            / self.rays.len() as f32;
+        if mean_sq_deviation < (3.0 * cfg.est_stdev_bearing).powi(2) {
+            return None;
+        }
+
+        // must lie in front of the latest ray, not behind the robot
+        let (x, y) = triangulate(&self.rays)?;
+        let depth = (x - latest_x) * latest_angle.cos() + (y - latest_y) * latest_angle.sin();
+        if depth <= 0.0 {
+            return None;
+        }
+
+        // reject if any ray misses the point by more than the bearing noise allows
+        for &(origin_x, origin_y, angle) in &self.rays {
+            let direction = (y - origin_y).atan2(x - origin_x);
+            if angle_between(direction, angle) > 3.0 * cfg.est_stdev_bearing {
+                return None;
+            }
+        }
+
+        let parallax = parallax(&self.rays, x, y);
+        (parallax >= cfg.triangulation_min_parallax).then_some(((x, y), parallax))
+    }
+
+    /*
+     * delayed trilateration of the range circles; returns the position and
+     * the largest angle the circle centers subtend at it. a path that is
+     * close to a straight line can't tell a landmark from its mirror image
+     * across that line, so the mirror image must not fit the ranges as well
+     */
+    fn trilaterate(&self, cfg: &Config) -> Option<((f32, f32), f32)> {
+        if self.circles.len() < 3 {
+            return None;
+        }
+
+        let (x, y) = trilaterate(&self.circles)?;
+        let fits = |x: f32, y: f32| {
+            self.circles.iter().all(|&(center_x, center_y, range)| {
+                let distance = ((x - center_x).powi(2) + (y - center_y).powi(2)).sqrt();
+                (distance - range).abs() <= 3.0 * cfg.est_stdev_range_at(range)
+            })
+        };
+
+        // reject if any circle misses the point by more than the range noise allows
+        if !fits(x, y) {
+            return None;
+        }
+
+        // principal axis of the circle centers
+        let count = self.circles.len() as f32;
+        let mean_x = self.circles.iter().map(|circle| circle.0).sum::<f32>() / count;
+        let mean_y = self.circles.iter().map(|circle| circle.1).sum::<f32>() / count;
+        let (mut s_xx, mut s_xy, mut s_yy) = (0.0, 0.0, 0.0);
+        for &(center_x, center_y, _) in &self.circles {
+            s_xx += (center_x - mean_x).powi(2);
+            s_xy += (center_x - mean_x) * (center_y - mean_y);
+            s_yy += (center_y - mean_y).powi(2);
+        }
+        let axis = 0.5 * f32::atan2(2.0 * s_xy, s_xx - s_yy);
+
+        // reflection of the point across the principal axis
+        let offset = -(x - mean_x) * axis.sin() + (y - mean_y) * axis.cos();
+        let mirror_x = x + 2.0 * offset * axis.sin();
+        let mirror_y = y - 2.0 * offset * axis.cos();
+        if fits(mirror_x, mirror_y) {
+            return None;
+        }
+
+        let parallax = parallax(&self.circles, x, y);
+        (parallax >= cfg.triangulation_min_parallax).then_some(((x, y), parallax))
+    }
 }
 
 impl EkfSlam {
     pub const COLOR: Color = Color::new(0.0, 0.7, 1.0, 0.5);
+    pub const GYRO_COLOR: Color = Color::new(0.2, 1.0, 0.4, 0.5);
 
     pub fn new() -> Self {
         Self {
             state: DVector::from_element(3, 0.0), // initial state vector contains robot x, y, angle
             covariance: DMatrix::identity(3, 3) * 0.01, // size is 3 + 2L where L is the number of landmarks
             observed_landmarks: HashMap::new(),
+            landmark_existence: HashMap::new(),
+            tentative_landmarks: HashMap::new(),
+            estimates_gyro_bias: false,
+            loop_closures: LoopClosureDetector::new(),
+            since_update: 0.0,
         }
     }
 
     /*
-     * ekf landmark initialization step for full observations
+     * variant that fuses a gyro into the heading prediction and learns the
+     * gyro's bias; the bias sits right after the pose, so the state is
+     * x, y, angle, bias, then the landmarks
      */
-    fn initialize_landmark(&mut self, observation: &Observation, cfg: &Config) {
-        let old_len = self.state.nrows(); // old length of state vector
+    pub fn with_gyro_bias(cfg: &Config) -> Self {
+        let mut covariance = DMatrix::identity(4, 4) * 0.01;
+        covariance[(3, 3)] = cfg.est_stdev_gyro_bias.powi(2);
+
+        Self {
+            state: DVector::from_element(4, 0.0),
+            covariance,
+            estimates_gyro_bias: true,
+            ..Self::new()
+        }
+    }
+
+    // number of leading state entries that describe the robot rather than the map
+    fn robot_size(&self) -> usize {
+        if self.estimates_gyro_bias { 4 } else { 3 }
+    }
+
+    pub fn gyro_bias(&self) -> Option<f32> {
+        self.estimates_gyro_bias.then(|| self.state[3])
+    }
+
+    // each mapped landmark's estimated position and its marginal covariance
+    pub fn landmark_covariances(&self) -> Vec<(usize, Vector2<f32>, Matrix2<f32>)> {
+        self.observed_landmarks
+            .iter()
+            .map(|(&id, &index)| {
+                let mean = Vector2::new(self.state[index], self.state[index + 1]);
+                (
+                    id,
+                    mean,
+                    self.covariance.fixed_view::<2, 2>(index, index).into(),
+                )
+            })
+            .collect()
+    }
+
+    /*
+     * each mapped landmark with the fourth root of its covariance determinant:
+     * the standard deviation of a circle as large as its uncertainty ellipse
+     */
+    pub fn landmark_uncertainties(&self) -> Vec<(usize, f32, f32, f32)> {
+        self.landmark_covariances()
+            .into_iter()
+            .map(|(id, mean, covariance)| {
+                let spread = covariance.determinant().max(0.0).sqrt().sqrt();
+                (id, mean.x, mean.y, spread)
+            })
+            .collect()
+    }
+
+    pub fn predict_with_imu(&mut self, control: &Control, imu: &ImuReading, cfg: &Config) {
+        self.propagate(control, Some(imu), cfg);
+    }
+
+    /*
+     * follows the EKF sparse prediction equations from
+     * https://www.iri.upc.edu/people/jsola/JoanSola/objectes/curs_SLAM/SLAM2D/SLAM%20course.pdf
+     * when the gyro bias is estimated, the heading change is the inverse
+     * variance weighted mean of the control's, the bias-corrected gyro's and,
+     * if there is an accelerometer, the one implied by lateral acceleration
+     */
+    fn propagate(&mut self, control: &Control, imu: Option<&ImuReading>, cfg: &Config) {
+        debug_assert!(
+            self.covariance.is_square(),
+            "Covariance must be square matrix."
+        );
+        self.loop_closures.moved(control, cfg);
+        self.since_update += control.delta_time();
+
+        let theta = self.state[2];
+        let robot_size = self.robot_size();
+
+        // jacobian of the motion model function, and process noise in pose space
+        let (f_x, process_noise) = control.linearize(theta, cfg);
+        let mut f_r = DMatrix::<f32>::identity(robot_size, robot_size);
+        let mut q_r = DMatrix::<f32>::zeros(robot_size, robot_size);
+        f_r.view_mut((0, 0), (3, 3)).copy_from(&f_x);
+        q_r.view_mut((0, 0), (3, 3)).copy_from(&process_noise);
+
+        let mut motion = control.as_odometry(cfg);
+
+        if self.estimates_gyro_bias
+            && let Some(imu) = imu
+        {
+            let delta_time = imu.delta_time;
+            let control_variance = process_noise[(2, 2)].max(1e-12);
+            let gyro_variance = (cfg.est_stdev_gyro * delta_time).powi(2);
+
+            // heading change and its variance, from each source
+            let mut sources = vec![
+                (motion.rot1 + motion.rot2, control_variance),
+                (
+                    (imu.angular_velocity - self.state[3]) * delta_time,
+                    gyro_variance,
+                ),
+            ];
+
+            // lateral acceleration is speed times turn rate, whatever the gyro bias
+            if let Some((_, lateral)) = imu.acceleration {
+                let speed = motion.trans / delta_time;
+                if speed.abs() > 1.0 {
+                    sources.push((
+                        lateral / speed * delta_time,
+                        (cfg.est_stdev_accelerometer / speed * delta_time).powi(2),
+                    ));
+                }
+            }
+
+            let total_information: f32 = sources.iter().map(|source| 1.0 / source.1).sum();
+            let weight = |variance: f32| 1.0 / (variance * total_information);
+            let rotation: f32 = sources
+                .iter()
+                .map(|&(rotation, variance)| weight(variance) * rotation)
+                .sum();
+            let direction = 0.5 * (motion.rot1 - motion.rot2); // of sideways travel
+            motion.rot1 = 0.5 * rotation + direction;
+            motion.rot2 = 0.5 * rotation - direction;
+
+            // the control's heading noise is scaled by its weight, the other sources add theirs
+            let control_weight = weight(control_variance);
+            for i in 0..3 {
+                q_r[(2, i)] *= control_weight;
+                q_r[(i, 2)] *= control_weight;
+            }
+            q_r[(2, 2)] += sources[1..]
+                .iter()
+                .map(|&(_, variance)| weight(variance).powi(2) * variance)
+                .sum::<f32>();
+            q_r[(3, 3)] = cfg.est_gyro_bias_walk.powi(2) * delta_time;
+
+            // the heading change, and through it the position, depends on the bias
+            let (f_x, _) = Control::Odometry { motion, delta_time }.linearize(theta, cfg);
+            f_r.view_mut((0, 0), (3, 3)).copy_from(&f_x);
+
+            let heading = theta + motion.rot1;
+            let d_rotation = -weight(gyro_variance) * delta_time;
+            f_r[(0, 3)] = -0.5 * motion.trans * heading.sin() * d_rotation;
+            f_r[(1, 3)] = 0.5 * motion.trans * heading.cos() * d_rotation;
+            f_r[(2, 3)] = d_rotation;
+        }
+
+        // update pose estimate
+        let (x, y, theta) = motion.apply((self.state[0], self.state[1], theta));
+        self.state[0] = x;
+        self.state[1] = y;
+        self.state[2] = theta;
+
+        // update robot covariance block
+        let p_rr = self.covariance.view((0, 0), (robot_size, robot_size));
+        let new_p_rr = &f_r * p_rr * f_r.transpose() + q_r;
+        self.covariance
+            .view_mut((0, 0), (robot_size, robot_size))
+            .copy_from(&new_p_rr);
+
+        let map_size = self.covariance.ncols() - robot_size;
+
+        if map_size > 0 {
+            // update robot-map cross-covariance
+            let p_rm = self
+                .covariance
+                .view((0, robot_size), (robot_size, map_size))
+                .into_owned();
+            let new_p_rm = &f_r * p_rm;
+            self.covariance
+                .view_mut((0, robot_size), (robot_size, map_size))
+                .copy_from(&new_p_rm);
+
+            // update map-robot cross-covariance
+            self.covariance
+                .view_mut((robot_size, 0), (map_size, robot_size))
+                .copy_from(&new_p_rm.transpose());
+        }
+    }
+
+    /*
+     * counts a sighting of a landmark that isn't in the state yet, and adds
+     * it to the state once it has been confirmed (and, for bearing-only or
+     * range-only sightings, triangulated or trilaterated)
+     */
+    fn track_tentative_landmark(
+        &mut self,
+        observation: &Observation,
+        delta_time: f32,
+        cfg: &Config,
+    ) {
+        let (robot_x, robot_y, robot_theta) = (self.state[0], self.state[1], self.state[2]);
+
+        // without a range, guess the landmark sits halfway into the sensor
+        // footprint; without a bearing, guess it sits straight ahead
+        let guessed_range = observation
+            .range
+            .unwrap_or(0.5 * (cfg.sensor_min_range + cfg.sensor_range));
+        let guessed_bearing = observation.bearing.unwrap_or(0.0);
         let (x, y) = relative_to_absolute(
-            self.state[0],
-            self.state[1],
-            self.state[2],
-            observation.range,
-            observation.bearing,
+            robot_x,
+            robot_y,
+            robot_theta,
+            guessed_range,
+            guessed_bearing,
         );
 
+        let tentative = self
+            .tentative_landmarks
+            .entry(observation.id)
+            .and_modify(|tentative| {
+                tentative.x = x;
+                tentative.y = y;
+                tentative.existence.observed(delta_time, cfg);
+                tentative.unseen = 0.0;
+            })
+            .or_insert_with(|| TentativeLandmark {
+                x,
+                y,
+                rays: Vec::new(),
+                circles: Vec::new(),
+                existence: LandmarkExistence::new(delta_time, cfg),
+                unseen: 0.0,
+            });
+
+        if let Some(bearing) = observation.bearing {
+            record_sighting(
+                &mut tentative.rays,
+                (robot_x, robot_y, robot_theta + bearing),
+            );
+        }
+        if let Some(range) = observation.range {
+            record_sighting(&mut tentative.circles, (robot_x, robot_y, range));
+        }
+
+        // range, bearing and their uncertainties to initialize with, if the landmark is ready
+        let initialization = match (observation.range, observation.bearing) {
+            (Some(range), Some(bearing)) => Some((
+                range,
+                bearing,
+                cfg.est_stdev_range_at(range),
+                cfg.est_stdev_bearing,
+            )),
+            (None, Some(_)) => tentative.triangulate(cfg).map(|((x, y), parallax)| {
+                tentative.x = x;
+                tentative.y = y;
+
+                let (range, bearing) = absolute_to_relative(robot_x, robot_y, robot_theta, x, y);
+                let stdev_range = (range * cfg.est_stdev_bearing / parallax.sin())
+                    .max(cfg.est_stdev_range_at(range));
+                (range, bearing, stdev_range, cfg.est_stdev_bearing)
+            }),
+            (Some(_), None) => tentative.trilaterate(cfg).map(|((x, y), parallax)| {
+                tentative.x = x;
+                tentative.y = y;
+
+                let (range, bearing) = absolute_to_relative(robot_x, robot_y, robot_theta, x, y);
+                let stdev_range = cfg.est_stdev_range_at(range);
+                let stdev_bearing =
+                    (stdev_range / (range.max(1e-3) * parallax.sin())).max(cfg.est_stdev_bearing);
+                (range, bearing, stdev_range, stdev_bearing)
+            }),
+            (None, None) => None,
+        };
+
+        if !tentative.existence.is_confirmed() {
+            return;
+        }
+
+        if let Some((range, bearing, stdev_range, stdev_bearing)) = initialization {
+            if let Some(tentative) = self.tentative_landmarks.remove(&observation.id) {
+                self.landmark_existence
+                    .insert(observation.id, tentative.existence);
+            }
+            self.initialize_landmark(observation.id, range, bearing, stdev_range, stdev_bearing);
+        }
+    }
+
+    /*
+     * lowers the existence belief of every landmark that should have been
+     * seen from the current pose estimate but wasn't, and removes the ones
+     * that have become unlikely. tentative landmarks that haven't been seen
+     * for a while are dropped as well, since clutter seen from places the
+     * robot doesn't come back to would otherwise pile up
+     */
+    fn apply_negative_evidence(
+        &mut self,
+        observations: &[Observation],
+        obstructions: &ObstructionGrid,
+        delta_time: f32,
+        cfg: &Config,
+    ) {
+        let seen: HashSet<usize> = observations
+            .iter()
+            .map(|observation| observation.id)
+            .collect();
+        let (robot_x, robot_y, robot_theta) = (self.state[0], self.state[1], self.state[2]);
+
+        let mut removed = Vec::new();
+        for (&id, &index) in &self.observed_landmarks {
+            if seen.contains(&id)
+                || !is_expected_visible(
+                    robot_x,
+                    robot_y,
+                    robot_theta,
+                    self.state[index],
+                    self.state[index + 1],
+                    obstructions,
+                    cfg,
+                )
+            {
+                continue;
+            }
+
+            if let Some(existence) = self.landmark_existence.get_mut(&id) {
+                existence.missed(delta_time, cfg);
+                if existence.should_remove(cfg) {
+                    removed.push(id);
+                }
+            }
+        }
+
+        for id in removed {
+            self.remove_landmark(id);
+        }
+
+        self.tentative_landmarks.retain(|id, tentative| {
+            if seen.contains(id) {
+                return true;
+            }
+
+            tentative.unseen += delta_time;
+            if tentative.unseen > cfg.landmark_tentative_timeout {
+                return false;
+            }
+
+            if !is_expected_visible(
+                robot_x,
+                robot_y,
+                robot_theta,
+                tentative.x,
+                tentative.y,
+                obstructions,
+                cfg,
+            ) {
+                return true;
+            }
+
+            tentative.existence.missed(delta_time, cfg);
+            !tentative.existence.should_remove(cfg)
+        });
+    }
+
+    /*
+     * removes a landmark's rows and columns from the state and covariance,
+     * shifting the indices of the landmarks after it
+     */
+    fn remove_landmark(&mut self, id: usize) {
+        let Some(index) = self.observed_landmarks.remove(&id) else {
+            return;
+        };
+        self.landmark_existence.remove(&id);
+
+        // take ownership because remove_rows/remove_columns require values, not references
+        let state = std::mem::take(&mut self.state);
+        self.state = state.remove_rows(index, 2);
+
+        let covariance = std::mem::take(&mut self.covariance);
+        self.covariance = covariance.remove_rows(index, 2).remove_columns(index, 2);
+
+        for other_index in self.observed_landmarks.values_mut() {
+            if *other_index > index {
+                *other_index -= 2;
+            }
+        }
+    }
+
+    /*
+     * ekf landmark initialization step from a range and bearing; either one
+     * may come from triangulation or trilateration instead of the sensor,
+     * which is why their uncertainties are passed in
+     */
+    fn initialize_landmark(
+        &mut self,
+        id: usize,
+        range: f32,
+        bearing: f32,
+        stdev_range: f32,
+        stdev_bearing: f32,
+    ) {
+        let old_len = self.state.nrows(); // old length of state vector
+        let (x, y) =
+            relative_to_absolute(self.state[0], self.state[1], self.state[2], range, bearing);
+
         // update hashmap
-        self.observed_landmarks.insert(observation.id, old_len);
+        self.observed_landmarks.insert(id, old_len);
 
         // take ownership of state because resize_vertically requires value, not reference
         let mut state = std::mem::take(&mut self.state);
@@ -51,36 +596,31 @@ impl EkfSlam {
 
         // calculate new values for covariance
         let theta = self.state[2];
-        let absolute_angle = theta + observation.bearing;
+        let absolute_angle = theta + bearing;
 
         // jacobian of landmark position with respect to robot state
         let g_r = Matrix2x3::new(
             1.0,
             0.0,
-            -observation.range * absolute_angle.sin(),
+            -range * absolute_angle.sin(),
             0.0,
             1.0,
-            observation.range * absolute_angle.cos(),
+            range * absolute_angle.cos(),
         );
 
         // jacobian of landmark position with respect to observation
         let g_y = Matrix2::new(
             absolute_angle.cos(),
-            -observation.range * absolute_angle.sin(),
+            -range * absolute_angle.sin(),
             absolute_angle.sin(),
-            observation.range * absolute_angle.cos(),
+            range * absolute_angle.cos(),
         );
 
         // covariance of landmark
         let p_rr = self.covariance.fixed_view::<3, 3>(0, 0);
 
         // sensor noise
-        let r = Matrix2::new(
-            cfg.est_stdev_range.powi(2),
-            0.0,
-            0.0,
-            cfg.est_stdev_bearing.powi(2),
-        );
+        let r = Matrix2::new(stdev_range.powi(2), 0.0, 0.0, stdev_bearing.powi(2));
 
         // landmark covariance
         let p_ll = (g_r * p_rr * g_r.transpose()) + (g_y * r * g_y.transpose());
@@ -111,50 +651,19 @@ impl EkfSlam {
     }
 
     /*
-     * ekf correction step
+     * ekf correction step; only the robot and landmark blocks of the
+     * jacobian are nonzero, so the products are taken blockwise
      */
     fn correct_landmark(&mut self, observation: &Observation, landmark_index: usize, cfg: &Config) {
-        let robot_x = self.state[0];
-        let robot_y = self.state[1];
-
-        let landmark_x = self.state[landmark_index];
-        let landmark_y = self.state[landmark_index + 1];
-
-        // predicted measurement and innovation
-        let (predicted_range, predicted_bearing) =
-            absolute_to_relative(robot_x, robot_y, self.state[2], landmark_x, landmark_y);
-        let range_difference = observation.range - predicted_range;
-        let bearing_difference = f32::atan2(
-            (observation.bearing - predicted_bearing).sin(),
-            (observation.bearing - predicted_bearing).cos(),
-        );
-
-        // innovation vector
-        let z = Vector2::new(range_difference, bearing_difference);
-
-        // distance to landmark
-        let distance_x = landmark_x - robot_x;
-        let distance_y = landmark_y - robot_y;
-        let distance_sq = (distance_x * distance_x + distance_y * distance_y).max(1e-6);
-        let distance = distance_sq.sqrt();
-
-        // jacobian with respect to robot
-        let h_r = Matrix2x3::new(
-            -distance_x / distance,
-            -distance_y / distance,
-            0.0,
-            distance_y / distance_sq,
-            -distance_x / distance_sq,
-            -1.0,
-        );
-
-        // jacobian with respect to landmark
-        let h_l = Matrix2::new(
-            distance_x / distance,
-            distance_y / distance,
-            -distance_y / distance_sq,
-            distance_x / distance_sq,
+        let model = linearize(
+            (self.state[0], self.state[1], self.state[2]),
+            self.state[landmark_index],
+            self.state[landmark_index + 1],
+            observation,
+            cfg,
         );
+        let h_r = &model.h_robot;
+        let h_l = &model.h_landmark;
 
         // innovation covariance calculation
         let p_rr = self.covariance.fixed_view::<3, 3>(0, 0); // robot-robot covariance
@@ -162,26 +671,13 @@ impl EkfSlam {
             .covariance
             .fixed_view::<2, 2>(landmark_index, landmark_index); // landmark-landmark covariance
         let p_rl = self.covariance.fixed_view::<3, 2>(0, landmark_index); // robot-landmark covariance
-        let p_lr = p_rl.transpose(); // landmark-robot covariance
-
-        // sensor noise
-        let r = Matrix2::new(
-            cfg.est_stdev_range.powi(2),
-            0.0,
-            0.0,
-            cfg.est_stdev_bearing.powi(2),
-        );
-
-        // block matrices
-        let h_block = stack![h_r, h_l];
-        let p_block = stack![
-            p_rr, p_rl;
-            p_lr, p_ll
-        ];
-        let h_t_block = h_block.transpose();
 
-        // innovation matrix
-        let z_matrix = h_block * p_block * h_t_block + r;
+        let h_rl = h_r * p_rl * h_l.transpose();
+        let z_matrix = h_r * p_rr * h_r.transpose()
+            + &h_rl
+            + h_rl.transpose()
+            + h_l * p_ll * h_l.transpose()
+            + &model.noise;
 
         // calculate product of covariance with jacobian transpose (PH^T)
         let total_map_size = self.state.nrows();
@@ -192,10 +688,10 @@ impl EkfSlam {
         let p_ht = (p_cols_robot * h_r.transpose()) + (p_cols_landmark * h_l.transpose());
 
         // Kalman gain
-        let k = p_ht * z_matrix.try_inverse().unwrap();
+        let k = p_ht * z_matrix.clone().try_inverse().unwrap();
 
         // update state and covariance
-        self.state = &self.state + &k * z;
+        self.state = &self.state + &k * &model.innovation;
         self.covariance = &self.covariance - &k * z_matrix * k.transpose();
 
         // force matrix to be symmetric to (hopefully) prevent covariance from exploding
@@ -207,106 +703,78 @@ impl EkfSlam {
 }
 
 impl Slam for EkfSlam {
+    fn predict(&mut self, control: &Control, cfg: &Config) {
+        self.propagate(control, None, cfg);
+    }
+
     /*
-     * follows the EKF sparse prediction equations from
+     * Follows EKF sparse prediction equations from
      * https://www.iri.upc.edu/people/jsola/JoanSola/objectes/curs_SLAM/SLAM2D/SLAM%20course.pdf
      */
-    fn predict(
+    fn update(
         &mut self,
-        linear_velocity: f32,
-        angular_velocity: f32,
-        delta_time: f32,
+        observations: &[Observation],
+        obstructions: &ObstructionGrid,
         cfg: &Config,
     ) {
-        debug_assert!(
-            self.covariance.is_square(),
-            "Covariance must be square matrix."
-        );
-
-        let theta = self.state[2];
-        let theta_half = theta + 0.5 * angular_velocity * delta_time; // approximate heading of the robot at the middle of the frame
-
-        // update position estimate
-        self.state[0] += linear_velocity * delta_time * theta_half.cos();
-        self.state[1] += linear_velocity * delta_time * theta_half.sin();
-        self.state[2] += angular_velocity * delta_time;
-
-        // normalize angle to (-PI, PI]
-        self.state[2] = f32::atan2(self.state[2].sin(), self.state[2].cos());
+        let stale = self
+            .loop_closures
+            .stale(observations.iter().map(|observation| observation.id), cfg)
+            .into_iter()
+            .filter_map(|id| {
+                let &index = self.observed_landmarks.get(&id)?;
+                Some((id, (self.state[index], self.state[index + 1])))
+            })
+            .collect();
+        let before = self.get_state();
+        let mut seen = Vec::new();
+        let delta_time = std::mem::take(&mut self.since_update);
 
-        // jacobian of the motion model function
-        let f_x = nalgebra::Matrix3::new(
-            1.0,
-            0.0,
-            -linear_velocity * delta_time * theta_half.sin(),
-            0.0,
-            1.0,
-            linear_velocity * delta_time * theta_half.cos(),
-            0.0,
-            0.0,
-            1.0,
-        );
-
-        // covariance of control noise
-        let sigma_linear_velocity = cfg.est_stdev_linear * linear_velocity.abs() + 0.01; // add 0.01 so noise doesn't vanish at 0 speed
-        let sigma_angular_velocity = cfg.est_stdev_angular * angular_velocity.abs() + 0.01;
-        let n = nalgebra::Matrix2::new(
-            (sigma_linear_velocity).powi(2),
-            0.0,
-            0.0,
-            (sigma_angular_velocity).powi(2),
-        );
-
-        // jacobian of control noise (assumes noise is on controls, not state
-        // and noise is indepentend between linear velocity and angular velocity)
-        let f_n = nalgebra::Matrix3x2::new(
-            theta_half.cos() * delta_time,
-            0.0,
-            theta_half.sin() * delta_time,
-            0.0,
-            0.0,
-            delta_time,
-        );
-
-        // update robot covariance block
-        let p_rr = self.covariance.fixed_view::<3, 3>(0, 0);
-        let new_p_rr = (f_x * p_rr * f_x.transpose()) + (f_n * n * f_n.transpose());
-        self.covariance
-            .fixed_view_mut::<3, 3>(0, 0)
-            .copy_from(&new_p_rr);
-
-        let map_size = self.covariance.ncols() - 3;
-
-        if map_size > 0 {
-            // update robot-map cross-covariance
-            let p_rm = self.covariance.view((0, 3), (3, map_size)).into_owned();
-            let new_p_rm = f_x * p_rm;
-            self.covariance
-                .view_mut((0, 3), (3, map_size))
-                .copy_from(&new_p_rm);
-
-            // update map-robot cross-covariance
-            self.covariance
-                .view_mut((3, 0), (map_size, 3))
-                .copy_from(&new_p_rm.transpose());
-        }
-    }
-
-    /*
-     * Follows EKF sparse prediction equations from
-     * https://www.iri.upc.edu/people/jsola/JoanSola/objectes/curs_SLAM/SLAM2D/SLAM%20course.pdf
-     */
-    fn update(&mut self, observations: &[Observation], cfg: &Config) {
         for observation in observations.iter() {
             match self.observed_landmarks.get(&observation.id) {
                 Some(&landmark_index) => {
+                    seen.push(observation.id);
                     self.correct_landmark(observation, landmark_index, cfg);
+                    if let Some(existence) = self.landmark_existence.get_mut(&observation.id) {
+                        existence.observed(delta_time, cfg);
+                    }
                 }
                 None => {
-                    self.initialize_landmark(observation, cfg);
+                    self.track_tentative_landmark(observation, delta_time, cfg);
                 }
             }
         }
+
+        self.apply_negative_evidence(observations, obstructions, delta_time, cfg);
+        self.loop_closures
+            .observed(seen.into_iter(), stale, before, self.get_state());
+    }
+
+    /*
+     * the fix measures the robot position directly, so the jacobian is the
+     * identity on x, y and zero elsewhere
+     */
+    fn update_position(&mut self, fix: &GpsFix, cfg: &Config) {
+        let total_size = self.state.nrows();
+        let innovation = Vector2::new(fix.x - self.state[0], fix.y - self.state[1]);
+
+        // innovation covariance
+        let z_matrix = self.covariance.fixed_view::<2, 2>(0, 0)
+            + Matrix2::identity() * cfg.est_stdev_gps.powi(2);
+
+        // Kalman gain
+        let p_ht = self.covariance.view((0, 0), (total_size, 2));
+        let k = p_ht * z_matrix.try_inverse().unwrap();
+
+        // update state and covariance
+        self.state += &k * innovation;
+        self.covariance -= &k * z_matrix * k.transpose();
+
+        // force matrix to be symmetric
+        self.covariance = (&self.covariance + self.covariance.transpose()) / 2.0;
+
+        // normalize angle
+        self.state[2] = f32::atan2(self.state[2].sin(), self.state[2].cos());
     }
 
     fn get_state(&self) -> (f32, f32, f32) {
@@ -323,7 +791,51 @@ impl Slam for EkfSlam {
         landmarks
     }
 
+    fn take_loop_closures(&mut self) -> Vec<LoopClosure> {
+        self.loop_closures.take()
+    }
+
     fn color(&self) -> Color {
-        Self::COLOR
+        if self.estimates_gyro_bias {
+            Self::GYRO_COLOR
+        } else {
+            Self::COLOR
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::ObservationSource;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn drops_tentative_landmarks_it_stops_seeing() {
+        let cfg = Config::default();
+        let obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+        let mut ekf_slam = EkfSlam::new();
+        let standing = Control::Velocity {
+            command: Vector3::zeros(),
+            drive: cfg.drive,
+            delta_time: 1.0 / 60.0,
+        };
+
+        // clutter at the edge of the range, where it isn't expected to show up again
+        let clutter = Observation {
+            id: 7,
+            range: Some(cfg.sensor_range),
+            bearing: Some(0.0),
+            ground_truth: ObservationSource::Clutter,
+        };
+        ekf_slam.predict(&standing, &cfg);
+        ekf_slam.update(&[clutter], &obstructions, &cfg);
+        assert!(ekf_slam.tentative_landmarks.contains_key(&7));
+
+        for _ in 0..(cfg.landmark_tentative_timeout * 60.0) as usize + 2 {
+            ekf_slam.predict(&standing, &cfg);
+            ekf_slam.update(&[], &obstructions, &cfg);
+        }
+        assert!(ekf_slam.tentative_landmarks.is_empty());
     }
 }
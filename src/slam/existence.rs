@@ -0,0 +1,119 @@
+use macroquad::prelude::vec2;
+
+use crate::config::Config;
+use crate::spatial_index::ObstructionGrid;
+
+// keeps log odds from saturating so a long-lived landmark can still be removed
+const MAX_LOG_ODDS: f32 = 8.0;
+
+/*
+ * landmark existence belief, tracked in log odds. landmarks start out
+ * tentative and are confirmed after enough sightings; the time a landmark
+ * should have been seen but wasn't lowers the belief until it is removed.
+ * evidence accrues at a rate per second of sightings or misses, so it doesn't
+ * depend on the frame rate; the sighting count does, one per update
+ */
+#[derive(Clone)]
+pub struct LandmarkExistence {
+    pub confirmations: u32,
+    pub misses: u32,
+    confirmed: bool,
+    log_odds: f32,
+}
+
+impl LandmarkExistence {
+    // belief after the first sighting, by an update covering delta_time seconds
+    pub fn new(delta_time: f32, cfg: &Config) -> Self {
+        Self {
+            confirmations: 1,
+            misses: 0,
+            confirmed: cfg.landmark_confirmations <= 1,
+            log_odds: cfg.landmark_log_odds_hit * delta_time,
+        }
+    }
+
+    pub fn observed(&mut self, delta_time: f32, cfg: &Config) {
+        self.confirmations += 1;
+        self.confirmed |= self.confirmations >= cfg.landmark_confirmations;
+        self.log_odds = (self.log_odds + cfg.landmark_log_odds_hit * delta_time).min(MAX_LOG_ODDS);
+    }
+
+    pub fn missed(&mut self, delta_time: f32, cfg: &Config) {
+        self.misses += 1;
+        self.log_odds =
+            (self.log_odds + cfg.landmark_log_odds_miss * delta_time).max(-MAX_LOG_ODDS);
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed
+    }
+
+    pub fn probability(&self) -> f32 {
+        1.0 / (1.0 + (-self.log_odds).exp())
+    }
+
+    pub fn should_remove(&self, cfg: &Config) -> bool {
+        self.probability() < cfg.landmark_removal_probability
+    }
+}
+
+/*
+ * whether a landmark at the given position should show up in a scan taken
+ * from the given pose; a margin of a couple of deviations keeps landmarks at
+ * the edge of the sensor footprint from being penalized for noise
+ */
+pub fn is_expected_visible(
+    robot_x: f32,
+    robot_y: f32,
+    robot_theta: f32,
+    landmark_x: f32,
+    landmark_y: f32,
+    obstructions: &ObstructionGrid,
+    cfg: &Config,
+) -> bool {
+    let distance_x = landmark_x - robot_x;
+    let distance_y = landmark_y - robot_y;
+    let range = (distance_x * distance_x + distance_y * distance_y).sqrt();
+
+    let range_margin = 2.0 * cfg.est_stdev_range_at(range);
+    if range > cfg.sensor_range - range_margin || range < cfg.sensor_min_range + range_margin {
+        return false;
+    }
+
+    let mut bearing = distance_y.atan2(distance_x) - robot_theta;
+    bearing = f32::atan2(bearing.sin(), bearing.cos()); // normalize to (-PI, PI]
+    // a full circle has no edge to stay clear of
+    if cfg.sensor_fov < std::f32::consts::TAU
+        && bearing.abs() > 0.5 * cfg.sensor_fov - 2.0 * cfg.est_stdev_bearing
+    {
+        return false;
+    }
+
+    !obstructions.blocks(vec2(robot_x, robot_y), vec2(landmark_x, landmark_y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // belief after a tenth of a second of sightings and then one of misses
+    fn after_a_tenth_each(frame_rate: u32, cfg: &Config) -> f32 {
+        let delta_time = 1.0 / frame_rate as f32;
+        let mut existence = LandmarkExistence::new(delta_time, cfg);
+        for _ in 1..frame_rate / 10 {
+            existence.observed(delta_time, cfg);
+        }
+        for _ in 0..frame_rate / 10 {
+            existence.missed(delta_time, cfg);
+        }
+        existence.probability()
+    }
+
+    #[test]
+    fn evidence_does_not_depend_on_the_frame_rate() {
+        let cfg = Config::default();
+        let slow = after_a_tenth_each(20, &cfg);
+        let fast = after_a_tenth_each(240, &cfg);
+        assert!((slow - fast).abs() < 1e-3, "{slow} vs {fast}");
+    }
+}
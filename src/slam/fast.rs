@@ -1,16 +1,23 @@
 use macroquad::prelude::Color;
-use nalgebra::{Matrix2, Vector2};
-use std::collections::HashMap;
+use nalgebra::{DMatrix, DVector, Matrix2, Vector2};
+use std::collections::{HashMap, HashSet};
 
 use crate::config::Config;
-use crate::simulation::Observation;
+use crate::simulation::{GpsFix, Observation};
 use crate::slam::Slam;
-use crate::utils::{absolute_to_relative, relative_to_absolute, sample_normal};
+use crate::slam::existence::{LandmarkExistence, is_expected_visible};
+use crate::slam::landmark_tree::LandmarkTree;
+use crate::slam::loop_closure::{LoopClosure, LoopClosureDetector};
+use crate::slam::measurement::{linearize, log_likelihood};
+use crate::slam::motion::Control;
+use crate::spatial_index::ObstructionGrid;
+use crate::utils::{Rng, available_threads, par_for_each_mut, relative_to_absolute};
 
 #[derive(Clone)]
 pub struct LandmarkEstimate {
     pub mu: Vector2<f32>,
     pub sigma: Matrix2<f32>,
+    pub existence: LandmarkExistence,
 }
 
 #[derive(Clone)]
@@ -18,114 +25,411 @@ pub struct Particle {
     pub x: f32,
     pub y: f32,
     pub theta: f32,
-    pub weight: f32,
-    pub landmarks: HashMap<usize, LandmarkEstimate>,
+    pub log_weight: f32, // kept in log-space so products of likelihoods don't underflow
+    pub landmarks: LandmarkTree<LandmarkEstimate>, // shared copy-on-write between resampled particles
+    pub pending_landmarks: LandmarkTree<LandmarkHypotheses>, // bearing- or range-only landmarks not yet localized
 }
 
 pub struct FastSlam {
     pub particles: Vec<Particle>,
     pub num_particles: usize,
+    pub num_threads: usize,
+    pub loop_closures: LoopClosureDetector,
+    since_update: f32, // seconds the next update's evidence covers
+    rng: Rng,          // master generator; per-particle streams are forked from it each step
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResamplingScheme {
+    Systematic,
+    Stratified,
+    Multinomial,
+    Residual,
+}
+
+impl ResamplingScheme {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Systematic => Self::Stratified,
+            Self::Stratified => Self::Multinomial,
+            Self::Multinomial => Self::Residual,
+            Self::Residual => Self::Systematic,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Systematic => "systematic",
+            Self::Stratified => "stratified",
+            Self::Multinomial => "multinomial",
+            Self::Residual => "residual",
+        }
+    }
+}
+
+/*
+ * gaussian sum over where an undelayed bearing-only (or range-only) landmark
+ * could be along the ray (or circle) it was first seen on; each component is
+ * refined like an ordinary landmark and reweighted by how well it explains
+ * later observations, until one dominates and the landmark is promoted
+ */
+#[derive(Clone)]
+pub struct LandmarkHypotheses {
+    pub components: Vec<HypothesisComponent>,
+    pub existence: LandmarkExistence,
+}
+
+#[derive(Clone)]
+pub struct HypothesisComponent {
+    pub log_weight: f32,
+    pub mu: Vector2<f32>,
+    pub sigma: Matrix2<f32>,
+}
+
+// hypotheses less likely than this (relative to the sum) are dropped
+const MIN_HYPOTHESIS_WEIGHT: f32 = 1e-3;
+// a hypothesis this likely is promoted to a landmark
+const PROMOTE_HYPOTHESIS_WEIGHT: f32 = 0.9;
+// the gaussian sum's spread ratio is kept within these, since a ratio of zero
+// never covers the range and one of one or more spaces by nonsense ratios;
+// it's widened where covering the sensor footprint would take more hypotheses
+// than the cap
+const MIN_SPREAD: f32 = 0.01;
+const MAX_SPREAD: f32 = 0.9;
+const MAX_HYPOTHESES: usize = 64;
+
+impl LandmarkHypotheses {
+    fn best(&self) -> Option<&HypothesisComponent> {
+        self.components
+            .iter()
+            .max_by(|a, b| a.log_weight.total_cmp(&b.log_weight))
+    }
+
+    // shifts log weights so that they sum to one, then prunes unlikely components
+    fn normalize_and_prune(&mut self) {
+        let max_log_weight = self
+            .components
+            .iter()
+            .map(|component| component.log_weight)
+            .fold(f32::NEG_INFINITY, f32::max);
+        if !max_log_weight.is_finite() {
+            return;
+        }
+
+        let log_total = max_log_weight
+            + self
+                .components
+                .iter()
+                .map(|component| (component.log_weight - max_log_weight).exp())
+                .sum::<f32>()
+                .ln();
+
+        for component in &mut self.components {
+            component.log_weight -= log_total;
+        }
+        self.components
+            .retain(|component| component.log_weight > MIN_HYPOTHESIS_WEIGHT.ln());
+    }
+}
+
+/*
+ * gaussian landmark estimate from a range and bearing seen from a known pose
+ */
+fn landmark_from_observation(
+    robot: (f32, f32, f32),
+    range: f32,
+    bearing: f32,
+    stdev_range: f32,
+    stdev_bearing: f32,
+) -> (Vector2<f32>, Matrix2<f32>) {
+    let (robot_x, robot_y, robot_theta) = robot;
+    let absolute_angle = robot_theta + bearing;
+    let (landmark_x, landmark_y) =
+        relative_to_absolute(robot_x, robot_y, robot_theta, range, bearing);
+
+    // jacobian of landmark position with respect to observation
+    let g_y = Matrix2::new(
+        absolute_angle.cos(),
+        -range * absolute_angle.sin(),
+        absolute_angle.sin(),
+        range * absolute_angle.cos(),
+    );
+
+    // sensor noise
+    let r = Matrix2::new(stdev_range.powi(2), 0.0, 0.0, stdev_bearing.powi(2));
+
+    // landmark covariance
+    let p_ll = g_y * r * g_y.transpose();
+
+    (Vector2::new(landmark_x, landmark_y), p_ll)
+}
+
+/*
+ * ekf update of a single landmark gaussian; returns the log likelihood of the
+ * innovation, which is what the particle (or hypothesis) gets weighted by
+ */
+fn correct_gaussian(
+    mu: &mut Vector2<f32>,
+    sigma: &mut Matrix2<f32>,
+    robot: (f32, f32, f32),
+    observation: &Observation,
+    cfg: &Config,
+) -> f32 {
+    let model = linearize(robot, mu.x, mu.y, observation, cfg);
+    let h_l = &model.h_landmark;
+
+    // innovation matrix
+    let z_matrix = h_l * *sigma * h_l.transpose() + &model.noise;
+    let log_likelihood = log_likelihood(&model.innovation, &z_matrix);
+
+    // Kalman gain
+    let k = *sigma * h_l.transpose() * z_matrix.try_inverse().unwrap();
+
+    // update state
+    *mu += &k * &model.innovation;
+
+    // update covariance
+    *sigma = (Matrix2::identity() - &k * h_l) * *sigma;
+
+    log_likelihood
 }
 
 impl Particle {
-    fn initialize_landmark(&mut self, observation: &Observation, cfg: &Config) {
-        let absolute_angle = self.theta + observation.bearing;
-        let (landmark_x, landmark_y) = relative_to_absolute(
-            self.x,
-            self.y,
-            self.theta,
-            observation.range,
-            observation.bearing,
-        );
+    fn pose(&self) -> (f32, f32, f32) {
+        (self.x, self.y, self.theta)
+    }
 
-        // jacobian of landmark position with respect to observation
-        let g_y = Matrix2::new(
-            absolute_angle.cos(),
-            -observation.range * absolute_angle.sin(),
-            absolute_angle.sin(),
-            observation.range * absolute_angle.cos(),
-        );
+    // routes an observation to the landmark, hypotheses or initializer it belongs to
+    fn observe(&mut self, observation: &Observation, delta_time: f32, cfg: &Config) {
+        if self.landmarks.contains_key(observation.id) {
+            self.correct_landmark(observation, delta_time, cfg);
+        } else if self.pending_landmarks.contains_key(observation.id) {
+            self.correct_hypotheses(observation, delta_time, cfg);
+        } else if let (Some(range), Some(bearing)) = (observation.range, observation.bearing) {
+            self.initialize_landmark(observation.id, range, bearing, delta_time, cfg);
+        } else {
+            self.initialize_hypotheses(observation, delta_time, cfg);
+        }
+    }
 
-        // sensor noise
-        let r = Matrix2::new(
-            cfg.est_stdev_range.powi(2),
-            0.0,
-            0.0,
-            cfg.est_stdev_bearing.powi(2),
+    fn initialize_landmark(
+        &mut self,
+        id: usize,
+        range: f32,
+        bearing: f32,
+        delta_time: f32,
+        cfg: &Config,
+    ) {
+        let (mu, sigma) = landmark_from_observation(
+            self.pose(),
+            range,
+            bearing,
+            cfg.est_stdev_range_at(range),
+            cfg.est_stdev_bearing,
         );
 
-        // landmark covariance
-        let p_ll = g_y * r * g_y.transpose();
-
         // create and insert the landmark
         let new_landmark = LandmarkEstimate {
-            mu: Vector2::new(landmark_x, landmark_y),
-            sigma: p_ll,
+            mu,
+            sigma,
+            existence: LandmarkExistence::new(delta_time, cfg),
         };
 
-        self.landmarks.insert(observation.id, new_landmark);
+        self.landmarks.insert(id, new_landmark);
     }
 
-    fn correct_landmark(&mut self, observation: &Observation, cfg: &Config) {
-        if let Some(landmark) = self.landmarks.get_mut(&observation.id) {
-            // compute distances
-            let distance_x = landmark.mu.x - self.x;
-            let distance_y = landmark.mu.y - self.y;
-            let distance_sq = (distance_x * distance_x) + (distance_y * distance_y);
-            let distance = distance_sq.sqrt();
+    /*
+     * spreads gaussians along the bearing ray at geometrically spaced depths,
+     * each one as wide as the spread ratio times its depth
+     * https://doi.org/10.1109/ROBOT.2004.1308834
+     * or, for a range-only sighting, around the part of the range circle
+     * inside the field of view, each one spanning the spread ratio in bearing
+     */
+    fn initialize_hypotheses(&mut self, observation: &Observation, delta_time: f32, cfg: &Config) {
+        let spread = cfg.gaussian_sum_spread.clamp(MIN_SPREAD, MAX_SPREAD);
+        let mut components = Vec::new();
+        let mut add_component = |range: f32, bearing: f32, stdev_range: f32, stdev_bearing: f32| {
+            let (mu, sigma) =
+                landmark_from_observation(self.pose(), range, bearing, stdev_range, stdev_bearing);
+            components.push(HypothesisComponent {
+                log_weight: 0.0,
+                mu,
+                sigma,
+            });
+        };
+
+        match (observation.range, observation.bearing) {
+            (None, Some(bearing)) => {
+                let near = cfg.sensor_min_range.max(cfg.robot_radius);
+                let ratio = ((1.0 + spread) / (1.0 - spread))
+                    .max((cfg.sensor_range / near).powf(1.0 / MAX_HYPOTHESES as f32));
+                let spread = (ratio - 1.0) / (ratio + 1.0);
+                // the first reaches in to the near edge, unless that puts it out of range
+                let mut range = (near / (1.0 - spread)).min(cfg.sensor_range);
+                while range <= cfg.sensor_range {
+                    add_component(range, bearing, spread * range, cfg.est_stdev_bearing);
+                    range *= ratio;
+                }
+            }
+            (Some(range), None) => {
+                let count = ((cfg.sensor_fov / (2.0 * spread)).ceil().max(1.0) as usize)
+                    .min(MAX_HYPOTHESES);
+                let step = cfg.sensor_fov / count as f32;
+                let spread = spread.max(0.5 * step);
+                for i in 0..count {
+                    let bearing = -0.5 * cfg.sensor_fov + (i as f32 + 0.5) * step;
+                    add_component(range, bearing, cfg.est_stdev_range_at(range), spread);
+                }
+            }
+            _ => return,
+        }
 
-            let (predicted_range, predicted_bearing) =
-                absolute_to_relative(self.x, self.y, self.theta, landmark.mu.x, landmark.mu.y);
+        let mut hypotheses = LandmarkHypotheses {
+            components,
+            existence: LandmarkExistence::new(delta_time, cfg),
+        };
+        hypotheses.normalize_and_prune();
 
-            let range_difference = observation.range - predicted_range;
-            let bearing_difference = f32::atan2(
-                (observation.bearing - predicted_bearing).sin(),
-                (observation.bearing - predicted_bearing).cos(),
-            );
+        self.pending_landmarks.insert(observation.id, hypotheses);
+    }
 
-            // innovation vector
-            let z = Vector2::new(range_difference, bearing_difference);
+    fn correct_landmark(&mut self, observation: &Observation, delta_time: f32, cfg: &Config) {
+        let robot = self.pose();
 
-            // jacobian with respect to landmark
-            let h_l = Matrix2::new(
-                distance_x / distance,
-                distance_y / distance,
-                -distance_y / distance_sq,
-                distance_x / distance_sq,
+        if let Some(landmark) = self.landmarks.get_mut(observation.id) {
+            let log_likelihood = correct_gaussian(
+                &mut landmark.mu,
+                &mut landmark.sigma,
+                robot,
+                observation,
+                cfg,
             );
 
-            // sensor noise
-            let r = Matrix2::new(
-                cfg.est_stdev_range.powi(2),
-                0.0,
-                0.0,
-                cfg.est_stdev_bearing.powi(2),
+            // weight update; tentative landmarks don't weigh in so clutter can't skew the particles
+            if landmark.existence.is_confirmed() {
+                self.log_weight += log_likelihood;
+            }
+            landmark.existence.observed(delta_time, cfg);
+        }
+    }
+
+    /*
+     * refines every hypothesis, reweights them by likelihood, and promotes
+     * the landmark once a single one explains the observations
+     */
+    fn correct_hypotheses(&mut self, observation: &Observation, delta_time: f32, cfg: &Config) {
+        let robot = self.pose();
+
+        let Some(hypotheses) = self.pending_landmarks.get_mut(observation.id) else {
+            return;
+        };
+
+        for component in &mut hypotheses.components {
+            component.log_weight += correct_gaussian(
+                &mut component.mu,
+                &mut component.sigma,
+                robot,
+                observation,
+                cfg,
             );
+        }
+        hypotheses.normalize_and_prune();
+        hypotheses.existence.observed(delta_time, cfg);
+
+        let promoted = match hypotheses.best() {
+            Some(best)
+                if hypotheses.existence.is_confirmed()
+                    && best.log_weight.exp() > PROMOTE_HYPOTHESIS_WEIGHT =>
+            {
+                Some(LandmarkEstimate {
+                    mu: best.mu,
+                    sigma: best.sigma,
+                    existence: hypotheses.existence.clone(),
+                })
+            }
+            _ => None,
+        };
 
-            // landmark-landmark covariance
-            let p_ll = landmark.sigma;
+        // every hypothesis was pruned; start over from the next sighting
+        if hypotheses.components.is_empty() {
+            self.pending_landmarks.remove(observation.id);
+        }
 
-            // innovation matrix
-            let z_matrix = h_l * p_ll * h_l.transpose() + r;
+        if let Some(landmark) = promoted {
+            self.pending_landmarks.remove(observation.id);
+            self.landmarks.insert(observation.id, landmark);
+        }
+    }
 
-            let z_inverse = z_matrix.try_inverse().unwrap();
+    /*
+     * lowers the existence belief of landmarks this particle expected to see
+     * but didn't, and removes the ones that have become unlikely
+     */
+    fn apply_negative_evidence(
+        &mut self,
+        seen: &HashSet<usize>,
+        obstructions: &ObstructionGrid,
+        delta_time: f32,
+        cfg: &Config,
+    ) {
+        let is_missed = |id: usize, position: &Vector2<f32>| {
+            !seen.contains(&id)
+                && is_expected_visible(
+                    self.x,
+                    self.y,
+                    self.theta,
+                    position.x,
+                    position.y,
+                    obstructions,
+                    cfg,
+                )
+        };
 
-            // weight update
-            let determinant = z_matrix.determinant().max(1e-6);
-            let exponent = -0.5 * (z.transpose() * z_inverse * z)[(0, 0)];
-            let weight_update =
-                (1.0 / (2.0 * std::f32::consts::PI * determinant.sqrt())) * exponent.exp();
-            self.weight *= weight_update.max(1e-20);
+        let missed: Vec<usize> = self
+            .landmarks
+            .iter()
+            .filter(|(id, landmark)| is_missed(*id, &landmark.mu))
+            .map(|(id, _)| id)
+            .collect();
+
+        // pending landmarks are judged by their most likely hypothesis
+        let missed_pending: Vec<usize> = self
+            .pending_landmarks
+            .iter()
+            .filter(|(id, hypotheses)| {
+                hypotheses
+                    .best()
+                    .is_some_and(|best| is_missed(*id, &best.mu))
+            })
+            .map(|(id, _)| id)
+            .collect();
+
+        for id in missed {
+            let remove = match self.landmarks.get_mut(id) {
+                Some(landmark) => {
+                    landmark.existence.missed(delta_time, cfg);
+                    landmark.existence.should_remove(cfg)
+                }
+                None => false,
+            };
 
-            // ekf update
-            // Kalman gain
-            let k = p_ll * h_l.transpose() * z_inverse;
+            if remove {
+                self.landmarks.remove(id);
+            }
+        }
 
-            // update state
-            landmark.mu += k * z;
+        for id in missed_pending {
+            let remove = match self.pending_landmarks.get_mut(id) {
+                Some(hypotheses) => {
+                    hypotheses.existence.missed(delta_time, cfg);
+                    hypotheses.existence.should_remove(cfg)
+                }
+                None => false,
+            };
 
-            // update covariance
-            landmark.sigma = (Matrix2::identity() - k * h_l) * p_ll;
+            if remove {
+                self.pending_landmarks.remove(id);
+            }
         }
     }
 }
@@ -139,8 +443,9 @@ impl FastSlam {
                 x: 0.0,
                 y: 0.0,
                 theta: 0.0,
-                weight: 1.0,
-                landmarks: HashMap::new(),
+                log_weight: 0.0,
+                landmarks: LandmarkTree::new(),
+                pending_landmarks: LandmarkTree::new(),
             };
             num_particles
         ];
@@ -148,84 +453,317 @@ impl FastSlam {
         Self {
             particles,
             num_particles,
+            num_threads: available_threads(),
+            loop_closures: LoopClosureDetector::new(),
+            since_update: 0.0,
+            rng: Rng::new(0),
         }
     }
 
-    fn resample(&mut self) {
-        let total_weight: f32 = self.particles.iter().map(|particle| particle.weight).sum();
+    /*
+     * particle weights normalized to sum to one, using log-sum-exp
+     * https://en.wikipedia.org/wiki/LogSumExp
+     */
+    pub fn normalized_weights(&self) -> Vec<f32> {
+        let max_log_weight = self
+            .particles
+            .iter()
+            .map(|particle| particle.log_weight)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        // every weight collapsed (or no particles); fall back to uniform
+        if !max_log_weight.is_finite() {
+            return vec![1.0 / self.particles.len() as f32; self.particles.len()];
+        }
+
+        let weights: Vec<f32> = self
+            .particles
+            .iter()
+            .map(|particle| (particle.log_weight - max_log_weight).exp())
+            .collect();
+        let total_weight: f32 = weights.iter().sum();
+
+        weights.iter().map(|weight| weight / total_weight).collect()
+    }
+
+    // where the particles that have confirmed a landmark put it, on average
+    fn mapped_position(&self, weights: &[f32], id: usize) -> Option<(f32, f32)> {
+        let (mut x, mut y, mut total_weight) = (0.0, 0.0, 0.0);
+        for (particle, weight) in self.particles.iter().zip(weights) {
+            if let Some(landmark) = particle.landmarks.get(id)
+                && landmark.existence.is_confirmed()
+            {
+                x += landmark.mu.x * weight;
+                y += landmark.mu.y * weight;
+                total_weight += weight;
+            }
+        }
+        (total_weight > 0.0).then(|| (x / total_weight, y / total_weight))
+    }
 
-        // safety check for if weights collapsed
-        if total_weight < 1e-10 {
+    /*
+     * each confirmed landmark's position and covariance over the particles:
+     * the weighted mixture of their gaussians, so disagreement between
+     * particles widens it
+     */
+    pub fn landmark_covariances(&self) -> Vec<(usize, Vector2<f32>, Matrix2<f32>)> {
+        let mut moments: HashMap<usize, (f32, Vector2<f32>, Matrix2<f32>)> = HashMap::new();
+        for (particle, weight) in self.particles.iter().zip(self.normalized_weights()) {
+            for (id, landmark) in particle.landmarks.iter() {
+                if !landmark.existence.is_confirmed() {
+                    continue;
+                }
+                let (total_weight, mean, second) = moments.entry(id).or_default();
+                *total_weight += weight;
+                *mean += landmark.mu * weight;
+                *second += (landmark.sigma + landmark.mu * landmark.mu.transpose()) * weight;
+            }
+        }
+
+        moments
+            .into_iter()
+            .filter(|&(_, (total_weight, _, _))| total_weight > 0.0)
+            .map(|(id, (total_weight, mean, second))| {
+                let mean = mean / total_weight;
+                (id, mean, second / total_weight - mean * mean.transpose())
+            })
+            .collect()
+    }
+
+    /*
+     * effective sample size, 1 / sum(w^2) for normalized weights;
+     * ranges from 1 (degenerate) to the number of particles (uniform)
+     */
+    pub fn effective_sample_size(&self) -> f32 {
+        let sum_sq: f32 = self
+            .normalized_weights()
+            .iter()
+            .map(|weight| weight * weight)
+            .sum();
+
+        if sum_sq > 0.0 { 1.0 / sum_sq } else { 0.0 }
+    }
+
+    /*
+     * shifts log weights so that they sum to one in linear space, keeping them bounded
+     */
+    fn normalize_log_weights(&mut self) {
+        let max_log_weight = self
+            .particles
+            .iter()
+            .map(|particle| particle.log_weight)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        if !max_log_weight.is_finite() {
+            let uniform = -(self.num_particles as f32).ln();
             for particle in &mut self.particles {
-                particle.weight = 1.0;
+                particle.log_weight = uniform;
             }
             return;
         }
 
-        let mut new_particles = Vec::with_capacity(self.num_particles);
-        let step = total_weight / (self.num_particles as f32);
-        let mut position = macroquad::rand::gen_range(0.0, step);
-        let mut cumulative_weight = 0.0;
-        let mut current_index = 0;
+        let log_total = max_log_weight
+            + self
+                .particles
+                .iter()
+                .map(|particle| (particle.log_weight - max_log_weight).exp())
+                .sum::<f32>()
+                .ln();
+
+        for particle in &mut self.particles {
+            particle.log_weight -= log_total;
+        }
+    }
+
+    // only resample once the weights have degenerated enough
+    fn resample_if_degenerate(&mut self, cfg: &Config) {
+        self.normalize_log_weights();
+
+        if self.effective_sample_size() < cfg.resampling_threshold * self.num_particles as f32 {
+            self.resample(cfg.resampling_scheme);
+        }
+    }
 
-        for _ in 0..self.num_particles {
-            while position > cumulative_weight + self.particles[current_index].weight {
-                cumulative_weight += self.particles[current_index].weight;
-                current_index = (current_index + 1) % self.num_particles;
+    pub fn resample(&mut self, scheme: ResamplingScheme) {
+        let weights = self.normalized_weights();
+        let rng = &mut self.rng;
+        let indices = match scheme {
+            ResamplingScheme::Systematic => systematic_resample(&weights, self.num_particles, rng),
+            ResamplingScheme::Stratified => stratified_resample(&weights, self.num_particles, rng),
+            ResamplingScheme::Multinomial => {
+                multinomial_resample(&weights, self.num_particles, rng)
             }
+            ResamplingScheme::Residual => residual_resample(&weights, self.num_particles, rng),
+        };
 
-            let mut particle = self.particles[current_index].clone();
-            particle.weight = 1.0;
-            new_particles.push(particle);
-            position += step;
+        let uniform = -(self.num_particles as f32).ln();
+        self.particles = indices
+            .into_iter()
+            .map(|index| {
+                let mut particle = self.particles[index].clone();
+                particle.log_weight = uniform;
+                particle
+            })
+            .collect();
+    }
+}
+
+/*
+ * resampling schemes; each takes normalized weights and returns the indices
+ * of the particles that survive, see
+ * https://www.robots.ox.ac.uk/~mosb/public/pdf/115/Douc%20et%20al.%20-%202005%20-%20Comparison%20of%20resampling%20schemes%20for%20particle%20filtering.pdf
+ */
+
+// finds the index whose cumulative weight bracket contains each (sorted) position
+fn select_sorted(weights: &[f32], positions: impl Iterator<Item = f32>) -> Vec<usize> {
+    let mut indices = Vec::with_capacity(weights.len());
+    let mut cumulative_weight = weights.first().copied().unwrap_or(0.0);
+    let mut current_index = 0;
+
+    for position in positions {
+        while position > cumulative_weight && current_index + 1 < weights.len() {
+            current_index += 1;
+            cumulative_weight += weights[current_index];
         }
-        self.particles = new_particles;
+        indices.push(current_index);
     }
+
+    indices
+}
+
+// single random offset, evenly spaced positions
+fn systematic_resample(weights: &[f32], count: usize, rng: &mut Rng) -> Vec<usize> {
+    let step = 1.0 / count as f32;
+    let offset = rng.gen_range(0.0, step);
+
+    select_sorted(weights, (0..count).map(|i| offset + i as f32 * step))
+}
+
+// independent random offset inside each of the evenly spaced strata
+fn stratified_resample(weights: &[f32], count: usize, rng: &mut Rng) -> Vec<usize> {
+    let step = 1.0 / count as f32;
+
+    select_sorted(
+        weights,
+        (0..count).map(|i| (i as f32 + rng.gen_range(0.0, 1.0)) * step),
+    )
+}
+
+// independent draws from the categorical distribution
+fn multinomial_resample(weights: &[f32], count: usize, rng: &mut Rng) -> Vec<usize> {
+    let mut positions: Vec<f32> = (0..count).map(|_| rng.gen_range(0.0, 1.0)).collect();
+    positions.sort_by(f32::total_cmp);
+
+    select_sorted(weights, positions.into_iter())
+}
+
+// deterministic floor(N * w) copies, remainder drawn multinomially from the residuals
+fn residual_resample(weights: &[f32], count: usize, rng: &mut Rng) -> Vec<usize> {
+    let mut indices = Vec::with_capacity(count);
+    let mut residuals = Vec::with_capacity(weights.len());
+
+    for (index, weight) in weights.iter().enumerate() {
+        let expected = weight * count as f32;
+        let copies = expected.floor() as usize;
+        indices.extend(std::iter::repeat_n(index, copies));
+        residuals.push(expected - copies as f32);
+    }
+
+    let remaining = count.saturating_sub(indices.len());
+    let residual_total: f32 = residuals.iter().sum();
+
+    if remaining > 0 && residual_total > 0.0 {
+        for residual in &mut residuals {
+            *residual /= residual_total;
+        }
+        indices.extend(multinomial_resample(&residuals, remaining, rng));
+    }
+
+    // floating point error can leave us short; pad with the heaviest particle
+    while indices.len() < count {
+        let heaviest = weights
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(index, _)| index)
+            .unwrap_or(0);
+        indices.push(heaviest);
+    }
+
+    indices
 }
 
 impl Slam for FastSlam {
-    fn predict(
+    fn predict(&mut self, control: &Control, cfg: &Config) {
+        self.loop_closures.moved(control, cfg);
+        self.since_update += control.delta_time();
+
+        // each particle gets its own stream so the result doesn't depend on thread count
+        let step_rng = Rng::new(self.rng.next_u64());
+
+        par_for_each_mut(&mut self.particles, self.num_threads, |index, particle| {
+            let mut rng = step_rng.stream(index as u64);
+            let noisy_control = control.sample(&mut rng, cfg);
+
+            (particle.x, particle.y, particle.theta) = noisy_control.apply(particle.pose(), cfg);
+        });
+    }
+
+    fn update(
         &mut self,
-        linear_velocity: f32,
-        angular_velocity: f32,
-        delta_time: f32,
+        observations: &[Observation],
+        obstructions: &ObstructionGrid,
         cfg: &Config,
     ) {
-        for particle in &mut self.particles {
-            let noisy_linear_velocity = linear_velocity
-                + sample_normal(
-                    0.0,
-                    (cfg.est_stdev_linear * linear_velocity.abs()).max(0.01),
-                );
-            let noisy_angular_velocity = angular_velocity
-                + sample_normal(
-                    0.0,
-                    (cfg.est_stdev_angular * angular_velocity.abs()).max(0.01),
-                );
+        let seen: HashSet<usize> = observations
+            .iter()
+            .map(|observation| observation.id)
+            .collect();
+
+        // the map as it was, for loop closures
+        let weights = self.normalized_weights();
+        let mapped: Vec<usize> = seen
+            .iter()
+            .copied()
+            .filter(|&id| self.mapped_position(&weights, id).is_some())
+            .collect();
+        let stale = self
+            .loop_closures
+            .stale(mapped.iter().copied(), cfg)
+            .into_iter()
+            .filter_map(|id| Some((id, self.mapped_position(&weights, id)?)))
+            .collect();
+        let before = self.get_state();
+        let delta_time = std::mem::take(&mut self.since_update);
+
+        // particles are independent given their pose, so they can be updated in parallel
+        par_for_each_mut(&mut self.particles, self.num_threads, |_, particle| {
+            for observation in observations {
+                particle.observe(observation, delta_time, cfg);
+            }
+            particle.apply_negative_evidence(&seen, obstructions, delta_time, cfg);
+        });
 
-            let theta_half = particle.theta + 0.5 * noisy_angular_velocity * delta_time;
+        self.resample_if_degenerate(cfg);
+        self.loop_closures
+            .observed(mapped.into_iter(), stale, before, self.get_state());
+    }
 
-            // update position estimate
-            particle.x += noisy_linear_velocity * delta_time * theta_half.cos();
-            particle.y += noisy_linear_velocity * delta_time * theta_half.sin();
-            particle.theta += noisy_angular_velocity * delta_time;
+    /*
+     * particles are only reweighted; they don't move towards the fix, so one
+     * only helps while some particles are near it. a cloud that drifted off
+     * as a whole, through an outage say, stays off until landmarks pull it
+     * back
+     */
+    fn update_position(&mut self, fix: &GpsFix, cfg: &Config) {
+        let noise = DMatrix::identity(2, 2) * cfg.est_stdev_gps.powi(2);
 
-            // normalize angle to (-PI, PI]
-            particle.theta = f32::atan2(particle.theta.sin(), particle.theta.cos());
+        for particle in &mut self.particles {
+            let innovation = DVector::from_vec(vec![fix.x - particle.x, fix.y - particle.y]);
+            particle.log_weight += log_likelihood(&innovation, &noise);
         }
-    }
 
-    fn update(&mut self, observations: &[Observation], cfg: &Config) {
-        for observation in observations {
-            for particle in &mut self.particles {
-                if particle.landmarks.contains_key(&observation.id) {
-                    particle.correct_landmark(observation, cfg);
-                } else {
-                    particle.initialize_landmark(observation, cfg);
-                }
-            }
-        }
-        self.resample();
+        self.resample_if_degenerate(cfg);
     }
 
     fn get_state(&self) -> (f32, f32, f32) {
@@ -233,55 +771,137 @@ impl Slam for FastSlam {
         let mut y = 0.0;
         let mut dir_x = 0.0;
         let mut dir_y = 0.0;
-        let mut total_weight = 0.0;
-
-        for particle in &self.particles {
-            x += particle.x * particle.weight;
-            y += particle.y * particle.weight;
-
-            dir_x += particle.theta.cos() * particle.weight;
-            dir_y += particle.theta.sin() * particle.weight;
 
-            total_weight += particle.weight;
-        }
+        for (particle, weight) in self.particles.iter().zip(self.normalized_weights()) {
+            x += particle.x * weight;
+            y += particle.y * weight;
 
-        if total_weight < 1e-10 {
-            return (0.0, 0.0, 0.0);
+            dir_x += particle.theta.cos() * weight;
+            dir_y += particle.theta.sin() * weight;
         }
 
-        (x / total_weight, y / total_weight, f32::atan2(dir_y, dir_x))
+        (x, y, f32::atan2(dir_y, dir_x))
     }
 
     fn get_landmarks(&self) -> Vec<(usize, f32, f32)> {
-        let mut total_weight = 0.0;
-        let mut hashmap: HashMap<usize, (f32, f32)> = std::collections::HashMap::new();
+        let mut hashmap: HashMap<usize, (f32, f32, f32)> = std::collections::HashMap::new();
         let mut landmarks = Vec::new();
 
-        for particle in &self.particles {
-            total_weight += particle.weight;
+        for (particle, weight) in self.particles.iter().zip(self.normalized_weights()) {
+            for (id, landmark) in particle.landmarks.iter() {
+                // tentative landmarks aren't part of the map yet
+                if !landmark.existence.is_confirmed() {
+                    continue;
+                }
 
-            for (id, landmark) in &particle.landmarks {
                 hashmap
-                    .entry(*id)
-                    .and_modify(|(x, y)| {
-                        *x += landmark.mu.x * particle.weight;
-                        *y += landmark.mu.y * particle.weight;
+                    .entry(id)
+                    .and_modify(|(x, y, total_weight)| {
+                        *x += landmark.mu.x * weight;
+                        *y += landmark.mu.y * weight;
+                        *total_weight += weight;
                     })
-                    .or_insert((
-                        landmark.mu.x * particle.weight,
-                        landmark.mu.y * particle.weight,
-                    ));
+                    .or_insert((landmark.mu.x * weight, landmark.mu.y * weight, weight));
             }
         }
 
-        for (id, landmark) in &mut hashmap {
-            landmarks.push((*id, landmark.0 / total_weight, landmark.1 / total_weight))
+        for (id, (x, y, total_weight)) in &hashmap {
+            landmarks.push((*id, x / total_weight, y / total_weight))
         }
 
         landmarks
     }
 
+    fn take_loop_closures(&mut self) -> Vec<LoopClosure> {
+        self.loop_closures.take()
+    }
+
     fn color(&self) -> macroquad::prelude::Color {
         Self::COLOR
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::ObservationSource;
+
+    #[test]
+    fn gaussian_sums_stay_bounded_for_any_spread() {
+        for spread in [0.0, 0.001, 0.25, 1.0, 5.0] {
+            // bearing-only, then range-only
+            for (range, bearing) in [(None, Some(0.2)), (Some(100.0), None)] {
+                let cfg = Config {
+                    gaussian_sum_spread: spread,
+                    ..Config::default()
+                };
+                let observation = Observation {
+                    id: 0,
+                    range,
+                    bearing,
+                    ground_truth: ObservationSource::Landmark,
+                };
+                let mut fast_slam = FastSlam::new(1);
+                fast_slam.update(
+                    &[observation],
+                    &ObstructionGrid::new(cfg.spatial_cell_size),
+                    &cfg,
+                );
+
+                let hypotheses = fast_slam.particles[0].pending_landmarks.get(0).unwrap();
+                let count = hypotheses.components.len();
+                assert!(
+                    (1..=MAX_HYPOTHESES).contains(&count),
+                    "{count} hypotheses for a spread of {spread}"
+                );
+            }
+        }
+    }
+
+    type Scheme = fn(&[f32], usize, &mut Rng) -> Vec<usize>;
+
+    // uneven weights, one of them zero
+    const WEIGHTS: [f32; 6] = [0.05, 0.4, 0.0, 0.3, 0.15, 0.1];
+
+    // how many copies of each particle the indices hold
+    fn copies(indices: &[usize]) -> Vec<usize> {
+        let mut copies = vec![0; WEIGHTS.len()];
+        for &index in indices {
+            copies[index] += 1;
+        }
+        copies
+    }
+
+    #[test]
+    fn every_scheme_returns_as_many_particles_as_asked() {
+        let schemes: [(&str, Scheme); 4] = [
+            ("systematic", systematic_resample),
+            ("stratified", stratified_resample),
+            ("multinomial", multinomial_resample),
+            ("residual", residual_resample),
+        ];
+        let mut rng = Rng::new(1);
+        for (name, scheme) in schemes {
+            for count in [1, 6, 7, 100] {
+                let indices = scheme(&WEIGHTS, count, &mut rng);
+                assert_eq!(indices.len(), count, "{name} with {count}");
+                assert!(indices.iter().all(|&index| index < WEIGHTS.len()));
+            }
+        }
+    }
+
+    #[test]
+    fn low_variance_schemes_keep_their_share() {
+        let mut rng = Rng::new(1);
+        for _ in 0..20 {
+            let systematic = copies(&systematic_resample(&WEIGHTS, 100, &mut rng));
+            let residual = copies(&residual_resample(&WEIGHTS, 100, &mut rng));
+            for (index, weight) in WEIGHTS.iter().enumerate() {
+                let expected = weight * 100.0;
+                let within = expected.floor() as usize..=expected.ceil() as usize;
+                assert!(within.contains(&systematic[index]), "{systematic:?}");
+                assert!(residual[index] >= expected.floor() as usize, "{residual:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,555 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
+
+use nalgebra::DVector;
+
+use crate::slam::sparse::{SparseCholesky, SparseSymmetric};
+
+// (index, value) pairs
+type SparseRow = Vec<(usize, f64)>;
+
+/*
+ * one row of a linearized, whitened least-squares problem: the entries of
+ * the jacobian by state index, and the right hand side
+ */
+pub struct LinearRow {
+    pub entries: SparseRow,
+    pub rhs: f64,
+}
+
+/*
+ * incremental smoothing in the manner of iSAM: the square root R of the
+ * information matrix, upper triangular and sparse, updated by Givens
+ * rotations as rows arrive, so that taking in a measurement only touches the
+ * rows of R it fills in rather than refactoring everything. new variables go
+ * at the end of the ordering, which keeps the rows of new measurements short
+ * but lets fill build up as loops close. a refactor starts over at the
+ * caller's new linearization point, with a fill reducing ordering if asked,
+ * and is spread over as many calls as its budget takes, so that it never
+ * holds up a frame for long.
+ * http://www.cs.cmu.edu/~kaess/pub/Kaess08tro.pdf
+ */
+pub struct IncrementalSolver {
+    sizes: Vec<usize>,    // scalars of each variable, in the order they were added
+    column: Vec<usize>,   // column of R for each state index
+    rows: Vec<SparseRow>, // R by row, in column order with the diagonal first
+    rhs: Vec<f64>,
+    reordered_nonzeros: usize, // right after the last reordering
+    refactor: Option<Refactor>,
+}
+
+impl IncrementalSolver {
+    pub fn new() -> Self {
+        Self {
+            sizes: Vec::new(),
+            column: Vec::new(),
+            rows: Vec::new(),
+            rhs: Vec::new(),
+            reordered_nonzeros: 0,
+            refactor: None,
+        }
+    }
+
+    // a new variable with this many scalars, last in the ordering; returns its first state index
+    pub fn add_variable(&mut self, size: usize) -> usize {
+        let offset = self.column.len();
+        self.sizes.push(size);
+        self.column.extend(offset..offset + size);
+        self.rows.resize(offset + size, Vec::new());
+        self.rhs.resize(offset + size, 0.0);
+        offset
+    }
+
+    /*
+     * rotates a row into R: its leftmost entry is zeroed against the row of R
+     * with the same diagonal, which leaves it with that row's later entries,
+     * until it's either empty or lands on a row of R that has none yet.
+     * returns the work that took, in entries rotated
+     */
+    pub fn add_row(&mut self, row: &LinearRow) -> usize {
+        let mut entries: SparseRow = row
+            .entries
+            .iter()
+            .map(|&(index, value)| (self.column[index], value))
+            .filter(|&(_, value)| value != 0.0)
+            .collect();
+        entries.sort_by_key(|&(column, _)| column);
+        entries.dedup_by(|later, earlier| {
+            let same = later.0 == earlier.0;
+            if same {
+                earlier.1 += later.1;
+            }
+            same
+        });
+        let mut rhs = row.rhs;
+        let mut work = 0;
+
+        while let Some(&(k, a)) = entries.first() {
+            if self.rows[k].is_empty() {
+                self.rows[k] = entries;
+                self.rhs[k] = rhs;
+                break;
+            }
+            work += self.rows[k].len() + entries.len();
+
+            let b = self.rows[k][0].1;
+            let rho = a.hypot(b);
+            let (c, s) = (b / rho, a / rho);
+            let (rotated, rest) = rotate(&self.rows[k], &entries, c, s);
+            self.rows[k] = rotated;
+            entries = rest;
+            (self.rhs[k], rhs) = (c * self.rhs[k] + s * rhs, c * rhs - s * self.rhs[k]);
+        }
+        work
+    }
+
+    /*
+     * the least-squares solution by back substitution, by state index; None
+     * while some variable isn't pinned down by the rows so far
+     */
+    pub fn solve(&self) -> Option<DVector<f64>> {
+        let mut solution = vec![0.0; self.rows.len()];
+        for k in (0..self.rows.len()).rev() {
+            let (&(_, diagonal), rest) = self.rows[k].split_first()?;
+            let known: f64 = rest.iter().map(|&(j, r)| r * solution[j]).sum();
+            solution[k] = (self.rhs[k] - known) / diagonal;
+        }
+        Some(DVector::from_iterator(
+            self.column.len(),
+            self.column.iter().map(|&column| solution[column]),
+        ))
+    }
+
+    pub fn variables(&self) -> usize {
+        self.sizes.len()
+    }
+
+    pub fn nonzeros(&self) -> usize {
+        self.rows.iter().map(Vec::len).sum()
+    }
+
+    // how much the factor has grown since the variables were last reordered
+    pub fn fill(&self) -> f32 {
+        self.nonzeros() as f32 / self.reordered_nonzeros.max(1) as f32
+    }
+
+    /*
+     * starts factoring the given rows from scratch, at the caller's new
+     * linearization point, through the normal equations; given the state
+     * index of a variable to eliminate last, the variables are reordered
+     * first. it covers the first so many variables, as counted by
+     * variables() when the rows were linearized. the work is done a budget
+     * at a time by continue_refactor, while rows keep going into the factor
+     * in use; variables added meanwhile go after the rest
+     */
+    pub fn start_refactor(&mut self, rows: Vec<LinearRow>, variables: usize, last: Option<usize>) {
+        let sizes = &self.sizes[..variables];
+        let covered = sizes.iter().sum();
+        self.refactor = Some(match last {
+            Some(last) => Refactor::Ordering {
+                ordering: MinimumDegree::new(sizes, last),
+                rows,
+                next: 0,
+            },
+            // later variables sit at the end, so the ones covered keep to the first columns
+            None => Refactor::summing(rows, self.column[..covered].to_vec(), false),
+        });
+    }
+
+    /*
+     * works on the refactor under way until the budget (entries worked on)
+     * is spent. once it's done, R is thrown away for the new factor, and
+     * whether that worked: it doesn't if the rows don't pin every variable
+     */
+    pub fn continue_refactor(&mut self, budget: &mut usize) -> Option<bool> {
+        while *budget > 0 {
+            self.refactor = Some(match self.refactor.take()? {
+                Refactor::Ordering {
+                    rows,
+                    mut next,
+                    mut ordering,
+                } => {
+                    while next < rows.len() && *budget > 0 {
+                        *budget = budget.saturating_sub(ordering.add_row(&rows[next]));
+                        next += 1;
+                    }
+                    match (next == rows.len()).then(|| ordering.eliminate(budget)) {
+                        Some(Some(column)) => Refactor::summing(rows, column, true),
+                        _ => Refactor::Ordering {
+                            rows,
+                            next,
+                            ordering,
+                        },
+                    }
+                }
+                Refactor::Summing {
+                    rows,
+                    mut next,
+                    column,
+                    mut normal,
+                    mut projected,
+                    reordered,
+                } => {
+                    // AᵀA and Aᵀb, in the factor's order
+                    while next < rows.len() && *budget > 0 {
+                        let row = &rows[next];
+                        for (a, &(i, value_i)) in row.entries.iter().enumerate() {
+                            projected[column[i]] += value_i * row.rhs;
+                            for &(j, value_j) in &row.entries[a..] {
+                                normal.add(column[i], column[j], value_i * value_j);
+                            }
+                        }
+                        *budget = budget.saturating_sub(row.entries.len().pow(2));
+                        next += 1;
+                    }
+                    if next == rows.len() {
+                        Refactor::Factoring {
+                            column,
+                            cholesky: SparseCholesky::new(normal, projected),
+                            reordered,
+                        }
+                    } else {
+                        Refactor::Summing {
+                            rows,
+                            next,
+                            column,
+                            normal,
+                            projected,
+                            reordered,
+                        }
+                    }
+                }
+                Refactor::Factoring {
+                    column,
+                    mut cholesky,
+                    reordered,
+                } => match cholesky.step(budget) {
+                    Some(true) => {
+                        self.swap_in(column, cholesky, reordered);
+                        return Some(true);
+                    }
+                    Some(false) => return Some(false),
+                    None => Refactor::Factoring {
+                        column,
+                        cholesky,
+                        reordered,
+                    },
+                },
+            });
+        }
+        None
+    }
+
+    /*
+     * R is Lᵀ, and the right hand side solves Rᵀ d = Aᵀb; variables added
+     * since the refactor started keep their place after the rest, with no
+     * rows yet
+     */
+    fn swap_in(&mut self, column: Vec<usize>, cholesky: SparseCholesky, reordered: bool) {
+        let size = self.column.len();
+        let covered = column.len();
+        let (rows, rhs) = cholesky.into_parts();
+        self.column = column;
+        self.column.extend(covered..size);
+        self.rows = rows;
+        self.rows.resize(size, Vec::new());
+        self.rhs = rhs;
+        self.rhs.resize(size, 0.0);
+        if reordered {
+            self.reordered_nonzeros = self.nonzeros();
+        }
+    }
+}
+
+// a refactor under way, a stage at a time
+enum Refactor {
+    Ordering {
+        rows: Vec<LinearRow>,
+        next: usize, // rows taken in so far
+        ordering: MinimumDegree,
+    },
+    Summing {
+        rows: Vec<LinearRow>,
+        next: usize,
+        column: Vec<usize>, // the new factor's column for each state index
+        normal: SparseSymmetric,
+        projected: Vec<f64>,
+        reordered: bool,
+    },
+    Factoring {
+        column: Vec<usize>,
+        cholesky: SparseCholesky,
+        reordered: bool,
+    },
+}
+
+impl Refactor {
+    fn summing(rows: Vec<LinearRow>, column: Vec<usize>, reordered: bool) -> Self {
+        Self::Summing {
+            rows,
+            next: 0,
+            normal: SparseSymmetric::new(column.len()),
+            projected: vec![0.0; column.len()],
+            column,
+            reordered,
+        }
+    }
+}
+
+/*
+ * greedy minimum degree over the variables tied together by the rows,
+ * eliminating a given one last: the newest pose, so the next measurements
+ * stay cheap
+ */
+struct MinimumDegree {
+    sizes: Vec<usize>,
+    variable_of: Vec<usize>, // by state index
+    last: usize,
+    neighbours: Vec<BTreeSet<usize>>,
+    queue: Option<BinaryHeap<Reverse<(usize, usize)>>>, // once the rows are in
+    eliminated: Vec<bool>,
+    order: Vec<usize>,
+}
+
+impl MinimumDegree {
+    fn new(sizes: &[usize], last: usize) -> Self {
+        let mut variable_of = Vec::new();
+        for (variable, &size) in sizes.iter().enumerate() {
+            variable_of.extend(std::iter::repeat_n(variable, size));
+        }
+        Self {
+            sizes: sizes.to_vec(),
+            last: variable_of[last],
+            variable_of,
+            neighbours: vec![BTreeSet::new(); sizes.len()],
+            queue: None,
+            eliminated: vec![false; sizes.len()],
+            order: Vec::with_capacity(sizes.len()),
+        }
+    }
+
+    // ties the row's variables together; returns the work it took
+    fn add_row(&mut self, row: &LinearRow) -> usize {
+        let variables: BTreeSet<usize> = row
+            .entries
+            .iter()
+            .map(|&(index, _)| self.variable_of[index])
+            .collect();
+        for &a in &variables {
+            self.neighbours[a].extend(variables.iter().filter(|&&b| b != a));
+        }
+        variables.len().pow(2)
+    }
+
+    /*
+     * eliminates variables until the budget is spent; once they're all
+     * gone, the column each state index goes to
+     */
+    fn eliminate(&mut self, budget: &mut usize) -> Option<Vec<usize>> {
+        // lazily updated: stale degrees are skipped when popped
+        let queue = self.queue.get_or_insert_with(|| {
+            (0..self.sizes.len())
+                .filter(|&variable| variable != self.last)
+                .map(|variable| Reverse((self.neighbours[variable].len(), variable)))
+                .collect()
+        });
+
+        while *budget > 0 {
+            let Some(Reverse((degree, variable))) = queue.pop() else {
+                break;
+            };
+            *budget -= 1;
+            if self.eliminated[variable] || degree != self.neighbours[variable].len() {
+                continue;
+            }
+            self.eliminated[variable] = true;
+            self.order.push(variable);
+
+            // its neighbours become a clique
+            let clique = std::mem::take(&mut self.neighbours[variable]);
+            for &a in &clique {
+                self.neighbours[a].remove(&variable);
+                self.neighbours[a].extend(clique.iter().filter(|&&b| b != a));
+                if a != self.last {
+                    queue.push(Reverse((self.neighbours[a].len(), a)));
+                }
+            }
+            *budget = budget.saturating_sub(clique.len().pow(2));
+        }
+        if !queue.is_empty() {
+            return None;
+        }
+        self.order.push(self.last);
+
+        let offsets: Vec<usize> = self
+            .sizes
+            .iter()
+            .scan(0, |offset, &size| {
+                *offset += size;
+                Some(*offset - size)
+            })
+            .collect();
+        let mut column = vec![0; self.variable_of.len()];
+        let mut next = 0;
+        for &variable in &self.order {
+            let offset = offsets[variable];
+            for slot in &mut column[offset..offset + self.sizes[variable]] {
+                *slot = next;
+                next += 1;
+            }
+        }
+        Some(column)
+    }
+}
+
+/*
+ * a Givens rotation of two sparse rows sorted by column, the first of which
+ * starts on the diagonal: (c r + s e, c e - s r), dropping the entry it zeroes
+ */
+fn rotate(
+    row: &[(usize, f64)],
+    entries: &[(usize, f64)],
+    c: f64,
+    s: f64,
+) -> (SparseRow, SparseRow) {
+    let mut rotated = Vec::with_capacity(row.len() + entries.len());
+    let mut rest = Vec::with_capacity(row.len() + entries.len());
+    let (mut i, mut j) = (0, 0);
+
+    while i < row.len() || j < entries.len() {
+        let column_r = row.get(i).map_or(usize::MAX, |entry| entry.0);
+        let column_e = entries.get(j).map_or(usize::MAX, |entry| entry.0);
+        let column = column_r.min(column_e);
+        let r = if column_r == column {
+            i += 1;
+            row[i - 1].1
+        } else {
+            0.0
+        };
+        let e = if column_e == column {
+            j += 1;
+            entries[j - 1].1
+        } else {
+            0.0
+        };
+
+        rotated.push((column, c * r + s * e));
+        if column != row[0].0 {
+            rest.push((column, c * e - s * r));
+        }
+    }
+    (rotated, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::DMatrix;
+
+    use super::*;
+
+    /*
+     * rows tying each of these three scalar variables to the next two, and a
+     * weak prior on every scalar so they're all pinned down
+     */
+    fn chain(offsets: &[usize]) -> Vec<LinearRow> {
+        let value = |seed: usize| ((seed * 7919) % 13) as f64 / 13.0 - 0.5;
+        let mut rows: Vec<LinearRow> = (0..3 * offsets.len())
+            .map(|index| LinearRow {
+                entries: vec![(index, 0.1)],
+                rhs: value(index),
+            })
+            .collect();
+        for (variable, &offset) in offsets.iter().enumerate() {
+            for &other in offsets.iter().skip(variable + 1).take(2) {
+                let seed = 31 * variable + other;
+                rows.push(LinearRow {
+                    entries: (0..3)
+                        .map(|i| (offset + i, 1.0 + value(seed + i)))
+                        .chain((0..2).map(|i| (other + i, value(seed + 5 + i) - 1.0)))
+                        .collect(),
+                    rhs: value(seed + 9),
+                });
+            }
+        }
+        rows
+    }
+
+    // least squares the dense way, through the normal equations
+    fn dense(rows: &[LinearRow], size: usize) -> DVector<f64> {
+        let mut a = DMatrix::zeros(rows.len(), size);
+        let b = DVector::from_iterator(rows.len(), rows.iter().map(|row| row.rhs));
+        for (r, row) in rows.iter().enumerate() {
+            for &(index, value) in &row.entries {
+                a[(r, index)] += value;
+            }
+        }
+        (a.transpose() * &a)
+            .cholesky()
+            .unwrap()
+            .solve(&(a.transpose() * b))
+    }
+
+    fn solver(count: usize) -> (IncrementalSolver, Vec<usize>) {
+        let mut solver = IncrementalSolver::new();
+        let offsets = (0..count).map(|_| solver.add_variable(3)).collect();
+        (solver, offsets)
+    }
+
+    #[test]
+    fn rotating_rows_in_matches_a_dense_solve() {
+        let (mut solver, offsets) = solver(12);
+        let rows = chain(&offsets);
+        for row in &rows {
+            solver.add_row(row);
+        }
+        let error = (solver.solve().unwrap() - dense(&rows, 36)).amax();
+        assert!(error < 1e-9, "off by {error}");
+    }
+
+    /*
+     * reordered a little budget at a time, leaving out a variable added
+     * after its rows were linearized, as the pose graph does when a keyframe
+     * comes in meanwhile
+     */
+    #[test]
+    fn a_spread_refactor_matches_a_dense_solve() {
+        let (mut solver, offsets) = solver(12);
+        let mut rows = chain(&offsets);
+        for row in &rows {
+            solver.add_row(row);
+        }
+
+        let variables = solver.variables();
+        let later = solver.add_variable(3);
+        solver.start_refactor(chain(&offsets), variables, Some(offsets[11]));
+        let mut calls = 1;
+        let factored = loop {
+            match solver.continue_refactor(&mut 20) {
+                Some(factored) => break factored,
+                None => calls += 1,
+            }
+        };
+        assert!(
+            factored && calls > 1,
+            "factored {factored} in {calls} calls"
+        );
+
+        // the newcomer only gets its rows once the new factor is in
+        let mut added: Vec<LinearRow> = (later..later + 3)
+            .map(|index| LinearRow {
+                entries: vec![(index, 0.1)],
+                rhs: 0.2,
+            })
+            .collect();
+        added.push(LinearRow {
+            entries: vec![(offsets[11], 1.0), (later, -1.0)],
+            rhs: 0.3,
+        });
+        for row in &added {
+            solver.add_row(row);
+        }
+        rows.extend(added);
+        let error = (solver.solve().unwrap() - dense(&rows, 39)).amax();
+        assert!(error < 1e-9, "off by {error}");
+    }
+}
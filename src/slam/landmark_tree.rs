@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+const BITS: usize = 4;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Clone)]
+enum Node<T> {
+    Branch(Box<[Option<Arc<Node<T>>>; WIDTH]>),
+    Leaf(T),
+}
+
+/*
+ * persistent radix tree keyed by landmark id, following the balanced landmark
+ * tree from the FastSLAM paper. cloning only bumps the root's reference count,
+ * and a write copies just the O(log N) nodes on the path to the changed leaf,
+ * so resampled particles share every landmark they haven't touched since.
+ * nodes are Arc'd so particles can be updated on worker threads
+ */
+#[derive(Clone)]
+pub struct LandmarkTree<T> {
+    root: Option<Arc<Node<T>>>,
+    height: usize, // number of branch levels above the leaves
+    len: usize,
+}
+
+impl<T: Clone> LandmarkTree<T> {
+    pub fn new() -> Self {
+        Self {
+            root: None,
+            height: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // whether the id can be addressed without growing the tree
+    fn fits(&self, id: usize) -> bool {
+        let shift = self.height * BITS;
+        shift >= usize::BITS as usize || id >> shift == 0
+    }
+
+    // index of the child containing id at the given branch level
+    fn slot(id: usize, level: usize) -> usize {
+        (id >> (level * BITS)) & MASK
+    }
+
+    pub fn get(&self, id: usize) -> Option<&T> {
+        if !self.fits(id) {
+            return None;
+        }
+
+        let mut node = self.root.as_deref()?;
+        for level in (0..self.height).rev() {
+            match node {
+                Node::Branch(children) => node = children[Self::slot(id, level)].as_deref()?,
+                Node::Leaf(_) => unreachable!("leaf above the bottom level"),
+            }
+        }
+
+        match node {
+            Node::Leaf(value) => Some(value),
+            Node::Branch(_) => None,
+        }
+    }
+
+    pub fn contains_key(&self, id: usize) -> bool {
+        self.get(id).is_some()
+    }
+
+    /*
+     * mutable access to a landmark; copies any node on the path that is
+     * shared with another particle
+     */
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut T> {
+        // avoid copying a path just to find out the landmark isn't there
+        if !self.contains_key(id) {
+            return None;
+        }
+
+        let mut node = Arc::make_mut(self.root.as_mut()?);
+        for level in (0..self.height).rev() {
+            match node {
+                Node::Branch(children) => {
+                    node = Arc::make_mut(children[Self::slot(id, level)].as_mut()?)
+                }
+                Node::Leaf(_) => unreachable!("leaf above the bottom level"),
+            }
+        }
+
+        match node {
+            Node::Leaf(value) => Some(value),
+            Node::Branch(_) => None,
+        }
+    }
+
+    pub fn insert(&mut self, id: usize, value: T) {
+        // grow upwards until the id fits; the old root becomes the first child
+        while !self.fits(id) {
+            if let Some(root) = self.root.take() {
+                let mut children: Box<[Option<Arc<Node<T>>>; WIDTH]> = Default::default();
+                children[0] = Some(root);
+                self.root = Some(Arc::new(Node::Branch(children)));
+            }
+            self.height += 1;
+        }
+
+        let mut slot = &mut self.root;
+        for level in (0..self.height).rev() {
+            let node = Arc::make_mut(
+                slot.get_or_insert_with(|| Arc::new(Node::Branch(Default::default()))),
+            );
+            match node {
+                Node::Branch(children) => slot = &mut children[Self::slot(id, level)],
+                Node::Leaf(_) => unreachable!("leaf above the bottom level"),
+            }
+        }
+
+        if slot.replace(Arc::new(Node::Leaf(value))).is_none() {
+            self.len += 1;
+        }
+    }
+
+    /*
+     * removes a landmark, copying the shared nodes on its path; emptied
+     * branches are left in place since ids are rarely reused
+     */
+    pub fn remove(&mut self, id: usize) {
+        if !self.contains_key(id) {
+            return;
+        }
+
+        let mut slot = &mut self.root;
+        for level in (0..self.height).rev() {
+            let Some(node) = slot.as_mut() else {
+                return;
+            };
+            match Arc::make_mut(node) {
+                Node::Branch(children) => slot = &mut children[Self::slot(id, level)],
+                Node::Leaf(_) => unreachable!("leaf above the bottom level"),
+            }
+        }
+
+        if slot.take().is_some() {
+            self.len -= 1;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        let mut entries = Vec::with_capacity(self.len);
+        if let Some(root) = &self.root {
+            Self::collect(root, 0, &mut entries);
+        }
+        entries.into_iter()
+    }
+
+    fn collect<'a>(node: &'a Node<T>, prefix: usize, entries: &mut Vec<(usize, &'a T)>) {
+        match node {
+            Node::Branch(children) => {
+                for (i, child) in children.iter().enumerate() {
+                    if let Some(child) = child {
+                        Self::collect(child, (prefix << BITS) | i, entries);
+                    }
+                }
+            }
+            Node::Leaf(value) => entries.push((prefix, value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the child at the given slot of a branch
+    fn child<T>(node: &Arc<Node<T>>, slot: usize) -> &Arc<Node<T>> {
+        match node.as_ref() {
+            Node::Branch(children) => children[slot].as_ref().unwrap(),
+            Node::Leaf(_) => panic!("leaf where a branch should be"),
+        }
+    }
+
+    #[test]
+    fn inserts_gets_and_removes() {
+        let mut tree = LandmarkTree::new();
+        for id in [3, 0, 300, 17, 5000] {
+            tree.insert(id, id * 2);
+        }
+        tree.insert(17, 1);
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.get(300), Some(&600));
+        assert_eq!(tree.get(17), Some(&1));
+        assert_eq!(tree.get(4), None);
+        assert_eq!(tree.get(usize::MAX), None);
+
+        *tree.get_mut(3).unwrap() += 1;
+        assert_eq!(tree.get(3), Some(&7));
+        assert!(tree.get_mut(4).is_none());
+
+        tree.remove(300);
+        tree.remove(300);
+        tree.remove(4);
+        assert_eq!(tree.len(), 4);
+        assert!(!tree.contains_key(300));
+
+        let ids: Vec<usize> = tree.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [0, 3, 17, 5000]);
+    }
+
+    #[test]
+    fn copies_share_what_they_have_not_written() {
+        let mut tree = LandmarkTree::new();
+        for id in 0..40 {
+            tree.insert(id, id);
+        }
+
+        let mut copy = tree.clone();
+        *copy.get_mut(1).unwrap() = 100;
+        copy.remove(20);
+        assert_eq!(tree.get(1), Some(&1));
+        assert_eq!(tree.get(20), Some(&20));
+        assert_eq!(copy.get(1), Some(&100));
+        assert_eq!((tree.len(), copy.len()), (40, 39));
+
+        // the paths written to were copied, the untouched subtree wasn't
+        let (root, copied_root) = (tree.root.as_ref().unwrap(), copy.root.as_ref().unwrap());
+        assert!(!Arc::ptr_eq(root, copied_root));
+        assert!(!Arc::ptr_eq(child(root, 0), child(copied_root, 0)));
+        assert!(!Arc::ptr_eq(child(root, 1), child(copied_root, 1)));
+        assert!(Arc::ptr_eq(child(root, 2), child(copied_root, 2)));
+    }
+}
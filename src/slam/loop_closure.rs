@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use crate::config::Config;
+use crate::slam::motion::Control;
+
+type Pose = (f32, f32, f32);
+
+/*
+ * a mapped landmark seen again after the robot had gone a long way, or a
+ * long time, without seeing it; for the pose graph, an old keyframe whose
+ * scan lined up with a new one. the correction is the whole update's, shared
+ * by every landmark that closed the loop in it
+ */
+#[derive(Clone, Debug)]
+pub struct LoopClosure {
+    pub landmarks: Vec<(usize, (f32, f32))>, // ids (or keyframes), and where the map had them just before
+    pub pose: Pose,                          // estimate after the update
+    pub correction: f32,                     // how far the update moved the position estimate
+    pub heading_correction: f32,             // and how far it turned the heading, in radians
+    pub distance: f32,                       // travelled since the stalest one was last seen
+    pub elapsed: f32,                        // seconds since
+}
+
+/*
+ * remembers when each mapped landmark was last seen, by the distance the
+ * estimate has travelled and the time the controls have covered, and
+ * collects loop closures until they're taken
+ */
+pub struct LoopClosureDetector {
+    odometer: f32,
+    clock: f32,
+    last_seen: HashMap<usize, (f32, f32)>, // odometer and clock at each landmark's last sighting
+    events: Vec<LoopClosure>,
+}
+
+impl LoopClosureDetector {
+    pub fn new() -> Self {
+        Self {
+            odometer: 0.0,
+            clock: 0.0,
+            last_seen: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn moved(&mut self, control: &Control, cfg: &Config) {
+        self.odometer += control.as_odometry(cfg).trans.abs();
+        self.clock += control.delta_time();
+    }
+
+    // the observed ids that would close a loop if they're in the map
+    pub fn stale(&self, ids: impl Iterator<Item = usize>, cfg: &Config) -> Vec<usize> {
+        ids.filter(|id| {
+            self.last_seen.get(id).is_some_and(|&(odometer, clock)| {
+                self.odometer - odometer >= cfg.loop_closure_distance
+                    || self.clock - clock >= cfg.loop_closure_time
+            })
+        })
+        .collect()
+    }
+
+    /*
+     * after an update: the mapped landmarks it saw, the stale ones among them
+     * with where the map had them before it, and the pose on either side
+     */
+    pub fn observed(
+        &mut self,
+        seen: impl Iterator<Item = usize>,
+        stale: Vec<(usize, (f32, f32))>,
+        before: Pose,
+        after: Pose,
+    ) {
+        let (distance, elapsed) = stale
+            .iter()
+            .filter_map(|(id, _)| self.last_seen.get(id))
+            .fold(
+                (0.0, 0.0),
+                |(distance, elapsed): (f32, f32), &(odometer, clock)| {
+                    (
+                        distance.max(self.odometer - odometer),
+                        elapsed.max(self.clock - clock),
+                    )
+                },
+            );
+        for id in seen {
+            self.last_seen.insert(id, (self.odometer, self.clock));
+        }
+
+        if !stale.is_empty() {
+            let turn = after.2 - before.2;
+            self.events.push(LoopClosure {
+                landmarks: stale,
+                pose: after,
+                correction: (after.0 - before.0).hypot(after.1 - before.1),
+                heading_correction: turn.sin().atan2(turn.cos()).abs(),
+                distance,
+                elapsed,
+            });
+        }
+    }
+
+    // the loop closures since the last call, oldest first
+    pub fn take(&mut self) -> Vec<LoopClosure> {
+        std::mem::take(&mut self.events)
+    }
+}
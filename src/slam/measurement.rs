@@ -0,0 +1,84 @@
+use nalgebra::{DMatrix, DVector, Dyn, OMatrix, U2, U3};
+
+use crate::config::Config;
+use crate::simulation::Observation;
+use crate::utils::absolute_to_relative;
+
+/*
+ * range-bearing measurement model linearized around a robot pose and a
+ * landmark position. only the components the observation actually carries
+ * get a row, so the same code handles full, bearing-only and range-only
+ * observations
+ */
+pub struct Linearization {
+    pub innovation: DVector<f32>,
+    pub h_robot: OMatrix<f32, Dyn, U3>, // jacobian with respect to robot
+    pub h_landmark: OMatrix<f32, Dyn, U2>, // jacobian with respect to landmark
+    pub noise: DMatrix<f32>,            // sensor noise
+}
+
+pub fn linearize(
+    robot: (f32, f32, f32),
+    landmark_x: f32,
+    landmark_y: f32,
+    observation: &Observation,
+    cfg: &Config,
+) -> Linearization {
+    let (robot_x, robot_y, robot_theta) = robot;
+
+    // distance to landmark
+    let distance_x = landmark_x - robot_x;
+    let distance_y = landmark_y - robot_y;
+    let distance_sq = (distance_x * distance_x + distance_y * distance_y).max(1e-6);
+    let distance = distance_sq.sqrt();
+
+    // predicted measurement
+    let (predicted_range, predicted_bearing) =
+        absolute_to_relative(robot_x, robot_y, robot_theta, landmark_x, landmark_y);
+
+    let mut innovation = Vec::with_capacity(2);
+    let mut h_robot = Vec::with_capacity(6);
+    let mut h_landmark = Vec::with_capacity(4);
+    let mut variances = Vec::with_capacity(2);
+
+    if let Some(range) = observation.range {
+        innovation.push(range - predicted_range);
+        h_robot.extend([-distance_x / distance, -distance_y / distance, 0.0]);
+        h_landmark.extend([distance_x / distance, distance_y / distance]);
+        variances.push(cfg.est_stdev_range_at(range).powi(2));
+    }
+
+    if let Some(bearing) = observation.bearing {
+        let bearing_difference = bearing - predicted_bearing;
+        innovation.push(f32::atan2(
+            bearing_difference.sin(),
+            bearing_difference.cos(),
+        ));
+        h_robot.extend([distance_y / distance_sq, -distance_x / distance_sq, -1.0]);
+        h_landmark.extend([-distance_y / distance_sq, distance_x / distance_sq]);
+        variances.push(cfg.est_stdev_bearing.powi(2));
+    }
+
+    Linearization {
+        innovation: DVector::from_vec(innovation),
+        h_robot: OMatrix::<f32, Dyn, U3>::from_row_slice(&h_robot),
+        h_landmark: OMatrix::<f32, Dyn, U2>::from_row_slice(&h_landmark),
+        noise: DMatrix::from_diagonal(&DVector::from_vec(variances)),
+    }
+}
+
+/*
+ * log of the gaussian density of an innovation with the given covariance
+ */
+pub fn log_likelihood(innovation: &DVector<f32>, innovation_covariance: &DMatrix<f32>) -> f32 {
+    let Some(inverse) = innovation_covariance.clone().try_inverse() else {
+        return f32::NEG_INFINITY;
+    };
+
+    let determinant = innovation_covariance.determinant().max(1e-6);
+    let exponent = -0.5 * (innovation.transpose() * inverse * innovation)[(0, 0)];
+
+    exponent
+        - 0.5 * innovation.nrows() as f32 * (2.0 * std::f32::consts::PI).ln()
+        - 0.5 * determinant.ln()
+}
@@ -1,7 +1,22 @@
+pub mod dead_reckoning;
 pub mod ekf;
+pub mod existence;
 pub mod fast;
+pub mod incremental;
+pub mod landmark_tree;
+pub mod loop_closure;
+pub mod measurement;
+pub mod motion;
+pub mod pose_graph;
+pub mod robust;
+pub mod sparse;
 pub mod trait_def;
 
+pub use dead_reckoning::DeadReckoning;
 pub use ekf::EkfSlam;
-pub use fast::FastSlam;
+pub use fast::{FastSlam, ResamplingScheme};
+pub use loop_closure::LoopClosure;
+pub use motion::{Control, MotionModel, OdometryMotion};
+pub use pose_graph::PoseGraphSlam;
+pub use robust::RobustKernel;
 pub use trait_def::Slam;
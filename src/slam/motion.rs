@@ -0,0 +1,275 @@
+use nalgebra::{Matrix3, Vector3};
+
+use crate::config::Config;
+use crate::kinematics::DriveType;
+use crate::simulation::EncoderTicks;
+use crate::utils::Rng;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MotionModel {
+    Velocity, // drive commands through the robot's kinematics
+    Odometry, // rot1-trans-rot2 from wheel encoders
+}
+
+impl MotionModel {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Velocity => Self::Odometry,
+            Self::Odometry => Self::Velocity,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Velocity => "velocity",
+            Self::Odometry => "odometry",
+        }
+    }
+}
+
+/*
+ * relative motion between two odometry poses, decomposed into an initial
+ * rotation, a straight translation and a final rotation
+ * (Probabilistic Robotics, section 5.4)
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct OdometryMotion {
+    pub rot1: f32,
+    pub trans: f32,
+    pub rot2: f32,
+}
+
+impl OdometryMotion {
+    /*
+     * the robot moves along an arc between encoder reads, and the chord of
+     * that arc leaves at half the heading change, turned further by any
+     * sideways travel the lateral wheel counted
+     */
+    pub fn from_ticks(ticks: EncoderTicks, cfg: &Config) -> Self {
+        let left = ticks.left as f32 / cfg.encoder_ticks_per_unit;
+        let right = ticks.right as f32 / cfg.encoder_ticks_per_unit;
+        let lateral = ticks.lateral as f32 / cfg.encoder_ticks_per_unit;
+
+        let arc = 0.5 * (left + right);
+        let rotation = (right - left) / cfg.wheel_base;
+        let half_rotation = 0.5 * rotation;
+        let chord_ratio = if half_rotation.abs() > 1e-6 {
+            half_rotation.sin() / half_rotation
+        } else {
+            1.0
+        };
+
+        Self::from_body(arc * chord_ratio, lateral * chord_ratio, rotation)
+    }
+
+    /*
+     * forward and lateral travel in the body frame at the middle of the
+     * motion, and the heading change. translation is signed, so driving
+     * backwards doesn't show up as a half turn in rot1
+     */
+    pub fn from_body(forward: f32, lateral: f32, rotation: f32) -> Self {
+        let direction = if forward != 0.0 {
+            (lateral / forward).atan()
+        } else if lateral != 0.0 {
+            std::f32::consts::FRAC_PI_2.copysign(lateral)
+        } else {
+            0.0
+        };
+        let trans = forward.signum() * forward.hypot(lateral);
+
+        Self {
+            rot1: 0.5 * rotation + direction,
+            trans,
+            rot2: 0.5 * rotation - direction,
+        }
+    }
+
+    pub fn apply(&self, pose: (f32, f32, f32)) -> (f32, f32, f32) {
+        let (x, y, theta) = pose;
+        let heading = theta + self.rot1;
+        let theta = theta + self.rot1 + self.rot2;
+
+        (
+            x + self.trans * heading.cos(),
+            y + self.trans * heading.sin(),
+            f32::atan2(theta.sin(), theta.cos()), // normalize to (-PI, PI]
+        )
+    }
+}
+
+/*
+ * what the estimators are told about the robot's motion over one frame,
+ * depending on the selected motion model
+ */
+#[derive(Clone, Copy, Debug)]
+pub enum Control {
+    Velocity {
+        command: Vector3<f32>,
+        drive: DriveType,
+        delta_time: f32,
+    },
+    Odometry {
+        motion: OdometryMotion,
+        delta_time: f32, // between the encoder reads
+    },
+}
+
+impl Control {
+    // the velocity model moves at its body velocity, oriented at the middle of the frame
+    pub fn as_odometry(&self, cfg: &Config) -> OdometryMotion {
+        match *self {
+            Self::Velocity {
+                command,
+                drive,
+                delta_time,
+            } => {
+                let travel = drive.model().body_velocity(command, cfg) * delta_time;
+                OdometryMotion::from_body(travel[0], travel[1], travel[2])
+            }
+            Self::Odometry { motion, .. } => motion,
+        }
+    }
+
+    pub fn delta_time(&self) -> f32 {
+        match *self {
+            Self::Velocity { delta_time, .. } | Self::Odometry { delta_time, .. } => delta_time,
+        }
+    }
+
+    // pose after applying the control without noise
+    pub fn apply(&self, pose: (f32, f32, f32), cfg: &Config) -> (f32, f32, f32) {
+        self.as_odometry(cfg).apply(pose)
+    }
+
+    /*
+     * jacobian of the motion with respect to the robot pose, and the process
+     * noise covariance mapped into pose space
+     */
+    pub fn linearize(&self, theta: f32, cfg: &Config) -> (Matrix3<f32>, Matrix3<f32>) {
+        let motion = self.as_odometry(cfg);
+        let heading = theta + motion.rot1;
+
+        let f_x = Matrix3::new(
+            1.0,
+            0.0,
+            -motion.trans * heading.sin(),
+            0.0,
+            1.0,
+            motion.trans * heading.cos(),
+            0.0,
+            0.0,
+            1.0,
+        );
+
+        let process_noise = match *self {
+            Self::Velocity {
+                command,
+                drive,
+                delta_time,
+            } => {
+                let n = Matrix3::from_diagonal(&command_stdevs(command, drive, cfg).map(|s| s * s));
+
+                // body frame at the middle of the frame, without the sideways direction of travel
+                let body_heading = theta + 0.5 * (motion.rot1 + motion.rot2);
+                let (sin, cos) = body_heading.sin_cos();
+                let body_to_pose = Matrix3::new(
+                    cos * delta_time,
+                    -sin * delta_time,
+                    0.0,
+                    sin * delta_time,
+                    cos * delta_time,
+                    0.0,
+                    0.0,
+                    0.0,
+                    delta_time,
+                );
+
+                // jacobian of control noise (assumes noise is on controls, not state
+                // and noise is independent between the commands)
+                let f_n = body_to_pose * drive.model().jacobian(command, cfg);
+
+                f_n * n * f_n.transpose()
+            }
+            Self::Odometry { motion, .. } => {
+                let (sigma_rot1, sigma_trans, sigma_rot2) = odometry_stdevs(&motion, cfg);
+                let n = Matrix3::from_diagonal(&Vector3::new(
+                    sigma_rot1.powi(2),
+                    sigma_trans.powi(2),
+                    sigma_rot2.powi(2),
+                ));
+
+                // jacobian with respect to rot1, trans and rot2
+                let f_n = Matrix3::new(
+                    -motion.trans * heading.sin(),
+                    heading.cos(),
+                    0.0,
+                    motion.trans * heading.cos(),
+                    heading.sin(),
+                    0.0,
+                    1.0,
+                    0.0,
+                    1.0,
+                );
+
+                f_n * n * f_n.transpose()
+            }
+        };
+
+        (f_x, process_noise)
+    }
+
+    // the control perturbed by a draw from its noise model
+    pub fn sample(&self, rng: &mut Rng, cfg: &Config) -> Self {
+        match *self {
+            Self::Velocity {
+                command,
+                drive,
+                delta_time,
+            } => Self::Velocity {
+                command: command
+                    + command_stdevs(command, drive, cfg)
+                        .map(|sigma| rng.sample_normal(0.0, sigma)),
+                drive,
+                delta_time,
+            },
+            Self::Odometry { motion, delta_time } => {
+                let (sigma_rot1, sigma_trans, sigma_rot2) = odometry_stdevs(&motion, cfg);
+
+                Self::Odometry {
+                    motion: OdometryMotion {
+                        rot1: motion.rot1 + rng.sample_normal(0.0, sigma_rot1),
+                        trans: motion.trans + rng.sample_normal(0.0, sigma_trans),
+                        rot2: motion.rot2 + rng.sample_normal(0.0, sigma_rot2),
+                    },
+                    delta_time,
+                }
+            }
+        }
+    }
+}
+
+// add 0.01 so noise doesn't vanish at 0 speed
+fn command_stdevs(command: Vector3<f32>, drive: DriveType, cfg: &Config) -> Vector3<f32> {
+    drive
+        .model()
+        .command_stdevs(command, cfg.est_stdev_linear, cfg.est_stdev_angular)
+        .add_scalar(0.01)
+}
+
+/*
+ * rotation noise grows with rotation (alpha 1) and translation (alpha 2),
+ * translation noise with translation (alpha 3) and rotation (alpha 4)
+ */
+fn odometry_stdevs(motion: &OdometryMotion, cfg: &Config) -> (f32, f32, f32) {
+    let [alpha1, alpha2, alpha3, alpha4] = cfg.est_odometry_alphas;
+    let rot1_sq = motion.rot1 * motion.rot1;
+    let trans_sq = motion.trans * motion.trans;
+    let rot2_sq = motion.rot2 * motion.rot2;
+
+    // add a small floor so noise doesn't vanish when the wheels stand still
+    (
+        (alpha1 * rot1_sq + alpha2 * trans_sq).sqrt() + 1e-4,
+        (alpha3 * trans_sq + alpha4 * (rot1_sq + rot2_sq)).sqrt() + 1e-3,
+        (alpha1 * rot2_sq + alpha2 * trans_sq).sqrt() + 1e-4,
+    )
+}
@@ -0,0 +1,1262 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use macroquad::prelude::{Color, Vec2, vec2};
+use nalgebra::{Matrix2, Matrix3, Vector2, Vector3};
+
+use crate::config::Config;
+use crate::geometry::RigidTransform;
+use crate::simulation::{GpsFix, LidarScan, Observation};
+use crate::slam::Slam;
+use crate::slam::incremental::{IncrementalSolver, LinearRow};
+use crate::slam::loop_closure::LoopClosure;
+use crate::slam::measurement;
+use crate::slam::motion::Control;
+use crate::slam::robust::RobustKernel;
+use crate::spatial_index::ObstructionGrid;
+use crate::utils::relative_to_absolute;
+
+// prior pinning the first keyframe, so the graph can't drift as a whole
+const ANCHOR_INFORMATION: f64 = 1e6;
+
+// variance added to the controls between keyframes, in position and heading
+const ODOMETRY_FLOOR: (f64, f64) = (1e-2, 1e-6);
+
+// candidates scan matched against a new keyframe, nearest first, until one matches
+const LOOP_CANDIDATES: usize = 3;
+
+// a constraint weighted below this is as good as rejected
+pub const REJECTED_WEIGHT: f32 = 0.1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EdgeKind {
+    Odometry,  // from the controls between consecutive keyframes
+    ScanMatch, // from lining up the scans of keyframes that see the same place
+}
+
+pub struct Keyframe {
+    pub pose: Vector3<f32>,
+    pub points: Vec<Vec2>,      // its lidar scan, in its own frame
+    offset: usize,              // where its pose starts in the solver's state
+    linearized: Vector3<f32>,   // the pose its measurements were last linearized at
+    relinearized: Vector3<f32>, // and will be, once the relinearization under way is done
+    odometer: f32,              // distance travelled when it was taken
+    clock: f32,                 // and seconds since the start
+}
+
+pub struct GraphLandmark {
+    pub id: usize,
+    pub position: Vector2<f32>,
+    offset: usize,
+    linearized: Vector2<f32>,
+    relinearized: Vector2<f32>,
+}
+
+// relative pose of one keyframe seen from another, and how sure that is
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub measurement: Vector3<f32>, // pose of `to` in the frame of `from`
+    pub information: Matrix3<f64>, // double, as odometry can be all but certain sideways
+    pub kind: EdgeKind,
+    pub weight: f32, // how much it counts against the rest, from 1 down to 0 for rejected
+    switch: Option<Switch>,
+}
+
+// a landmark seen from a keyframe
+pub struct Sighting {
+    pub keyframe: usize,
+    pub landmark: usize, // index into the graph's landmarks
+    pub observation: Observation,
+    pub weight: f32,
+    switch: Option<Switch>,
+}
+
+// a switch variable scaling a constraint's error, from 1 (on) down to 0 (off)
+struct Switch {
+    offset: usize,
+    value: f32,
+    linearized: f32,
+    relinearized: f32,
+}
+
+// which point rows are linearized at
+#[derive(Clone, Copy)]
+enum At {
+    Estimate,        // the current one, on top of the factor in use
+    Relinearization, // where the relinearization under way started, from scratch
+}
+
+/*
+ * a relinearization spread over frames: every constraint there was when it
+ * started, linearized where the estimate was then and factored afresh by the
+ * solver a budget at a time, while the factor in use takes in new keyframes.
+ * those that came in since go on top once it's done
+ */
+struct Relinearization {
+    keyframes: usize, // what it covers
+    landmarks: usize,
+    edges: usize,
+    sightings: usize,
+    variables: usize,             // the solver's, switches included
+    linearized: usize,            // edges, then sightings, done so far
+    rows: Option<Vec<LinearRow>>, // until they're handed to the solver
+    last: Option<usize>,          // variable to order last, when reordering
+}
+
+/*
+ * graph SLAM on lidar scans and landmarks: a keyframe every so far or so
+ * much turning, tied to the one before by the controls in between, to older
+ * ones nearby wherever their scans line up (ICP), and to the landmarks seen
+ * from it. it runs in lidar-only scenes with no landmarks at all. every new
+ * keyframe's measurements are rotated into an incremental factorization and
+ * the estimate updated by back substitution. once the estimate has moved
+ * too far from where it was linearized, the whole graph is relinearized, and
+ * reordered as well once loops have filled the factor in, spread over as many
+ * frames as that takes. in between keyframes, the pose is the last
+ * keyframe's plus the controls since
+ * http://www.cs.cmu.edu/~kaess/pub/Kaess08tro.pdf
+ * http://www2.informatik.uni-freiburg.de/~stachnis/pdf/grisetti10titsmag.pdf
+ */
+pub struct PoseGraphSlam {
+    pub keyframes: Vec<Keyframe>,
+    pub landmarks: Vec<GraphLandmark>,
+    pub edges: Vec<Edge>,
+    pub sightings: Vec<Sighting>,
+    landmark_index: HashMap<usize, usize>, // landmark id to index
+    unconfirmed: HashMap<usize, (usize, Observation)>, // unmapped ids, by the keyframe that last saw them
+    anchor: Vector3<f32>,                              // where the first keyframe is pinned
+    solver: IncrementalSolver,
+    relinearization: Option<Relinearization>,
+    relinearize_failed: bool, // so it's tried again on the next keyframe
+    kernel: RobustKernel,     // the one the rows in the solver were built with
+    confirming: bool,         // whether landmarks need backing up to show on the map
+    observations: Vec<Observation>,
+    since_keyframe: Vector3<f32>, // relative pose since the last keyframe, in its frame
+    since_covariance: Matrix3<f32>,
+    odometer: f32,
+    clock: f32,
+    events: Vec<LoopClosure>,
+}
+
+impl PoseGraphSlam {
+    pub const COLOR: Color = Color::new(1.0, 0.6, 0.1, 0.5);
+
+    pub fn new() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            landmarks: Vec::new(),
+            edges: Vec::new(),
+            sightings: Vec::new(),
+            landmark_index: HashMap::new(),
+            unconfirmed: HashMap::new(),
+            anchor: Vector3::zeros(),
+            solver: IncrementalSolver::new(),
+            relinearization: None,
+            relinearize_failed: false,
+            kernel: RobustKernel::Off,
+            confirming: true,
+            observations: Vec::new(),
+            since_keyframe: Vector3::zeros(),
+            since_covariance: Matrix3::zeros(),
+            odometer: 0.0,
+            clock: 0.0,
+            events: Vec::new(),
+        }
+    }
+
+    /*
+     * takes a keyframe once the robot has moved or turned far enough since
+     * the last one, links it to the graph, closes any loop it finds and
+     * brings the estimate up to date. a relinearization under way gets on
+     * with it every frame
+     */
+    pub fn update_scan(&mut self, scan: &LidarScan, cfg: &Config) {
+        self.continue_relinearization(cfg);
+
+        let since = self.since_keyframe;
+        if !self.keyframes.is_empty()
+            && since.xy().norm() < cfg.keyframe_distance
+            && since.z.abs() < cfg.keyframe_angle
+        {
+            return;
+        }
+        let kernel_changed = self.kernel != cfg.graph_kernel;
+        if kernel_changed {
+            self.set_kernel(cfg.graph_kernel, cfg);
+        }
+        self.confirming = cfg.graph_confirm_landmarks;
+
+        let before = self.get_state();
+        let pose = to_vector(before);
+        self.keyframes.push(Keyframe {
+            pose,
+            points: scan.points(cfg),
+            offset: self.solver.add_variable(3),
+            linearized: pose,
+            relinearized: pose,
+            odometer: self.odometer,
+            clock: self.clock,
+        });
+        let newest = self.keyframes.len() - 1;
+        let mut rows = Vec::new();
+        if newest == 0 {
+            self.anchor = pose;
+            rows.extend(self.anchor_rows(At::Estimate));
+        } else {
+            // floored, so controls that were certain in some direction don't make it singular
+            let covariance = self.since_covariance.cast::<f64>()
+                + Matrix3::from_diagonal(&Vector3::new(
+                    ODOMETRY_FLOOR.0,
+                    ODOMETRY_FLOOR.0,
+                    ODOMETRY_FLOOR.1,
+                ));
+            let information = covariance
+                .try_inverse()
+                .map(|information| 0.5 * (information + information.transpose()))
+                .unwrap_or_else(Matrix3::identity);
+            self.edges.push(Edge {
+                from: newest - 1,
+                to: newest,
+                measurement: since,
+                information,
+                kind: EdgeKind::Odometry,
+                weight: 1.0,
+                switch: None,
+            });
+        }
+        self.since_keyframe = Vector3::zeros();
+        self.since_covariance = Matrix3::zeros();
+
+        // the landmarks seen since; placing a new one takes both range and bearing
+        self.unconfirmed
+            .retain(|_, (keyframe, _)| newest - *keyframe <= cfg.graph_confirm_keyframes);
+        let first_sighting = self.sightings.len();
+        for observation in std::mem::take(&mut self.observations) {
+            let landmark = match self.landmark_index.get(&observation.id) {
+                Some(&landmark) => landmark,
+                None => {
+                    let Some(position) = self.place(newest, &observation) else {
+                        continue;
+                    };
+                    // confirming, in once an earlier keyframe's sighting agrees, which keeps
+                    // out clutter and most of what wrong ids would put on the map
+                    let mut first = None;
+                    if cfg.graph_confirm_landmarks {
+                        let backed_up = self.unconfirmed.get(&observation.id).copied().filter(
+                            |(keyframe, first)| {
+                                self.place(*keyframe, first).is_some_and(|first| {
+                                    (first - position).norm() < cfg.graph_landmark_gate
+                                })
+                            },
+                        );
+                        if backed_up.is_none() {
+                            self.unconfirmed
+                                .insert(observation.id, (newest, observation));
+                            continue;
+                        }
+                        self.unconfirmed.remove(&observation.id);
+                        first = backed_up;
+                    }
+                    self.landmarks.push(GraphLandmark {
+                        id: observation.id,
+                        position,
+                        offset: self.solver.add_variable(2),
+                        linearized: position,
+                        relinearized: position,
+                    });
+                    let landmark = self.landmarks.len() - 1;
+                    self.landmark_index.insert(observation.id, landmark);
+                    if let Some((keyframe, first)) = first {
+                        self.add_sighting(keyframe, landmark, first);
+                    }
+                    landmark
+                }
+            };
+            self.add_sighting(newest, landmark, observation);
+        }
+
+        let first_edge = self.edges.len() - usize::from(newest > 0);
+        let closed = self.close_loops(newest, cfg);
+        rows.extend(self.linearize_edges(first_edge..self.edges.len(), At::Estimate, cfg));
+        rows.extend(self.linearize_sightings(
+            first_sighting..self.sightings.len(),
+            At::Estimate,
+            cfg,
+        ));
+        let work: usize = rows.iter().map(|row| self.solver.add_row(row)).sum();
+        self.update_estimate();
+
+        // a new kernel starts over even if a relinearization is under way
+        let replaced = self.relinearization.is_none()
+            && self.replace_outvoted(first_sighting..self.sightings.len(), cfg);
+        let reorder = self.solver.fill() > cfg.graph_fill_limit || work > cfg.graph_rotation_limit;
+        if kernel_changed
+            || self.relinearization.is_none()
+                && (self.relinearize_failed
+                    || replaced
+                    || reorder
+                    || self.moved_from_linearization(cfg))
+        {
+            self.start_relinearization(reorder.then_some(self.keyframes[newest].offset));
+        }
+
+        if !closed.is_empty() {
+            let after = self.get_state();
+            let oldest = &self.keyframes[closed[0].0];
+            let turn = after.2 - before.2;
+            self.events.push(LoopClosure {
+                distance: self.odometer - oldest.odometer,
+                elapsed: self.clock - oldest.clock,
+                landmarks: closed,
+                pose: after,
+                correction: (after.0 - before.0).hypot(after.1 - before.1),
+                heading_correction: turn.sin().atan2(turn.cos()).abs(),
+            });
+        }
+    }
+
+    // nonzeros in the square root information matrix, and how much that's grown since reordering
+    pub fn factor_size(&self) -> (usize, f32) {
+        (self.solver.nonzeros(), self.solver.fill())
+    }
+
+    // scan matches and sightings the robust kernel, or their switches, have all but thrown out
+    pub fn rejected(&self) -> usize {
+        let edges = self.edges.iter().map(|edge| edge.weight);
+        let sightings = self.sightings.iter().map(|sighting| sighting.weight);
+        edges
+            .chain(sightings)
+            .filter(|&weight| weight < REJECTED_WEIGHT)
+            .count()
+    }
+
+    /*
+     * rows are built with the kernel from here on; switching to switchable
+     * constraints gives every scan match and sighting that hasn't got one a
+     * switch, turned on, and held on in the factor in use until the next
+     * relinearization brings it in
+     */
+    fn set_kernel(&mut self, kernel: RobustKernel, cfg: &Config) {
+        self.kernel = kernel;
+        if kernel != RobustKernel::Switchable {
+            return;
+        }
+        let edges = self
+            .edges
+            .iter_mut()
+            .filter(|edge| edge.kind == EdgeKind::ScanMatch)
+            .map(|edge| &mut edge.switch);
+        let sightings = self
+            .sightings
+            .iter_mut()
+            .map(|sighting| &mut sighting.switch);
+        for switch in edges.chain(sightings).filter(|switch| switch.is_none()) {
+            let added = Switch::on(self.solver.add_variable(1));
+            self.solver
+                .add_row(&switch_prior(&added, At::Estimate, cfg));
+            *switch = Some(added);
+        }
+    }
+
+    // where a keyframe's sighting puts a landmark, if it measured both range and bearing
+    fn place(&self, keyframe: usize, observation: &Observation) -> Option<Vector2<f32>> {
+        let pose = self.keyframes[keyframe].pose;
+        let (x, y) = relative_to_absolute(
+            pose.x,
+            pose.y,
+            pose.z,
+            observation.range?,
+            observation.bearing?,
+        );
+        Some(Vector2::new(x, y))
+    }
+
+    fn add_sighting(&mut self, keyframe: usize, landmark: usize, observation: Observation) {
+        let switch = self.new_switch();
+        self.sightings.push(Sighting {
+            keyframe,
+            landmark,
+            observation,
+            weight: 1.0,
+            switch,
+        });
+    }
+
+    /*
+     * moves each landmark in these sightings that the kernel has turned most
+     * of its sightings down on, as it does once a wrong id placed it, to
+     * where the most of them agree, switching those on and the rest off.
+     * left alone if no more agree there than it has already. whether any
+     * moved, and so needs relinearizing
+     */
+    fn replace_outvoted(&mut self, sightings: Range<usize>, cfg: &Config) -> bool {
+        if self.kernel == RobustKernel::Off {
+            return false;
+        }
+        let mut landmarks: Vec<usize> = self.sightings[sightings]
+            .iter()
+            .map(|sighting| sighting.landmark)
+            .collect();
+        landmarks.sort_unstable();
+        landmarks.dedup();
+
+        let mut moved = false;
+        for landmark in landmarks {
+            let sightings: Vec<usize> = (0..self.sightings.len())
+                .filter(|&index| self.sightings[index].landmark == landmark)
+                .collect();
+            let counted = sightings
+                .iter()
+                .filter(|&&index| self.sightings[index].weight >= REJECTED_WEIGHT)
+                .count();
+            if 2 * counted >= sightings.len() {
+                continue;
+            }
+
+            let placed: Vec<(usize, Vector2<f32>)> = sightings
+                .iter()
+                .filter_map(|&index| {
+                    let sighting = &self.sightings[index];
+                    Some((index, self.place(sighting.keyframe, &sighting.observation)?))
+                })
+                .collect();
+            let agreeing = placed
+                .iter()
+                .map(|&(_, position)| {
+                    placed
+                        .iter()
+                        .filter(|(_, other)| (other - position).norm() < cfg.graph_landmark_gate)
+                        .copied()
+                        .collect::<Vec<_>>()
+                })
+                .max_by_key(Vec::len)
+                .unwrap_or_default();
+            if agreeing.len() <= counted {
+                continue;
+            }
+
+            let sum: Vector2<f32> = agreeing.iter().map(|&(_, position)| position).sum();
+            self.landmarks[landmark].position = sum / agreeing.len() as f32;
+            for index in sightings {
+                let on = agreeing.iter().any(|&(agrees, _)| agrees == index);
+                if let Some(switch) = &mut self.sightings[index].switch {
+                    switch.value = if on { 1.0 } else { 0.0 };
+                }
+            }
+            moved = true;
+        }
+        moved
+    }
+
+    // a switch for a new scan match or sighting, when the kernel calls for one
+    fn new_switch(&mut self) -> Option<Switch> {
+        (self.kernel == RobustKernel::Switchable).then(|| Switch::on(self.solver.add_variable(1)))
+    }
+
+    /*
+     * scan matches the newest keyframe against older ones close to it, and
+     * adds an edge for the first that matches, unless one was added only a
+     * few keyframes back; returns the keyframe matched, if any, with where it
+     * was before optimizing
+     */
+    fn close_loops(&mut self, newest: usize, cfg: &Config) -> Vec<(usize, (f32, f32))> {
+        // one loop edge every few keyframes holds the map as well as one on each, at far less fill
+        let recently_matched = self
+            .edges
+            .iter()
+            .rev()
+            .take_while(|edge| edge.to + cfg.loop_search_interval > newest)
+            .any(|edge| edge.kind == EdgeKind::ScanMatch);
+        if recently_matched {
+            return Vec::new();
+        }
+
+        let pose = self.keyframes[newest].pose;
+        let mut candidates: Vec<(usize, f32)> = self.keyframes
+            [..newest.saturating_sub(cfg.loop_min_separation)]
+            .iter()
+            .enumerate()
+            .map(|(index, keyframe)| (index, (keyframe.pose.xy() - pose.xy()).norm()))
+            .filter(|&(_, distance)| distance < cfg.loop_search_radius)
+            .collect();
+        candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut closed = Vec::new();
+        for &(index, _) in candidates.iter().take(LOOP_CANDIDATES) {
+            let candidate = &self.keyframes[index];
+            let guess = relative(candidate.pose, pose);
+            let Some(matched) = match_scans(
+                &self.keyframes[newest].points,
+                &candidate.points,
+                RigidTransform {
+                    angle: guess.z,
+                    translation: vec2(guess.x, guess.y),
+                },
+                cfg,
+            ) else {
+                continue;
+            };
+
+            let switch = self.new_switch();
+            self.edges.push(Edge {
+                from: index,
+                to: newest,
+                measurement: Vector3::new(
+                    matched.translation.x,
+                    matched.translation.y,
+                    matched.angle,
+                ),
+                information: Matrix3::from_diagonal(&Vector3::new(
+                    cfg.scan_match_stdev_position.powi(2).recip(),
+                    cfg.scan_match_stdev_position.powi(2).recip(),
+                    cfg.scan_match_stdev_angle.powi(2).recip(),
+                ))
+                .cast::<f64>(),
+                kind: EdgeKind::ScanMatch,
+                weight: 1.0,
+                switch,
+            });
+            let candidate = self.keyframes[index].pose;
+            closed.push((index, (candidate.x, candidate.y)));
+            break;
+        }
+        closed
+    }
+
+    /*
+     * whether any variable has moved so far from where its measurements were
+     * linearized that the linearization no longer holds
+     */
+    fn moved_from_linearization(&self, cfg: &Config) -> bool {
+        let distance = cfg.graph_relinearize_distance as f64;
+        self.keyframes.iter().any(|keyframe| {
+            let step = keyframe.step();
+            step.xy().norm() > distance || step.z.abs() > cfg.graph_relinearize_angle as f64
+        }) || self
+            .landmarks
+            .iter()
+            .any(|landmark| landmark.step().norm() > distance)
+    }
+
+    /*
+     * a new linearization point where the estimate is now, reordering the
+     * variables too if given the newest keyframe's, to be eliminated last
+     */
+    fn start_relinearization(&mut self, last: Option<usize>) {
+        for keyframe in self.keyframes.iter_mut() {
+            keyframe.relinearized = keyframe.pose;
+        }
+        for landmark in self.landmarks.iter_mut() {
+            landmark.relinearized = landmark.position;
+        }
+        let edges = self.edges.iter_mut().map(|edge| &mut edge.switch);
+        let sightings = self
+            .sightings
+            .iter_mut()
+            .map(|sighting| &mut sighting.switch);
+        for switch in edges.chain(sightings).flatten() {
+            switch.relinearized = switch.value;
+        }
+
+        self.relinearization = Some(Relinearization {
+            keyframes: self.keyframes.len(),
+            landmarks: self.landmarks.len(),
+            edges: self.edges.len(),
+            sightings: self.sightings.len(),
+            variables: self.solver.variables(),
+            linearized: 0,
+            rows: Some(self.anchor_rows(At::Relinearization)),
+            last,
+        });
+        self.relinearize_failed = false;
+    }
+
+    /*
+     * a frame's worth of the relinearization under way: linearizing its
+     * constraints, then factoring them
+     */
+    fn continue_relinearization(&mut self, cfg: &Config) {
+        let Some(mut relinearization) = self.relinearization.take() else {
+            return;
+        };
+        let mut budget = cfg.graph_batch_budget;
+
+        if let Some(mut rows) = relinearization.rows.take() {
+            let (edges, sightings) = (relinearization.edges, relinearization.sightings);
+            while relinearization.linearized < edges + sightings && budget > 0 {
+                let next = relinearization.linearized;
+                let constraint_rows = if next < edges {
+                    self.linearize_edges(next..next + 1, At::Relinearization, cfg)
+                } else {
+                    self.linearize_sightings(
+                        next - edges..next - edges + 1,
+                        At::Relinearization,
+                        cfg,
+                    )
+                };
+                let entries: usize = constraint_rows.iter().map(|row| row.entries.len()).sum();
+                budget = budget.saturating_sub(entries);
+                rows.extend(constraint_rows);
+                relinearization.linearized += 1;
+            }
+            if relinearization.linearized < edges + sightings {
+                relinearization.rows = Some(rows);
+            } else {
+                self.solver
+                    .start_refactor(rows, relinearization.variables, relinearization.last);
+            }
+        }
+
+        if relinearization.rows.is_none() {
+            match self.solver.continue_refactor(&mut budget) {
+                Some(true) => return self.finish_relinearization(&relinearization, cfg),
+                Some(false) => return self.relinearize_failed = true,
+                None => {}
+            }
+        }
+        self.relinearization = Some(relinearization);
+    }
+
+    /*
+     * the new factor is in: everything it covers is linearized where it
+     * started, and the constraints that came in since go on top
+     */
+    fn finish_relinearization(&mut self, relinearization: &Relinearization, cfg: &Config) {
+        for keyframe in self.keyframes[..relinearization.keyframes].iter_mut() {
+            keyframe.linearized = keyframe.relinearized;
+        }
+        for landmark in self.landmarks[..relinearization.landmarks].iter_mut() {
+            landmark.linearized = landmark.relinearized;
+        }
+        let edges = self.edges[..relinearization.edges]
+            .iter_mut()
+            .map(|edge| &mut edge.switch);
+        let sightings = self.sightings[..relinearization.sightings]
+            .iter_mut()
+            .map(|sighting| &mut sighting.switch);
+        for switch in edges.chain(sightings).flatten() {
+            switch.linearized = switch.relinearized;
+        }
+
+        let mut rows =
+            self.linearize_edges(relinearization.edges..self.edges.len(), At::Estimate, cfg);
+        rows.extend(self.linearize_sightings(
+            relinearization.sightings..self.sightings.len(),
+            At::Estimate,
+            cfg,
+        ));
+        for row in &rows {
+            self.solver.add_row(row);
+        }
+        self.update_estimate();
+    }
+
+    // every variable at its linearization point plus the solver's step
+    fn update_estimate(&mut self) {
+        let Some(step) = self.solver.solve() else {
+            return;
+        };
+        for keyframe in self.keyframes.iter_mut() {
+            let offset = keyframe.offset;
+            keyframe.pose =
+                keyframe.linearized + step.fixed_rows::<3>(offset).into_owned().cast::<f32>();
+            keyframe.pose.z = normalize(keyframe.pose.z);
+        }
+        for landmark in self.landmarks.iter_mut() {
+            let offset = landmark.offset;
+            landmark.position =
+                landmark.linearized + step.fixed_rows::<2>(offset).into_owned().cast::<f32>();
+        }
+
+        // switches stay between off and on; while they're in charge, they're the weight
+        let switched = self.kernel == RobustKernel::Switchable;
+        let edges = self
+            .edges
+            .iter_mut()
+            .map(|edge| (&mut edge.switch, &mut edge.weight));
+        let sightings = self
+            .sightings
+            .iter_mut()
+            .map(|sighting| (&mut sighting.switch, &mut sighting.weight));
+        for (switch, weight) in edges.chain(sightings) {
+            if let Some(switch) = switch {
+                switch.value = (switch.linearized + step[switch.offset] as f32).clamp(0.0, 1.0);
+                if switched {
+                    *weight = switch.value.powi(2);
+                }
+            }
+        }
+    }
+
+    // rows for these edges, noting how much each counts
+    fn linearize_edges(&mut self, edges: Range<usize>, at: At, cfg: &Config) -> Vec<LinearRow> {
+        let mut rows = Vec::new();
+        for index in edges {
+            let (edge_rows, weight) = self.edge_rows(&self.edges[index], at, cfg);
+            self.edges[index].weight = weight as f32;
+            rows.extend(edge_rows);
+        }
+        rows
+    }
+
+    // the same for sightings
+    fn linearize_sightings(
+        &mut self,
+        sightings: Range<usize>,
+        at: At,
+        cfg: &Config,
+    ) -> Vec<LinearRow> {
+        let mut rows = Vec::new();
+        for index in sightings {
+            let (sighting_rows, weight) = self.sighting_rows(&self.sightings[index], at, cfg);
+            self.sightings[index].weight = weight as f32;
+            rows.extend(sighting_rows);
+        }
+        rows
+    }
+
+    // the first keyframe held where it was taken
+    fn anchor_rows(&self, at: At) -> Vec<LinearRow> {
+        let keyframe = &self.keyframes[0];
+        let (pose, step) = keyframe.at(at);
+        let mut error = (pose - self.anchor).cast::<f64>();
+        error.z = error.z.sin().atan2(error.z.cos());
+        let predicted = step - error;
+
+        let weight = ANCHOR_INFORMATION.sqrt();
+        (0..3)
+            .map(|i| LinearRow {
+                entries: vec![(keyframe.offset + i, weight)],
+                rhs: weight * predicted[i],
+            })
+            .collect()
+    }
+
+    /*
+     * an edge linearized at the given point, as rows on the step from the
+     * linearization point, whitened by the square root of its information;
+     * scan matches are made robust. also how much it counts
+     */
+    fn edge_rows(&self, edge: &Edge, at: At, cfg: &Config) -> (Vec<LinearRow>, f64) {
+        let (from, to) = (&self.keyframes[edge.from], &self.keyframes[edge.to]);
+        let ((from_pose, from_step), (to_pose, to_step)) = (from.at(at), to.at(at));
+        let (error, a, b) = linearize_edge(from_pose, to_pose, edge.measurement);
+        let predicted = a * from_step + b * to_step - error;
+
+        let whiten = edge
+            .information
+            .cholesky()
+            .map_or_else(Matrix3::identity, |cholesky| cholesky.l().transpose());
+        let (a, b, predicted) = (whiten * a, whiten * b, whiten * predicted);
+        let rows = (0..3)
+            .map(|i| LinearRow {
+                entries: (0..3)
+                    .map(|j| (from.offset + j, a[(i, j)]))
+                    .chain((0..3).map(|j| (to.offset + j, b[(i, j)])))
+                    .collect(),
+                rhs: predicted[i],
+            })
+            .collect();
+
+        match edge.kind {
+            EdgeKind::Odometry => (rows, 1.0),
+            EdgeKind::ScanMatch => {
+                let residuals: Vec<f64> = (whiten * error).iter().copied().collect();
+                self.robust_rows(rows, &residuals, edge.switch.as_ref(), at, cfg)
+            }
+        }
+    }
+
+    // a sighting the same way, a row for each component the sensor measured
+    fn sighting_rows(&self, sighting: &Sighting, at: At, cfg: &Config) -> (Vec<LinearRow>, f64) {
+        let keyframe = &self.keyframes[sighting.keyframe];
+        let landmark = &self.landmarks[sighting.landmark];
+        let ((pose, robot_step), (position, landmark_step)) = (keyframe.at(at), landmark.at(at));
+        let linearization = measurement::linearize(
+            (pose.x, pose.y, pose.z),
+            position.x,
+            position.y,
+            &sighting.observation,
+            cfg,
+        );
+
+        let mut residuals = Vec::with_capacity(linearization.innovation.len());
+        let rows = (0..linearization.innovation.len())
+            .map(|i| {
+                let h_robot = linearization.h_robot.row(i).transpose().cast::<f64>();
+                let h_landmark = linearization.h_landmark.row(i).transpose().cast::<f64>();
+                let weight = (linearization.noise[(i, i)] as f64).sqrt().recip();
+                let innovation = linearization.innovation[i] as f64;
+                residuals.push(-weight * innovation);
+                LinearRow {
+                    entries: (0..3)
+                        .map(|j| (keyframe.offset + j, weight * h_robot[j]))
+                        .chain((0..2).map(|j| (landmark.offset + j, weight * h_landmark[j])))
+                        .collect(),
+                    rhs: weight
+                        * (h_robot.dot(&robot_step) + h_landmark.dot(&landmark_step) + innovation),
+                }
+            })
+            .collect();
+        self.robust_rows(rows, &residuals, sighting.switch.as_ref(), at, cfg)
+    }
+
+    /*
+     * a constraint's whitened rows guarded against it being wrong, given its
+     * whitened residuals at the current estimate: scaled by its switch, which
+     * gets a row of its own pulling it on, or else reweighted by the kernel.
+     * also the weight it ends up with
+     */
+    fn robust_rows(
+        &self,
+        mut rows: Vec<LinearRow>,
+        residuals: &[f64],
+        switch: Option<&Switch>,
+        at: At,
+        cfg: &Config,
+    ) -> (Vec<LinearRow>, f64) {
+        let weight = match switch {
+            // the error is the switch times the constraint's, linearized in both
+            Some(switch) if self.kernel == RobustKernel::Switchable => {
+                let (value, step) = switch.at(at);
+                let value = value as f64;
+                for (row, &residual) in rows.iter_mut().zip(residuals) {
+                    for entry in row.entries.iter_mut() {
+                        entry.1 *= value;
+                    }
+                    row.entries.push((switch.offset, residual));
+                    row.rhs = value * row.rhs + residual * step;
+                }
+                value * value
+            }
+            _ => {
+                let squared_error = residuals.iter().map(|residual| residual * residual).sum();
+                let weight = self.kernel.weight(squared_error, cfg);
+                for row in rows.iter_mut() {
+                    for entry in row.entries.iter_mut() {
+                        entry.1 *= weight.sqrt();
+                    }
+                    row.rhs *= weight.sqrt();
+                }
+                weight
+            }
+        };
+
+        // a switch is held on by its prior even while the kernel isn't using it
+        if let Some(switch) = switch {
+            rows.push(switch_prior(switch, at, cfg));
+        }
+        (rows, weight)
+    }
+}
+
+// the row pulling a switch on
+fn switch_prior(switch: &Switch, at: At, cfg: &Config) -> LinearRow {
+    let (value, step) = switch.at(at);
+    let prior = (cfg.graph_switch_stdev as f64).recip();
+    LinearRow {
+        entries: vec![(switch.offset, prior)],
+        rhs: prior * (step - (value as f64 - 1.0)),
+    }
+}
+
+impl Switch {
+    fn on(offset: usize) -> Self {
+        Self {
+            offset,
+            value: 1.0,
+            linearized: 1.0,
+            relinearized: 1.0,
+        }
+    }
+
+    fn at(&self, at: At) -> (f32, f64) {
+        match at {
+            At::Estimate => (self.value, (self.value - self.linearized) as f64),
+            At::Relinearization => (self.relinearized, 0.0),
+        }
+    }
+}
+
+impl Keyframe {
+    // how far the estimate has moved from the linearization point
+    fn step(&self) -> Vector3<f64> {
+        let mut step = (self.pose - self.linearized).cast::<f64>();
+        step.z = step.z.sin().atan2(step.z.cos());
+        step
+    }
+
+    // the pose rows are built at, and its step from where they're linearized
+    fn at(&self, at: At) -> (Vector3<f32>, Vector3<f64>) {
+        match at {
+            At::Estimate => (self.pose, self.step()),
+            At::Relinearization => (self.relinearized, Vector3::zeros()),
+        }
+    }
+}
+
+impl GraphLandmark {
+    fn step(&self) -> Vector2<f64> {
+        (self.position - self.linearized).cast::<f64>()
+    }
+
+    fn at(&self, at: At) -> (Vector2<f32>, Vector2<f64>) {
+        match at {
+            At::Estimate => (self.position, self.step()),
+            At::Relinearization => (self.relinearized, Vector2::zeros()),
+        }
+    }
+}
+
+/*
+ * error of an edge between two poses, and its jacobians with respect to
+ * each: the measured relative pose undone from the one the poses imply
+ */
+fn linearize_edge(
+    from: Vector3<f32>,
+    to: Vector3<f32>,
+    measurement: Vector3<f32>,
+) -> (Vector3<f64>, Matrix3<f64>, Matrix3<f64>) {
+    let (from, to, measurement) = (
+        from.cast::<f64>(),
+        to.cast::<f64>(),
+        measurement.cast::<f64>(),
+    );
+    let rotation = |angle: f64| Matrix2::new(angle.cos(), -angle.sin(), angle.sin(), angle.cos());
+    let (r_from, r_measured) = (rotation(from.z), rotation(measurement.z));
+    let offset: Vector2<f64> = to.xy() - from.xy();
+
+    let translation = r_measured.transpose() * (r_from.transpose() * offset - measurement.xy());
+    let angle = to.z - from.z - measurement.z;
+    let error = Vector3::new(translation.x, translation.y, angle.sin().atan2(angle.cos()));
+
+    // derivative of the transposed rotation by its angle
+    let d_r_from = Matrix2::new(-from.z.sin(), from.z.cos(), -from.z.cos(), -from.z.sin());
+    let rotated = r_measured.transpose() * r_from.transpose();
+    let turned = r_measured.transpose() * d_r_from * offset;
+
+    let mut a = Matrix3::zeros();
+    a.fixed_view_mut::<2, 2>(0, 0).copy_from(&-rotated);
+    a.fixed_view_mut::<2, 1>(0, 2).copy_from(&turned);
+    a[(2, 2)] = -1.0;
+
+    let mut b = Matrix3::zeros();
+    b.fixed_view_mut::<2, 2>(0, 0).copy_from(&rotated);
+    b[(2, 2)] = 1.0;
+
+    (error, a, b)
+}
+
+/*
+ * point to point ICP with correspondences gated ever tighter, down to the
+ * configured distance: the transform taking the source scan onto the
+ * target, from a guess, if enough of it lines up closely enough
+ */
+fn match_scans(
+    source: &[Vec2],
+    target: &[Vec2],
+    guess: RigidTransform,
+    cfg: &Config,
+) -> Option<RigidTransform> {
+    if source.is_empty() || target.is_empty() {
+        return None;
+    }
+
+    let pairs = |transform: &RigidTransform, gate: f32| -> Vec<(Vec2, Vec2)> {
+        source
+            .iter()
+            .filter_map(|&point| {
+                let moved = transform.apply(point);
+                let nearest = target.iter().copied().min_by(|a, b| {
+                    a.distance_squared(moved)
+                        .total_cmp(&b.distance_squared(moved))
+                })?;
+                (nearest.distance(moved) < gate).then_some((point, nearest))
+            })
+            .collect()
+    };
+
+    let mut transform = guess;
+    let mut gate = 4.0 * cfg.icp_max_distance;
+    for _ in 0..cfg.icp_iterations {
+        let matched = pairs(&transform, gate);
+        let next = RigidTransform::fit(&matched)?;
+        let moved = (next.translation - transform.translation).length()
+            + (next.angle - transform.angle).abs() * cfg.lidar_range;
+        transform = next;
+        if gate <= cfg.icp_max_distance && moved < 0.01 {
+            break;
+        }
+        gate = (0.5 * gate).max(cfg.icp_max_distance);
+    }
+
+    let matched = pairs(&transform, cfg.icp_max_distance);
+    let overlap = matched.len() as f32 / source.len().min(target.len()) as f32;
+    let squared: f32 = matched
+        .iter()
+        .map(|&(point, nearest)| transform.apply(point).distance_squared(nearest))
+        .sum();
+    let rms = (squared / matched.len().max(1) as f32).sqrt();
+    (overlap >= cfg.icp_min_overlap && rms <= cfg.icp_max_error).then_some(transform)
+}
+
+// pose of `to` in the frame of `from`
+fn relative(from: Vector3<f32>, to: Vector3<f32>) -> Vector3<f32> {
+    let offset = Vec2::from_angle(-from.z).rotate(vec2(to.x - from.x, to.y - from.y));
+    Vector3::new(offset.x, offset.y, normalize(to.z - from.z))
+}
+
+// a relative pose applied to a pose
+fn compose(pose: Vector3<f32>, relative: Vector3<f32>) -> Vector3<f32> {
+    let offset = Vec2::from_angle(pose.z).rotate(vec2(relative.x, relative.y));
+    Vector3::new(
+        pose.x + offset.x,
+        pose.y + offset.y,
+        normalize(pose.z + relative.z),
+    )
+}
+
+fn normalize(angle: f32) -> f32 {
+    angle.sin().atan2(angle.cos())
+}
+
+fn to_vector((x, y, theta): (f32, f32, f32)) -> Vector3<f32> {
+    Vector3::new(x, y, theta)
+}
+
+impl Slam for PoseGraphSlam {
+    // the controls since the last keyframe, with their uncertainty in its frame
+    fn predict(&mut self, control: &Control, cfg: &Config) {
+        let since = self.since_keyframe;
+        let (f_x, process_noise) = control.linearize(since.z, cfg);
+        self.since_covariance = f_x * self.since_covariance * f_x.transpose() + process_noise;
+        self.since_keyframe = to_vector(control.apply((since.x, since.y, since.z), cfg));
+
+        self.odometer += control.as_odometry(cfg).trans.abs();
+        self.clock += control.delta_time();
+    }
+
+    // kept for the next keyframe, see update_scan
+    fn update(
+        &mut self,
+        observations: &[Observation],
+        _obstructions: &ObstructionGrid,
+        _cfg: &Config,
+    ) {
+        self.observations = observations.to_vec();
+    }
+
+    // only relative constraints go in the graph
+    fn update_position(&mut self, _fix: &GpsFix, _cfg: &Config) {}
+
+    fn get_state(&self) -> (f32, f32, f32) {
+        let pose = match self.keyframes.last() {
+            Some(keyframe) => compose(keyframe.pose, self.since_keyframe),
+            None => self.since_keyframe,
+        };
+        (pose.x, pose.y, pose.z)
+    }
+
+    /*
+     * the landmarks, confirming only those more than one sighting still holds
+     * up, which leaves out one-off clutter and what wrong ids alone put there
+     */
+    fn get_landmarks(&self) -> Vec<(usize, f32, f32)> {
+        let mut support = vec![0; self.landmarks.len()];
+        for sighting in &self.sightings {
+            if sighting.weight >= REJECTED_WEIGHT {
+                support[sighting.landmark] += 1;
+            }
+        }
+        self.landmarks
+            .iter()
+            .zip(support)
+            .filter(|&(_, support)| !self.confirming || support > 1)
+            .map(|(landmark, _)| (landmark.id, landmark.position.x, landmark.position.y))
+            .collect()
+    }
+
+    fn take_loop_closures(&mut self) -> Vec<LoopClosure> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn color(&self) -> Color {
+        Self::COLOR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::rand;
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::simulation::{Landmark, Robot};
+
+    struct Lap {
+        landmark_error: f32, // mean, at the end
+        pose_error: f32,     // mean, on the way
+        relinearized: usize, // relinearizations that went through
+        failed: usize,       // frames after one that didn't
+    }
+
+    /*
+     * drives two laps of a circle between two rings of landmarks, never
+     * confirming landmarks, so the kernel is all that guards the map
+     */
+    fn circle(cfg: Config) -> Lap {
+        const FRAMES: usize = 1500;
+
+        rand::srand(7);
+        let cfg = Config {
+            graph_confirm_landmarks: false,
+            ..cfg
+        };
+        let delta_time = 1.0 / 60.0;
+        let obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+        let landmarks: Vec<Landmark> = (0..12)
+            .map(|id| {
+                let angle = id as f32 * std::f32::consts::TAU / 12.0;
+                let radius = if id % 2 == 0 { 120.0 } else { 280.0 };
+                Landmark {
+                    id,
+                    x: radius * angle.cos(),
+                    y: 200.0 + radius * angle.sin(),
+                    moving: false,
+                    velocity: (0.0, 0.0),
+                }
+            })
+            .collect();
+
+        let mut robot = Robot::new();
+        let mut graph = PoseGraphSlam::new();
+        let (mut pose_error, mut relinearized, mut failed) = (0.0, 0, 0);
+        for _ in 0..FRAMES {
+            robot.command = cfg.drive.model().command(100.0, 0.5, &cfg);
+            robot.update(delta_time, &cfg, &obstructions);
+            graph.predict(
+                &Control::Velocity {
+                    command: robot.command,
+                    drive: cfg.drive,
+                    delta_time,
+                },
+                &cfg,
+            );
+            let observations = robot.sense(&landmarks, &obstructions, &cfg);
+            graph.update(&observations, &obstructions, &cfg);
+            let linearized: Vec<Vector3<f32>> = graph
+                .keyframes
+                .iter()
+                .map(|keyframe| keyframe.linearized)
+                .collect();
+            graph.update_scan(&robot.scan(&obstructions, &cfg), &cfg);
+            let mut moved = graph.keyframes.iter().zip(&linearized);
+            relinearized +=
+                usize::from(moved.any(|(keyframe, before)| keyframe.linearized != *before));
+            failed += usize::from(graph.relinearize_failed);
+
+            let (x, y, _) = graph.get_state();
+            pose_error += (x - robot.x).hypot(y - robot.y) / FRAMES as f32;
+        }
+
+        let mapped = graph.get_landmarks();
+        let landmark_error = mapped
+            .iter()
+            .map(|&(id, x, y)| (x - landmarks[id].x).hypot(y - landmarks[id].y))
+            .sum::<f32>()
+            / mapped.len() as f32;
+        Lap {
+            landmark_error,
+            pose_error,
+            relinearized,
+            failed,
+        }
+    }
+
+    // within a bound of the same lap run some other way
+    fn assert_close(lap: &Lap, against: &Lap, label: &str) {
+        assert!(
+            lap.landmark_error < 1.5 * against.landmark_error + 2.0,
+            "{label}: landmark error {} against {}",
+            lap.landmark_error,
+            against.landmark_error
+        );
+        assert!(
+            lap.pose_error < 1.5 * against.pose_error + 2.0,
+            "{label}: pose error {} against {}",
+            lap.pose_error,
+            against.pose_error
+        );
+    }
+
+    #[test]
+    fn wrong_ids_leave_the_map_intact() {
+        let clean = circle(Config {
+            graph_kernel: RobustKernel::Off,
+            ..Config::default()
+        });
+        for kernel in [RobustKernel::Dcs, RobustKernel::Switchable] {
+            let lap = circle(Config {
+                graph_kernel: kernel,
+                wrong_id_probability: 0.1,
+                ..Config::default()
+            });
+            assert_close(&lap, &clean, kernel.name());
+        }
+    }
+
+    // keyframes keep coming while a relinearization is spread thin over many frames
+    #[test]
+    fn relinearizes_a_little_at_a_time() {
+        let whole = circle(Config::default());
+        let spread = circle(Config {
+            graph_batch_budget: 60,
+            ..Config::default()
+        });
+        assert_eq!(spread.failed, 0);
+        assert!(spread.relinearized > 0);
+        assert_close(&spread, &whole, "spread");
+    }
+
+    #[test]
+    fn unconfirmed_clutter_ages_out() {
+        rand::srand(7);
+        let cfg = Config {
+            clutter_rate: 2.0,
+            ..Config::default()
+        };
+        let delta_time = 1.0 / 60.0;
+        let obstructions = ObstructionGrid::new(cfg.spatial_cell_size);
+
+        let mut robot = Robot::new();
+        let mut graph = PoseGraphSlam::new();
+        let mut clutter = HashSet::new();
+        for _ in 0..1500 {
+            robot.command = cfg.drive.model().command(100.0, 0.5, &cfg);
+            robot.update(delta_time, &cfg, &obstructions);
+            graph.predict(
+                &Control::Velocity {
+                    command: robot.command,
+                    drive: cfg.drive,
+                    delta_time,
+                },
+                &cfg,
+            );
+            let observations = robot.sense(&[], &obstructions, &cfg);
+            clutter.extend(observations.iter().map(|observation| observation.id));
+            graph.update(&observations, &obstructions, &cfg);
+            graph.update_scan(&robot.scan(&obstructions, &cfg), &cfg);
+        }
+
+        let newest = graph.keyframes.len() - 1;
+        assert!(newest > 2 * cfg.graph_confirm_keyframes);
+        assert!(graph.unconfirmed.len() < clutter.len() / 2);
+        assert!(
+            graph
+                .unconfirmed
+                .values()
+                .all(|(keyframe, _)| newest - keyframe <= cfg.graph_confirm_keyframes)
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use crate::config::Config;
+
+/*
+ * how a least-squares backend keeps wrong associations from wrecking the
+ * solution. the kernels reweight each constraint by its whitened error
+ * (iteratively reweighted least squares); switchable constraints instead give
+ * each one a variable of its own that the optimizer can turn down, held near
+ * on by a prior (Sünderhauf and Protzel, IROS 2012), which dynamic covariance
+ * scaling solves for in closed form (Agarwal et al., ICRA 2013)
+ * https://doi.org/10.1109/IROS.2012.6385590
+ * http://www2.informatik.uni-freiburg.de/~spinello/agarwalICRA13.pdf
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RobustKernel {
+    Off,        // plain least squares
+    Huber,      // quadratic near zero, linear beyond the width
+    Cauchy,     // logarithmic, so gross errors count for almost nothing
+    Dcs,        // dynamic covariance scaling, switchable constraints in closed form
+    Switchable, // a switch variable on every constraint
+}
+
+impl RobustKernel {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Huber,
+            Self::Huber => Self::Cauchy,
+            Self::Cauchy => Self::Dcs,
+            Self::Dcs => Self::Switchable,
+            Self::Switchable => Self::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Huber => "Huber",
+            Self::Cauchy => "Cauchy",
+            Self::Dcs => "DCS",
+            Self::Switchable => "switchable",
+        }
+    }
+
+    /*
+     * the weight a constraint's information is scaled by, given its squared
+     * whitened error. a switch settles where DCS would put it, with phi the
+     * inverse square of the switch's prior stdev, so that's its weight here
+     */
+    pub fn weight(self, squared_error: f64, cfg: &Config) -> f64 {
+        let width = cfg.graph_kernel_width as f64;
+        let dcs = |phi: f64| (2.0 * phi / (phi + squared_error)).min(1.0).powi(2);
+        match self {
+            Self::Off => 1.0,
+            Self::Huber => {
+                let error = squared_error.sqrt();
+                if error <= width { 1.0 } else { width / error }
+            }
+            Self::Cauchy => (1.0 + squared_error / (width * width)).recip(),
+            Self::Dcs => dcs(cfg.graph_dcs_phi as f64),
+            Self::Switchable => dcs((cfg.graph_switch_stdev as f64).powi(-2)),
+        }
+    }
+}
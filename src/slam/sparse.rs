@@ -0,0 +1,146 @@
+/*
+ * symmetric matrix that is mostly zeros, as the normal equations of a pose
+ * graph are: a pose only shares entries with the poses it has an edge to.
+ * only the lower triangle is kept, row by row, as the entries were added:
+ * entries for the same place are summed when it's factored. double
+ * precision, since long chains of poses leave the system badly conditioned
+ */
+pub struct SparseSymmetric {
+    rows: Vec<Vec<(usize, f64)>>, // column and value, columns at or before the row
+}
+
+impl SparseSymmetric {
+    pub fn new(size: usize) -> Self {
+        Self {
+            rows: vec![Vec::new(); size],
+        }
+    }
+
+    pub fn add(&mut self, row: usize, column: usize, value: f64) {
+        let (row, column) = (row.max(column), row.min(column));
+        self.rows[row].push((column, value));
+    }
+}
+
+/*
+ * cholesky factor L of a sparse symmetric positive definite matrix, column by
+ * column with the diagonal first. eliminating a variable only fills in
+ * between the variables it's tied to, so for a chain of poses the factor
+ * stays as sparse as the matrix, and each loop adds fill along the loop.
+ * it's worked out a row at a time, so the work can be spread out, solving
+ * L y = b along the way
+ */
+pub struct SparseCholesky {
+    matrix: SparseSymmetric,
+    y: Vec<f64>, // b, with the rows done so far solved for
+    columns: Vec<Vec<(usize, f64)>>,
+    parent: Vec<Option<usize>>, // elimination tree over the rows done so far
+    ancestor: Vec<Option<usize>>,
+    values: Vec<f64>, // the row being solved, scattered
+    visited: Vec<usize>,
+    pattern: Vec<usize>,
+}
+
+impl SparseCholesky {
+    pub fn new(matrix: SparseSymmetric, b: Vec<f64>) -> Self {
+        let size = matrix.rows.len();
+        Self {
+            matrix,
+            y: b,
+            columns: Vec::with_capacity(size),
+            parent: vec![None; size],
+            ancestor: vec![None; size],
+            values: vec![0.0; size],
+            visited: vec![usize::MAX; size],
+            pattern: Vec::new(),
+        }
+    }
+
+    /*
+     * up-looking factorization, a row of L at a time, each a sparse triangular
+     * solve against the rows before it, until the budget (entries worked on)
+     * runs out. once every row is done, whether the matrix was positive
+     * definite. after Davis, Direct Methods for Sparse Linear Systems
+     */
+    pub fn step(&mut self, budget: &mut usize) -> Option<bool> {
+        while *budget > 0 {
+            let k = self.columns.len();
+            if k == self.matrix.rows.len() {
+                return Some(true);
+            }
+            let row = &self.matrix.rows[k];
+
+            /*
+             * the row's parent links in the elimination tree, each earlier
+             * variable's being the first later one its column of the factor
+             * reaches: climbing to the root so far, pointing everything on
+             * the way at k
+             */
+            for &(column, _) in row.iter().filter(|&&(column, _)| column < k) {
+                let mut node = column;
+                loop {
+                    let next = self.ancestor[node].replace(k);
+                    match next {
+                        None => {
+                            self.parent[node] = Some(k);
+                            break;
+                        }
+                        Some(next) if next == k => break,
+                        Some(next) => node = next,
+                    }
+                }
+            }
+
+            /*
+             * a row of L only has entries on the paths up the tree from the
+             * entries in the same row of the matrix, found by walking up from
+             * each until a variable already found; every path is kept root
+             * side first, so read backwards every variable comes before its
+             * ancestors
+             */
+            self.pattern.clear();
+            self.visited[k] = k;
+            for &(column, value) in row {
+                self.values[column] += value;
+                let start = self.pattern.len();
+                let mut node = column;
+                while self.visited[node] != k {
+                    self.visited[node] = k;
+                    self.pattern.push(node);
+                    let Some(parent) = self.parent[node] else {
+                        return Some(false);
+                    };
+                    node = parent;
+                }
+                self.pattern[start..].reverse();
+            }
+
+            let mut work = row.len();
+            let mut diagonal = std::mem::take(&mut self.values[k]);
+            let mut solved = self.y[k];
+            for &j in self.pattern.iter().rev() {
+                let l = std::mem::take(&mut self.values[j]) / self.columns[j][0].1;
+                for &(i, l_i) in &self.columns[j][1..] {
+                    self.values[i] -= l_i * l;
+                }
+                work += self.columns[j].len();
+                diagonal -= l * l;
+                solved -= l * self.y[j];
+                self.columns[j].push((k, l));
+            }
+
+            if diagonal <= 0.0 || !diagonal.is_finite() {
+                return Some(false);
+            }
+            self.columns.push(vec![(k, diagonal.sqrt())]);
+            self.y[k] = solved / diagonal.sqrt();
+            *budget = budget.saturating_sub(work);
+        }
+        (self.columns.len() == self.matrix.rows.len()).then_some(true)
+    }
+
+    // the columns of L, which are the rows of Lᵀ, and y
+    pub fn into_parts(self) -> (Vec<Vec<(usize, f64)>>, Vec<f64>) {
+        (self.columns, self.y)
+    }
+}
@@ -1,17 +1,21 @@
 use crate::config::Config;
-use crate::simulation::Observation;
+use crate::simulation::{GpsFix, Observation};
+use crate::slam::loop_closure::LoopClosure;
+use crate::slam::motion::Control;
+use crate::spatial_index::ObstructionGrid;
 use macroquad::color::Color;
 
 pub trait Slam {
-    fn predict(
+    fn predict(&mut self, control: &Control, cfg: &Config);
+    fn update(
         &mut self,
-        linear_velocity: f32,
-        angular_velocity: f32,
-        delta_time: f32,
+        observations: &[Observation],
+        obstructions: &ObstructionGrid,
         cfg: &Config,
     );
-    fn update(&mut self, observations: &[Observation], cfg: &Config);
+    fn update_position(&mut self, fix: &GpsFix, cfg: &Config);
     fn get_state(&self) -> (f32, f32, f32);
     fn get_landmarks(&self) -> Vec<(usize, f32, f32)>;
+    fn take_loop_closures(&mut self) -> Vec<LoopClosure>; // detected since the last call
     fn color(&self) -> Color;
 }
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+
+use macroquad::prelude::Vec2;
+
+use crate::config::Config;
+use crate::dynamics::ObstacleMotion;
+use crate::geometry::Obstruction;
+
+type Cell = (i32, i32);
+
+/*
+ * uniform grid over the obstructions, so line-of-sight and collision queries
+ * only test shapes in the cells they pass through. each shape is binned into
+ * every cell its bounding box overlaps, and re-binned whenever it's edited or
+ * moves on its own
+ */
+pub struct ObstructionGrid {
+    obstructions: Vec<Obstruction>,
+    motions: Vec<Option<ObstacleMotion>>, // None for static obstructions
+    spans: Vec<(Cell, Cell)>,             // first and last cell each obstruction is binned into
+    cells: HashMap<Cell, Vec<usize>>,
+    cell_size: f32,
+}
+
+impl ObstructionGrid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            obstructions: Vec::new(),
+            motions: Vec::new(),
+            spans: Vec::new(),
+            cells: HashMap::new(),
+            cell_size,
+        }
+    }
+
+    pub fn obstructions(&self) -> &[Obstruction] {
+        &self.obstructions
+    }
+
+    pub fn is_moving(&self, index: usize) -> bool {
+        self.motions[index].is_some()
+    }
+
+    pub fn push(&mut self, obstruction: Obstruction) {
+        self.push_moving(obstruction, None);
+    }
+
+    pub fn push_moving(&mut self, obstruction: Obstruction, motion: Option<ObstacleMotion>) {
+        let span = self.span(&obstruction);
+        let index = self.obstructions.len();
+        self.obstructions.push(obstruction);
+        self.motions.push(motion);
+        self.spans.push(span);
+        self.bin(index);
+    }
+
+    // swaps the last obstruction into the removed one's place
+    pub fn remove(&mut self, index: usize) -> Obstruction {
+        let last = self.obstructions.len() - 1;
+        self.unbin(index);
+        if index != last {
+            self.unbin(last);
+        }
+
+        let removed = self.obstructions.swap_remove(index);
+        self.motions.swap_remove(index);
+        self.spans.swap_remove(index);
+        if index != last {
+            self.bin(index);
+        }
+        removed
+    }
+
+    // edits an obstruction in place and re-bins it
+    pub fn modify(&mut self, index: usize, edit: impl FnOnce(&mut Obstruction)) {
+        self.unbin(index);
+        edit(&mut self.obstructions[index]);
+        self.spans[index] = self.span(&self.obstructions[index]);
+        self.bin(index);
+    }
+
+    // advances every moving obstruction by a frame
+    pub fn step(&mut self, delta_time: f32, cfg: &Config) {
+        for index in 0..self.obstructions.len() {
+            let center = self.obstructions[index].center();
+            if let Some(motion) = &mut self.motions[index] {
+                let offset = motion.step(center, delta_time, cfg);
+                self.modify(index, |obstruction| obstruction.translate(offset));
+            }
+        }
+    }
+
+    // topmost obstruction a click at the point picks
+    pub fn pick(&self, point: Vec2, tolerance: f32) -> Option<usize> {
+        self.near(point, tolerance)
+            .into_iter()
+            .filter(|&index| self.obstructions[index].is_near(point, tolerance))
+            .max()
+    }
+
+    // obstructions whose cells overlap the square around the point, in index order
+    pub fn near(&self, point: Vec2, radius: f32) -> Vec<usize> {
+        let (min, max) = (
+            self.cell(point - Vec2::splat(radius)),
+            self.cell(point + Vec2::splat(radius)),
+        );
+
+        let mut indices = Vec::new();
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                if let Some(bucket) = self.cells.get(&(x, y)) {
+                    indices.extend_from_slice(bucket);
+                }
+            }
+        }
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    }
+
+    // whether any obstruction cuts the line of sight
+    pub fn blocks(&self, from: Vec2, to: Vec2) -> bool {
+        let mut blocked = false;
+        self.walk(from, to, |index, _| {
+            blocked |= self.obstructions[index].blocks(from, to);
+            blocked
+        });
+        blocked
+    }
+
+    // distance from the first point to the nearest obstruction towards the second, if any
+    pub fn raycast(&self, from: Vec2, to: Vec2) -> Option<f32> {
+        let mut nearest: Option<f32> = None;
+        self.walk(from, to, |index, exit| {
+            if let Some(hit) = self.obstructions[index].ray_hit(from, to) {
+                nearest = Some(nearest.map_or(hit, |nearest| nearest.min(hit)));
+            }
+            // a hit inside the cells walked so far can't be beaten further on
+            nearest.is_some_and(|nearest| nearest <= exit)
+        });
+        nearest.map(|hit| hit * from.distance(to))
+    }
+
+    /*
+     * walks the cells along the segment (Amanatides & Woo), handing each
+     * obstruction in them over once, with the fraction of the segment at
+     * which it leaves the current cell; told to stop, it finishes the cell
+     * http://www.cse.yorku.ca/~amana/research/grid.pdf
+     */
+    fn walk(&self, from: Vec2, to: Vec2, mut visit: impl FnMut(usize, f32) -> bool) {
+        let (mut x, mut y) = self.cell(from);
+        let end = self.cell(to);
+        let direction = to - from;
+
+        let axis = |position: f32, cell: i32, delta: f32| {
+            if delta > 0.0 {
+                (
+                    1,
+                    ((cell + 1) as f32 * self.cell_size - position) / delta,
+                    self.cell_size / delta,
+                )
+            } else if delta < 0.0 {
+                (
+                    -1,
+                    (cell as f32 * self.cell_size - position) / delta,
+                    -self.cell_size / delta,
+                )
+            } else {
+                (0, f32::INFINITY, f32::INFINITY)
+            }
+        };
+        let (step_x, mut next_x, delta_x) = axis(from.x, x, direction.x);
+        let (step_y, mut next_y, delta_y) = axis(from.y, y, direction.y);
+
+        // every step moves one cell closer to the end cell
+        let steps = (end.0 - x).abs() + (end.1 - y).abs();
+
+        /*
+         * the walk never turns back on either axis, so it crosses the
+         * rectangle of cells an obstruction is binned into in one run: it's
+         * been handed over already if the previous cell was in that rectangle
+         */
+        let mut previous: Option<Cell> = None;
+        for _ in 0..=steps {
+            let exit = if (x, y) == end {
+                1.0
+            } else {
+                next_x.min(next_y)
+            };
+            let mut stop = false;
+            if let Some(bucket) = self.cells.get(&(x, y)) {
+                for &index in bucket {
+                    let (first, last) = self.spans[index];
+                    let seen = previous.is_some_and(|(px, py)| {
+                        (first.0..=last.0).contains(&px) && (first.1..=last.1).contains(&py)
+                    });
+                    if !seen {
+                        stop |= visit(index, exit);
+                    }
+                }
+            }
+            if stop {
+                return;
+            }
+            previous = Some((x, y));
+
+            if (x, y) == end {
+                break;
+            }
+            // once one axis lines up with the end cell, rounding can't step it past
+            if y == end.1 || (x != end.0 && next_x < next_y) {
+                x += step_x;
+                next_x += delta_x;
+            } else {
+                y += step_y;
+                next_y += delta_y;
+            }
+        }
+    }
+
+    fn cell(&self, point: Vec2) -> Cell {
+        (
+            (point.x / self.cell_size).floor() as i32,
+            (point.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn span(&self, obstruction: &Obstruction) -> (Cell, Cell) {
+        let (min, max) = bounding_box(obstruction);
+        (self.cell(min), self.cell(max))
+    }
+
+    fn bin(&mut self, index: usize) {
+        let (min, max) = self.spans[index];
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                self.cells.entry((x, y)).or_default().push(index);
+            }
+        }
+    }
+
+    fn unbin(&mut self, index: usize) {
+        let (min, max) = self.spans[index];
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                if let Some(bucket) = self.cells.get_mut(&(x, y)) {
+                    bucket.retain(|&other| other != index);
+                    if bucket.is_empty() {
+                        self.cells.remove(&(x, y));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn bounding_box(obstruction: &Obstruction) -> (Vec2, Vec2) {
+    match obstruction {
+        Obstruction::Polygon(vertices) => vertices.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), &vertex| (min.min(vertex), max.max(vertex)),
+        ),
+        Obstruction::Circle { center, radius } => (
+            *center - Vec2::splat(*radius),
+            *center + Vec2::splat(*radius),
+        ),
+        Obstruction::Wall { start, end } => (start.min(*end), start.max(*end)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::prelude::vec2;
+    use macroquad::rand;
+
+    // circles and walls scattered over a few dozen cells
+    fn scattered(count: usize) -> Vec<Obstruction> {
+        rand::srand(3);
+        (0..count)
+            .map(|i| {
+                let center = vec2(
+                    rand::gen_range(-300.0, 300.0),
+                    rand::gen_range(-300.0, 300.0),
+                );
+                if i % 2 == 0 {
+                    Obstruction::Circle {
+                        center,
+                        radius: rand::gen_range(5.0, 80.0),
+                    }
+                } else {
+                    let half = vec2(rand::gen_range(-90.0, 90.0), rand::gen_range(-90.0, 90.0));
+                    Obstruction::Wall {
+                        start: center - half,
+                        end: center + half,
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn grid(obstructions: &[Obstruction]) -> ObstructionGrid {
+        let mut grid = ObstructionGrid::new(50.0);
+        for obstruction in obstructions {
+            grid.push(obstruction.clone());
+        }
+        grid
+    }
+
+    #[test]
+    fn walk_hands_each_obstruction_over_once() {
+        let obstructions = scattered(40);
+        let grid = grid(&obstructions);
+        for (from, to) in [
+            (vec2(-320.0, -310.0), vec2(310.0, 305.0)),
+            (vec2(290.0, -300.0), vec2(-300.0, 10.0)),
+            (vec2(0.0, -320.0), vec2(0.0, 320.0)),
+        ] {
+            let mut visits = vec![0; obstructions.len()];
+            grid.walk(from, to, |index, _| {
+                visits[index] += 1;
+                false
+            });
+            assert!(visits.iter().all(|&count| count <= 1), "{visits:?}");
+
+            // everything the segment touches is among them
+            for (index, obstruction) in obstructions.iter().enumerate() {
+                if obstruction.blocks(from, to) {
+                    assert_eq!(visits[index], 1, "obstruction {index} skipped");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn raycasts_match_testing_every_obstruction() {
+        let obstructions = scattered(40);
+        let grid = grid(&obstructions);
+        for _ in 0..200 {
+            let from = vec2(
+                rand::gen_range(-350.0, 350.0),
+                rand::gen_range(-350.0, 350.0),
+            );
+            let to = vec2(
+                rand::gen_range(-350.0, 350.0),
+                rand::gen_range(-350.0, 350.0),
+            );
+            let nearest = obstructions
+                .iter()
+                .filter_map(|obstruction| obstruction.ray_hit(from, to))
+                .reduce(f32::min)
+                .map(|hit| hit * from.distance(to));
+
+            match (grid.raycast(from, to), nearest) {
+                (Some(hit), Some(nearest)) => assert!((hit - nearest).abs() < 1e-3),
+                (hit, nearest) => assert_eq!(hit, nearest),
+            }
+            assert_eq!(grid.blocks(from, to), nearest.is_some());
+        }
+    }
+
+    #[test]
+    fn removing_keeps_the_rest_findable() {
+        let obstructions = scattered(10);
+        let mut grid = grid(&obstructions);
+        let removed = grid.remove(2);
+        assert!(matches!(removed, Obstruction::Circle { .. }));
+
+        // the last one took its place
+        assert_eq!(grid.obstructions().len(), 9);
+        let Obstruction::Wall { start, end } = obstructions[9] else {
+            panic!("expected the last one to be a wall");
+        };
+        let (middle, across) = (0.5 * (start + end), (end - start).perp().normalize());
+        assert!(grid.blocks(middle - 10.0 * across, middle + 10.0 * across));
+        assert!(grid.near(middle, 1.0).contains(&2));
+        for index in 0..9 {
+            let center = grid.obstructions()[index].center();
+            assert!(grid.near(center, 1.0).contains(&index));
+        }
+    }
+}
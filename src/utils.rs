@@ -13,6 +13,114 @@ pub fn sample_normal(mean: f32, std_dev: f32) -> f32 {
     mean + std_dev * z0
 }
 
+/*
+ * Knuth's algorithm for Poisson distributed counts; fine for the small means used here
+ * https://en.wikipedia.org/wiki/Poisson_distribution#Random_variate_generation
+ */
+pub fn sample_poisson(mean: f32) -> usize {
+    let limit = (-mean).exp();
+    let mut count = 0;
+    let mut product = rand::gen_range(0.0f32, 1.0f32);
+
+    while product > limit {
+        count += 1;
+        product *= rand::gen_range(0.0f32, 1.0f32);
+    }
+
+    count
+}
+
+/*
+ * small seedable generator (SplitMix64) for reproducible random streams
+ * that don't depend on macroquad's global state or on thread scheduling
+ * https://prng.di.unimi.it/splitmix64.c
+ */
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /*
+     * independent generator for the given stream index, derived without
+     * advancing this one
+     */
+    pub fn stream(&self, index: u64) -> Self {
+        Self::new(Self::mix(self.state ^ Self::mix(index.wrapping_add(1))))
+    }
+
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        Self::mix(self.state)
+    }
+
+    // uniform in [low, high)
+    pub fn gen_range(&mut self, low: f32, high: f32) -> f32 {
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        low + (high - low) * unit
+    }
+
+    // same Box-Muller transform as sample_normal
+    pub fn sample_normal(&mut self, mean: f32, std_dev: f32) -> f32 {
+        let u1 = self.gen_range(0.0, 1.0).max(1e-6);
+        let u2 = self.gen_range(0.0, 1.0);
+
+        let z0 = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos();
+
+        mean + std_dev * z0
+    }
+}
+
+// below this many items per thread, spawning costs more than it saves
+const MIN_ITEMS_PER_THREAD: usize = 64;
+
+/*
+ * runs f on every item, splitting the slice into one contiguous chunk per
+ * thread; falls back to the current thread when there's only one (e.g. wasm)
+ */
+pub fn par_for_each_mut<T: Send>(
+    items: &mut [T],
+    num_threads: usize,
+    f: impl Fn(usize, &mut T) + Sync,
+) {
+    let num_threads = num_threads.clamp(1, (items.len() / MIN_ITEMS_PER_THREAD).max(1));
+
+    if num_threads == 1 {
+        for (index, item) in items.iter_mut().enumerate() {
+            f(index, item);
+        }
+        return;
+    }
+
+    let chunk_size = items.len().div_ceil(num_threads);
+    let f = &f;
+
+    std::thread::scope(|scope| {
+        for (chunk_index, chunk) in items.chunks_mut(chunk_size).enumerate() {
+            scope.spawn(move || {
+                for (offset, item) in chunk.iter_mut().enumerate() {
+                    f(chunk_index * chunk_size + offset, item);
+                }
+            });
+        }
+    });
+}
+
+// number of worker threads to use for parallel loops
+pub fn available_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
 /*
  * helper that converts relative position of landmark (range and bearing)
  * to absolute (x, y) coordinates
@@ -55,3 +163,104 @@ pub fn absolute_to_relative(
 
     (range, bearing)
 }
+
+/*
+ * least-squares intersection of bearing rays given as (origin x, origin y,
+ * absolute angle); minimizes the summed squared perpendicular distance
+ * https://en.wikipedia.org/wiki/Line%E2%80%93line_intersection#In_two_dimensions_2
+ */
+pub fn triangulate(rays: &[(f32, f32, f32)]) -> Option<(f32, f32)> {
+    let (mut a11, mut a12, mut a22, mut b1, mut b2) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+    for &(origin_x, origin_y, angle) in rays {
+        // normal of the ray
+        let normal_x = -angle.sin();
+        let normal_y = angle.cos();
+
+        a11 += normal_x * normal_x;
+        a12 += normal_x * normal_y;
+        a22 += normal_y * normal_y;
+
+        let offset = normal_x * origin_x + normal_y * origin_y;
+        b1 += normal_x * offset;
+        b2 += normal_y * offset;
+    }
+
+    let determinant = a11 * a22 - a12 * a12;
+    if determinant.abs() < 1e-6 {
+        return None; // rays are (nearly) parallel
+    }
+
+    Some((
+        (a22 * b1 - a12 * b2) / determinant,
+        (a11 * b2 - a12 * b1) / determinant,
+    ))
+}
+
+/*
+ * least-squares position from ranges measured at several points, given as
+ * (center x, center y, range). subtracting the first circle's equation from
+ * the others gives a linear system for a starting point, which is then
+ * refined with a few gauss-newton steps on the actual range residuals
+ * https://en.wikipedia.org/wiki/True-range_multilateration
+ */
+pub fn trilaterate(circles: &[(f32, f32, f32)]) -> Option<(f32, f32)> {
+    let &(first_x, first_y, first_range) = circles.first()?;
+    let (mut a11, mut a12, mut a22, mut b1, mut b2) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+    // centers relative to the first one, to keep the squares small
+    for &(center_x, center_y, range) in &circles[1..] {
+        let row_x = 2.0 * (center_x - first_x);
+        let row_y = 2.0 * (center_y - first_y);
+        let rhs = first_range * first_range - range * range
+            + (center_x - first_x).powi(2)
+            + (center_y - first_y).powi(2);
+
+        a11 += row_x * row_x;
+        a12 += row_x * row_y;
+        a22 += row_y * row_y;
+        b1 += row_x * rhs;
+        b2 += row_y * rhs;
+    }
+
+    let determinant = a11 * a22 - a12 * a12;
+    if determinant.abs() < 1e-6 * (a11 + a22).powi(2).max(1e-6) {
+        return None; // centers are (nearly) collinear, so the result could be mirrored
+    }
+
+    let mut x = (a22 * b1 - a12 * b2) / determinant;
+    let mut y = (a11 * b2 - a12 * b1) / determinant;
+
+    for _ in 0..5 {
+        let (mut a11, mut a12, mut a22, mut b1, mut b2) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+        for &(center_x, center_y, range) in circles {
+            let distance_x = x - (center_x - first_x);
+            let distance_y = y - (center_y - first_y);
+            let distance = (distance_x * distance_x + distance_y * distance_y)
+                .sqrt()
+                .max(1e-6);
+
+            // jacobian row of the range residual
+            let row_x = distance_x / distance;
+            let row_y = distance_y / distance;
+            let residual = range - distance;
+
+            a11 += row_x * row_x;
+            a12 += row_x * row_y;
+            a22 += row_y * row_y;
+            b1 += row_x * residual;
+            b2 += row_y * residual;
+        }
+
+        let determinant = a11 * a22 - a12 * a12;
+        if determinant.abs() < 1e-9 {
+            break;
+        }
+
+        x += (a22 * b1 - a12 * b2) / determinant;
+        y += (a11 * b2 - a12 * b1) / determinant;
+    }
+
+    Some((x + first_x, y + first_y))
+}
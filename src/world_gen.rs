@@ -0,0 +1,390 @@
+use macroquad::prelude::{Vec2, vec2};
+
+use crate::config::Config;
+use crate::geometry::Obstruction;
+use crate::simulation::Landmark;
+use crate::spatial_index::ObstructionGrid;
+use crate::utils::Rng;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WorldKind {
+    Maze,      // corridors one cell wide, with loops
+    Office,    // rooms split off each other, joined by doors
+    Field,     // open space with scattered round obstacles
+    Manhattan, // rectangular buildings on a street grid
+}
+
+impl WorldKind {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Maze => Self::Office,
+            Self::Office => Self::Field,
+            Self::Field => Self::Manhattan,
+            Self::Manhattan => Self::Maze,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Maze => "maze",
+            Self::Office => "office",
+            Self::Field => "field",
+            Self::Manhattan => "manhattan",
+        }
+    }
+}
+
+pub struct World {
+    pub obstructions: Vec<Obstruction>,
+    pub landmarks: Vec<Landmark>,
+}
+
+/*
+ * random world of the given kind, square and centered on the origin, where
+ * the first robot starts; the same seed and config always give the same world
+ */
+pub fn generate(kind: WorldKind, seed: u64, cfg: &Config) -> World {
+    let mut rng = Rng::new(seed);
+    let half_size = 0.5 * cfg.world_size;
+
+    let mut obstructions = match kind {
+        WorldKind::Maze => maze(&mut rng, cfg),
+        WorldKind::Office => office(&mut rng, cfg),
+        WorldKind::Field => field(&mut rng, cfg),
+        WorldKind::Manhattan => manhattan(&mut rng, cfg),
+    };
+
+    // keep the spawn points clear
+    let spawns = spawn_points(cfg);
+    obstructions.retain(|obstruction| {
+        spawns
+            .iter()
+            .all(|&spawn| !obstruction.is_near(spawn, 2.0 * cfg.robot_radius))
+    });
+
+    // landmarks anywhere not on or in an obstruction
+    let mut grid = ObstructionGrid::new(cfg.spatial_cell_size);
+    for obstruction in obstructions.iter() {
+        grid.push(obstruction.clone());
+    }
+    let clearance = 2.0 * cfg.landmark_radius;
+    let landmarks = poisson_disk(&mut rng, half_size, cfg.world_landmark_spacing)
+        .into_iter()
+        .filter(|&point| grid.pick(point, clearance).is_none())
+        .enumerate()
+        .map(|(id, point)| Landmark {
+            id,
+            x: point.x,
+            y: point.y,
+            moving: rng.gen_range(0.0, 1.0) < cfg.moving_landmark_fraction,
+            velocity: (0.0, 0.0),
+        })
+        .collect();
+
+    World {
+        obstructions,
+        landmarks,
+    }
+}
+
+// where the robots start, a row along the x axis from the origin
+pub fn spawn_points(cfg: &Config) -> Vec<Vec2> {
+    (0..cfg.robot_count)
+        .map(|index| vec2(index as f32 * cfg.robot_spacing, 0.0))
+        .collect()
+}
+
+/*
+ * recursive backtracker over an odd number of cells, so the origin sits in
+ * the middle of one. density is the fraction of walls the carving left
+ * standing that are kept; anything less opens up loops
+ */
+fn maze(rng: &mut Rng, cfg: &Config) -> Vec<Obstruction> {
+    let cell = cfg.world_cell_size;
+    let n = ((cfg.world_size / cell) as usize).max(1) | 1;
+    let corner = -0.5 * n as f32 * cell;
+    let point = |i: usize, j: usize| vec2(corner + i as f32 * cell, corner + j as f32 * cell);
+
+    // open[i][j] is (passage to the east, passage to the south)
+    let mut open = vec![vec![(false, false); n]; n];
+    let mut visited = vec![vec![false; n]; n];
+    let mut stack = vec![(n / 2, n / 2)];
+    visited[n / 2][n / 2] = true;
+
+    while let Some(&(i, j)) = stack.last() {
+        let neighbors: Vec<(usize, usize)> = [
+            (i.wrapping_sub(1), j),
+            (i + 1, j),
+            (i, j.wrapping_sub(1)),
+            (i, j + 1),
+        ]
+        .into_iter()
+        .filter(|&(x, y)| x < n && y < n && !visited[x][y])
+        .collect();
+
+        if neighbors.is_empty() {
+            stack.pop();
+            continue;
+        }
+
+        let (x, y) = neighbors[(rng.next_u64() % neighbors.len() as u64) as usize];
+        match (x.cmp(&i), y.cmp(&j)) {
+            (std::cmp::Ordering::Greater, _) => open[i][j].0 = true,
+            (std::cmp::Ordering::Less, _) => open[x][y].0 = true,
+            (_, std::cmp::Ordering::Greater) => open[i][j].1 = true,
+            _ => open[x][y].1 = true,
+        }
+        visited[x][y] = true;
+        stack.push((x, y));
+    }
+
+    let mut walls = boundary(corner, -corner);
+    for (i, column) in open.iter().enumerate() {
+        for (j, &(east, south)) in column.iter().enumerate() {
+            if i + 1 < n && !east && rng.gen_range(0.0, 1.0) < cfg.world_obstacle_density {
+                walls.push(Obstruction::Wall {
+                    start: point(i + 1, j),
+                    end: point(i + 1, j + 1),
+                });
+            }
+            if j + 1 < n && !south && rng.gen_range(0.0, 1.0) < cfg.world_obstacle_density {
+                walls.push(Obstruction::Wall {
+                    start: point(i, j + 1),
+                    end: point(i + 1, j + 1),
+                });
+            }
+        }
+    }
+
+    walls
+}
+
+/*
+ * binary space partition into rooms; each split is a wall with a door in it.
+ * the first two levels always split, deeper ones with probability density
+ */
+fn office(rng: &mut Rng, cfg: &Config) -> Vec<Obstruction> {
+    let half_size = 0.5 * cfg.world_size;
+    let min_room = 1.5 * cfg.world_cell_size;
+
+    let mut walls = boundary(-half_size, half_size);
+    let mut rooms = vec![(Vec2::splat(-half_size), Vec2::splat(half_size), 0)];
+
+    while let Some((min, max, depth)) = rooms.pop() {
+        let size = max - min;
+        let vertical = size.x >= size.y; // split across the longer side
+        let length = if vertical { size.x } else { size.y };
+
+        if length < 2.0 * min_room
+            || (depth >= 2 && rng.gen_range(0.0, 1.0) >= cfg.world_obstacle_density)
+        {
+            continue;
+        }
+
+        let offset = rng.gen_range(min_room, length - min_room);
+        let (start, end, first, second) = if vertical {
+            let x = min.x + offset;
+            (
+                vec2(x, min.y),
+                vec2(x, max.y),
+                (min, vec2(x, max.y)),
+                (vec2(x, min.y), max),
+            )
+        } else {
+            let y = min.y + offset;
+            (
+                vec2(min.x, y),
+                vec2(max.x, y),
+                (min, vec2(max.x, y)),
+                (vec2(min.x, y), max),
+            )
+        };
+        walls.extend(wall_with_door(rng, start, end, cfg.world_door_width));
+        rooms.push((first.0, first.1, depth + 1));
+        rooms.push((second.0, second.1, depth + 1));
+    }
+
+    walls
+}
+
+// open ground with round obstacles, density per square cell
+fn field(rng: &mut Rng, cfg: &Config) -> Vec<Obstruction> {
+    let half_size = 0.5 * cfg.world_size;
+    let cells = (cfg.world_size / cfg.world_cell_size).powi(2);
+    let count = (cfg.world_obstacle_density * cells) as usize;
+
+    (0..count)
+        .map(|_| Obstruction::Circle {
+            center: vec2(
+                rng.gen_range(-half_size, half_size),
+                rng.gen_range(-half_size, half_size),
+            ),
+            radius: rng.gen_range(15.0, 50.0),
+        })
+        .collect()
+}
+
+/*
+ * blocks two and a half cells apart with a street one cell wide between
+ * them, aligned so the origin is at an intersection. each block holds a
+ * building with probability density
+ */
+fn manhattan(rng: &mut Rng, cfg: &Config) -> Vec<Obstruction> {
+    let pitch = 2.5 * cfg.world_cell_size;
+    let street = cfg.world_cell_size;
+    let blocks = (0.5 * cfg.world_size / pitch) as i32;
+
+    let mut buildings = Vec::new();
+    for i in -blocks..blocks {
+        for j in -blocks..blocks {
+            if rng.gen_range(0.0, 1.0) >= cfg.world_obstacle_density {
+                continue;
+            }
+
+            // buildings don't fill their lot exactly
+            let center = vec2((i as f32 + 0.5) * pitch, (j as f32 + 0.5) * pitch);
+            let half_extent = 0.5
+                * vec2(
+                    (pitch - street) * rng.gen_range(0.7, 1.0),
+                    (pitch - street) * rng.gen_range(0.7, 1.0),
+                );
+            buildings.push(Obstruction::rectangle(
+                center - half_extent,
+                center + half_extent,
+            ));
+        }
+    }
+
+    buildings
+}
+
+// four walls around the square from min to max
+fn boundary(min: f32, max: f32) -> Vec<Obstruction> {
+    let corners = [
+        vec2(min, min),
+        vec2(max, min),
+        vec2(max, max),
+        vec2(min, max),
+    ];
+    (0..4)
+        .map(|i| Obstruction::Wall {
+            start: corners[i],
+            end: corners[(i + 1) % 4],
+        })
+        .collect()
+}
+
+// the wall from start to end, with a gap of the given width somewhere along it
+fn wall_with_door(rng: &mut Rng, start: Vec2, end: Vec2, door_width: f32) -> Vec<Obstruction> {
+    let length = start.distance(end);
+    if length <= 2.0 * door_width {
+        return vec![Obstruction::Wall { start, end }];
+    }
+
+    let direction = (end - start) / length;
+    let door = rng.gen_range(0.5 * door_width, length - 1.5 * door_width);
+    vec![
+        Obstruction::Wall {
+            start,
+            end: start + direction * door,
+        },
+        Obstruction::Wall {
+            start: start + direction * (door + door_width),
+            end,
+        },
+    ]
+}
+
+/*
+ * points in the square at least the spacing apart, filling it evenly
+ * (Bridson's algorithm)
+ * https://www.cs.ubc.ca/~rbridson/docs/bridson-siggraph07-poissondisk.pdf
+ */
+fn poisson_disk(rng: &mut Rng, half_size: f32, spacing: f32) -> Vec<Vec2> {
+    const ATTEMPTS: usize = 30;
+
+    let cell = spacing / std::f32::consts::SQRT_2; // at most one point per cell
+    let n = (2.0 * half_size / cell).ceil() as usize;
+    let index = |point: Vec2| {
+        let x = (((point.x + half_size) / cell) as usize).min(n - 1);
+        let y = (((point.y + half_size) / cell) as usize).min(n - 1);
+        (x, y)
+    };
+
+    let mut grid: Vec<Option<usize>> = vec![None; n * n];
+    let mut points = vec![vec2(
+        rng.gen_range(-half_size, half_size),
+        rng.gen_range(-half_size, half_size),
+    )];
+    let (x, y) = index(points[0]);
+    grid[x * n + y] = Some(0);
+    let mut active = vec![0];
+
+    while !active.is_empty() {
+        let slot = (rng.next_u64() % active.len() as u64) as usize;
+        let origin = points[active[slot]];
+
+        let candidate = (0..ATTEMPTS).find_map(|_| {
+            let candidate = origin
+                + Vec2::from_angle(rng.gen_range(0.0, std::f32::consts::TAU))
+                    * rng.gen_range(spacing, 2.0 * spacing);
+            if candidate.abs().max_element() >= half_size {
+                return None;
+            }
+
+            let (x, y) = index(candidate);
+            let too_close = (x.saturating_sub(2)..(x + 3).min(n)).any(|i| {
+                (y.saturating_sub(2)..(y + 3).min(n)).any(|j| {
+                    grid[i * n + j].is_some_and(|other| points[other].distance(candidate) < spacing)
+                })
+            });
+            (!too_close).then_some(candidate)
+        });
+
+        match candidate {
+            Some(point) => {
+                let (x, y) = index(point);
+                grid[x * n + y] = Some(points.len());
+                active.push(points.len());
+                points.push(point);
+            }
+            None => {
+                active.swap_remove(slot);
+            }
+        }
+    }
+
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poisson_disk_keeps_its_spacing_and_fills_the_square() {
+        let (half_size, spacing) = (500.0, 60.0);
+        let points = poisson_disk(&mut Rng::new(5), half_size, spacing);
+
+        for (i, a) in points.iter().enumerate() {
+            assert!(a.abs().max_element() < half_size);
+            for b in &points[i + 1..] {
+                assert!(a.distance(*b) >= spacing, "{a} and {b}");
+            }
+        }
+
+        // no gap wide enough to take another point
+        let steps = 40;
+        for i in 0..=steps {
+            for j in 0..=steps {
+                let probe = vec2(i as f32, j as f32) * (2.0 * half_size / steps as f32)
+                    - Vec2::splat(half_size);
+                let nearest = points
+                    .iter()
+                    .map(|point| point.distance(probe))
+                    .fold(f32::INFINITY, f32::min);
+                assert!(nearest < 2.0 * spacing, "gap at {probe}");
+            }
+        }
+    }
+}